@@ -0,0 +1,186 @@
+//! Benchmarks of reading, sorting and rendering trees of a few shapes.
+//!
+//! Run with `cargo bench`, or `cargo bench -- render` for those whose name
+//! contains `render`. Reading benchmarks use trees written to a temporary
+//! directory; sorting and rendering ones use the same trees as a `Listing`,
+//! so they measure no filesystem access.
+
+use std::fs::{self, File};
+use std::hint::black_box;
+use std::path::Path;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use rust_tree::rust_tree::listing::{InputFormat, Listing};
+use rust_tree::rust_tree::options::{SortKey, TreeOptions};
+use rust_tree::rust_tree::traversal::{
+    build_tree, list_directory_as_string, render_roots, ListingRoot,
+};
+use tempfile::TempDir;
+
+/// A tree to benchmark, as the paths of its files.
+struct Shape {
+    name: &'static str,
+    files: Vec<String>,
+}
+
+impl Shape {
+    /// The shapes benchmarked: one long chain of directories, one directory
+    /// holding many files, and many directories holding a few small files
+    /// each, as in a source tree.
+    fn all() -> Vec<Shape> {
+        let deep = (1..=200)
+            .map(|depth| format!("{}file.txt", "d/".repeat(depth)))
+            .collect();
+        let wide = (0..5000).map(|file| format!("file{}.txt", file)).collect();
+        let mut many_small = Vec::new();
+        for dir in 0..20 {
+            for subdir in 0..20 {
+                for file in 0..10 {
+                    many_small.push(format!("dir{}/sub{}/file{}.rs", dir, subdir, file));
+                }
+            }
+        }
+        vec![
+            Shape {
+                name: "deep",
+                files: deep,
+            },
+            Shape {
+                name: "wide",
+                files: wide,
+            },
+            Shape {
+                name: "many_small",
+                files: many_small,
+            },
+        ]
+    }
+
+    /// A size for the file at `index`, varied so that sorting by size has
+    /// work to do.
+    fn size(index: usize) -> u64 {
+        (index as u64 * 7919) % 100_000
+    }
+
+    /// Writes the files, of their sizes, below a new temporary directory.
+    fn write(&self) -> TempDir {
+        let dir = TempDir::new().unwrap();
+        for (index, file) in self.files.iter().enumerate() {
+            let path = dir.path().join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            File::create(&path)
+                .unwrap()
+                .set_len(Shape::size(index))
+                .unwrap();
+        }
+        dir
+    }
+
+    /// The files as a TSV listing, with their sizes and varied mtimes.
+    fn listing(&self) -> Listing {
+        let text: String = self
+            .files
+            .iter()
+            .enumerate()
+            .map(|(index, file)| {
+                let mtime = 1_600_000_000 + (index as u64 * 104_729) % 10_000_000;
+                format!("{}\t{}\t{}\t644\n", file, Shape::size(index), mtime)
+            })
+            .collect();
+        Listing::parse(&text, Some(InputFormat::Tsv)).unwrap()
+    }
+}
+
+fn render(listing: &Listing, options: &TreeOptions) -> String {
+    let roots = [ListingRoot {
+        source: listing,
+        path: Path::new("").to_path_buf(),
+        name: "bench".to_string(),
+    }];
+    render_roots(&roots, options).unwrap()
+}
+
+/// Reading trees from disk: into nodes, and rendered with and without the
+/// sizes that need each entry's metadata, and with `--du` totals.
+fn bench_traversal(c: &mut Criterion) {
+    let mut group = c.benchmark_group("traversal");
+    for shape in Shape::all() {
+        let dir = shape.write();
+        group.bench_with_input(
+            BenchmarkId::new("build_tree", shape.name),
+            &dir,
+            |b, dir| b.iter(|| build_tree(dir.path(), &TreeOptions::default()).unwrap()),
+        );
+        group.bench_with_input(BenchmarkId::new("names", shape.name), &dir, |b, dir| {
+            b.iter(|| list_directory_as_string(dir.path(), &TreeOptions::default()).unwrap())
+        });
+        let sizes = TreeOptions {
+            print_size: true,
+            ..Default::default()
+        };
+        group.bench_with_input(BenchmarkId::new("sizes", shape.name), &dir, |b, dir| {
+            b.iter(|| list_directory_as_string(dir.path(), &sizes).unwrap())
+        });
+        let disk_usage = TreeOptions {
+            disk_usage: true,
+            ..Default::default()
+        };
+        group.bench_with_input(BenchmarkId::new("du", shape.name), &dir, |b, dir| {
+            b.iter(|| list_directory_as_string(dir.path(), &disk_usage).unwrap())
+        });
+    }
+    group.finish();
+}
+
+/// Sorting the widest directory by each key.
+fn bench_sorting(c: &mut Criterion) {
+    let mut group = c.benchmark_group("sorting");
+    let wide = Shape::all()
+        .into_iter()
+        .find(|shape| shape.name == "wide")
+        .unwrap()
+        .listing();
+    for (name, sort) in [
+        ("name", SortKey::Name),
+        ("version", SortKey::Version),
+        ("size", SortKey::Size),
+        ("mtime", SortKey::ModifiedTime),
+        ("none", SortKey::None),
+    ] {
+        let options = TreeOptions {
+            sort,
+            ..Default::default()
+        };
+        group.bench_function(name, |b| b.iter(|| render(black_box(&wide), &options)));
+    }
+    group.finish();
+}
+
+/// Rendering trees already read, plain and with columns.
+fn bench_rendering(c: &mut Criterion) {
+    let mut group = c.benchmark_group("rendering");
+    for shape in Shape::all() {
+        let listing = shape.listing();
+        group.bench_with_input(
+            BenchmarkId::new("plain", shape.name),
+            &listing,
+            |b, listing| b.iter(|| render(listing, &TreeOptions::default())),
+        );
+        let columns = TreeOptions {
+            print_size: true,
+            human_readable: true,
+            print_date: true,
+            show_permissions: true,
+            ..Default::default()
+        };
+        group.bench_with_input(
+            BenchmarkId::new("columns", shape.name),
+            &listing,
+            |b, listing| b.iter(|| render(listing, &columns)),
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_traversal, bench_sorting, bench_rendering);
+criterion_main!(benches);
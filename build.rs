@@ -0,0 +1,65 @@
+use std::env;
+use std::path::Path;
+use std::process::Command;
+
+// Embeds build metadata for `tree --version --verbose`, and with the `ffi`
+// feature generates the C header for the bindings.
+fn main() {
+    let mut features: Vec<String> = env::vars()
+        .filter_map(|(key, _)| {
+            key.strip_prefix("CARGO_FEATURE_")
+                .map(|feature| feature.to_lowercase().replace('_', "-"))
+        })
+        .collect();
+    features.sort();
+    println!("cargo:rustc-env=TREE_BUILD_FEATURES={}", features.join(","));
+    println!(
+        "cargo:rustc-env=TREE_BUILD_TARGET={}",
+        env::var("TARGET").unwrap_or_default()
+    );
+
+    let commit = Command::new("git")
+        .args(["rev-parse", "--short", "HEAD"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string())
+        .unwrap_or_default();
+    println!("cargo:rustc-env=TREE_BUILD_COMMIT={}", commit);
+    println!("cargo:rerun-if-changed=build.rs");
+    // Not present in packaged sources, where they would force a rebuild
+    for path in [".git/HEAD", ".git/refs"] {
+        if Path::new(path).exists() {
+            println!("cargo:rerun-if-changed={}", path);
+        }
+    }
+    #[cfg(feature = "ffi")]
+    generate_header();
+}
+
+/// Writes `rust_tree.h`, declaring the `extern "C"` items of
+/// `src/rust_tree/ffi.rs`, to the build's output directory.
+#[cfg(feature = "ffi")]
+fn generate_header() {
+    let source = "src/rust_tree/ffi.rs";
+    println!("cargo:rerun-if-changed={}", source);
+    let config = cbindgen::Config {
+        language: cbindgen::Language::C,
+        include_guard: Some("RUST_TREE_H".to_string()),
+        header: Some("/* Generated from src/rust_tree/ffi.rs by cbindgen. */".to_string()),
+        documentation_style: cbindgen::DocumentationStyle::C99,
+        // Declared by the C library
+        export: cbindgen::ExportConfig {
+            exclude: vec!["malloc".to_string()],
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let out_dir = env::var("OUT_DIR").unwrap();
+    cbindgen::Builder::new()
+        .with_config(config)
+        .with_src(source)
+        .generate()
+        .expect("src/rust_tree/ffi.rs could not be parsed for the C header")
+        .write_to_file(Path::new(&out_dir).join("rust_tree.h"));
+}
@@ -0,0 +1,53 @@
+/* Generated from src/rust_tree/ffi.rs by cbindgen. */
+
+#ifndef RUST_TREE_H
+#define RUST_TREE_H
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+// Options for `rust_tree_render`, covering the most used of `TreeOptions`.
+typedef struct RustTreeOptions {
+  // List hidden entries (`-a`).
+  bool all_files;
+  // How many levels deep to list (`-L`), or 0 for no limit.
+  int32_t level;
+  // List directories only (`-d`).
+  bool dir_only;
+  // Print the full path of each entry (`-f`).
+  bool full_path;
+  // Print sizes (`-s`), in human readable units with `human_readable`
+  // (`-h`).
+  bool print_size;
+  bool human_readable;
+  // List directories before files (`--dirsfirst`).
+  bool dirs_first;
+  // Color names with ANSI escape sequences (`-C`).
+  bool color;
+  // Wild-card patterns of the files to list, separated by `|` (`-P`), or
+  // NULL to list all.
+  const char *pattern;
+  // Wild-card patterns of the entries to leave out, separated by `|`
+  // (`-I`), or NULL.
+  const char *exclude;
+} RustTreeOptions;
+
+// Options with everything off and no level limit, as `tree` without flags.
+struct RustTreeOptions rust_tree_default_options(void);
+
+// Renders the tree below `path` as `tree` prints it, into a NUL-terminated
+// string allocated with `malloc` that the caller releases with `free`.
+// `options` may be NULL for the defaults. Returns NULL when the path or a
+// pattern is not valid UTF-8, a pattern is invalid, or the root cannot be
+// read.
+//
+// # Safety
+//
+// `path` must point to a NUL-terminated string. `options` must be NULL or
+// point to a `RustTreeOptions` whose `pattern` and `exclude` are each NULL
+// or point to a NUL-terminated string.
+char *rust_tree_render(const char *path, const struct RustTreeOptions *options);
+
+#endif  /* RUST_TREE_H */
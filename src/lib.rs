@@ -1 +1 @@
-pub mod rust_tree;
\ No newline at end of file
+pub mod rust_tree;
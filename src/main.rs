@@ -1,13 +1,52 @@
 use clap::{App, Arg};
 use glob::Pattern;
-use std::option::Option;
+use regex::bytes::Regex as BytesRegex;
+use regex::Regex;
+use std::fmt::Display;
+use std::fs;
+use std::io::{self, ErrorKind, IsTerminal, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 
-use rust_tree::rust_tree::options::TreeOptions;
-use rust_tree::rust_tree::traversal::list_directory;
+use rust_tree::rust_tree::display::truncate_line;
+use rust_tree::rust_tree::listing::{InputFormat, Listing};
+use rust_tree::rust_tree::options::{
+    Charset, EntryType, Progress, ProgressHandler, SortKey, TreeOptions, LINE_STYLES,
+};
+use rust_tree::rust_tree::theme::{Theme, THEME_NAMES};
+use rust_tree::rust_tree::traversal::{find_first, list_directories, list_roots, ListingRoot};
+use rust_tree::rust_tree::utils::{
+    home_dir, is_valid_time_format, parse_date, parse_duration, parse_pattern_alternatives,
+    parse_size, read_ignore_file, terminal_width, MAX_SIZE_PRECISION, SMART_IGNORE_PATTERNS,
+};
+
+/// Exit status for arguments that cannot be used, as clap exits with for
+/// those it rejects itself.
+const USAGE_ERROR: i32 = 2;
 
 fn main() {
     let matches = App::new("tree")
-        .arg(Arg::new("directory").index(1).required(false))
+        .arg(
+            Arg::new("directory")
+                .index(1)
+                .required(false)
+                .multiple_values(true)
+                .help("Directories to list, in turn (default: the current directory)."),
+        )
+        .arg(
+            Arg::new("show_version")
+                .short('V')
+                .long("version")
+                .multiple_occurrences(true)
+                .help("Print version information. Repeat (-VV) or add --verbose to include the build's features, target and commit."),
+        )
+        .arg(
+            Arg::new("verbose")
+                .long("verbose")
+                .requires("show_version")
+                .help("With --version, print build details."),
+        )
         .arg(
             Arg::new("all_files")
                 .short('a')
@@ -19,54 +58,1053 @@ fn main() {
                 .takes_value(true)
                 .help("Max display depth of the directory tree."),
         )
+        .arg(
+            Arg::new("min_depth")
+                .long("mindepth")
+                .takes_value(true)
+                .value_name("N")
+                .help("Do not list entries less than N levels deep; the contents of such directories are listed in their place, named by their path (e.g. 'data/2024/jan.csv'). Entries of the listed directory are at level 1."),
+        )
         .arg(
             Arg::new("pattern")
             .short('P')
             .takes_value(true)
-            .help("List only those files that match the wild-card pattern. Note: you must use the -a option to also consider those files beginning with a dot '.' for matching."),
+            .help("List only those files that match the wild-card pattern. Separate alternatives with '|' (e.g. '*.c|*.h'). Note: you must use the -a option to also consider those files beginning with a dot '.' for matching."),
+        )
+        .arg(
+            Arg::new("ext")
+                .long("ext")
+                .takes_value(true)
+                .value_name("EXTENSIONS")
+                .multiple_occurrences(true)
+                .help("List only files with one of these comma-separated extensions, e.g. 'rs,toml,md'. Shorthand for -P '*.rs|*.toml|*.md', and combined with any -P patterns."),
+        )
+        .arg(
+            Arg::new("include_from")
+                .long("include-from")
+                .takes_value(true)
+                .value_name("FILE")
+                .multiple_occurrences(true)
+                .help("List only files that match one of the wild-card patterns in FILE, one per line, as with -P; blank lines and lines starting with '#' are skipped. With --full-match, a manifest of paths such as 'src/main.rs' lists exactly those files."),
+        )
+        .arg(
+            Arg::new("exclude")
+            .short('I')
+            .takes_value(true)
+            .multiple_occurrences(true)
+            .help("Do not list files that match the wild-card pattern. Separate alternatives with '|'; may be given multiple times."),
+        )
+        .arg(
+            Arg::new("smart_ignore")
+                .long("smart-ignore")
+                .visible_alias("dev")
+                .help("Do not list well-known dependency, cache and build directories such as node_modules, target, .venv, __pycache__, dist and build. See the whole list with --print-smart-ignore."),
+        )
+        .arg(
+            Arg::new("smart_ignore_add")
+                .long("smart-ignore-add")
+                .takes_value(true)
+                .value_name("PATTERN")
+                .multiple_occurrences(true)
+                .help("Add wild-card patterns, separated by '|', to the --smart-ignore list; implies --smart-ignore. May be given multiple times."),
+        )
+        .arg(
+            Arg::new("print_smart_ignore")
+                .long("print-smart-ignore")
+                .help("Print the patterns --smart-ignore leaves out, including those from --smart-ignore-add, one per line, and exit."),
+        )
+        .arg(
+            Arg::new("exclude_from")
+                .long("exclude-from")
+                .takes_value(true)
+                .value_name("FILE")
+                .multiple_occurrences(true)
+                .help("Do not list files that match any of the wild-card patterns in FILE, one per line; blank lines and lines starting with '#' are skipped. May be given multiple times."),
+        )
+        .arg(
+            Arg::new("gitignore")
+                .long("gitignore")
+                .help("Do not list what git ignores: files matching the .gitignore files of their directory and its parents, .git/info/exclude, and the core.excludesFile from the git configuration."),
+        )
+        .arg(
+            Arg::new("git_status")
+                .long("git-status")
+                .help("Print each entry's two-letter git status code ahead of its name, as 'git status --short' does: e.g. ' M' for a modified file, 'A ' for one added to the index, '??' for untracked and '!!' for ignored entries. Requires git; outside a repository, warns and prints no codes."),
+        )
+        .arg(
+            Arg::new("no_treeignore")
+                .long("no-treeignore")
+                .help("Do not read exclude patterns from the .treeignore files in the listed directories and the home directory."),
+        )
+        .arg(
+            Arg::new("regex")
+                .long("regex")
+                .takes_value(true)
+                .value_name("REGEX")
+                .multiple_occurrences(true)
+                .help("List only those files whose name matches the regular expression, e.g. '^test_.*\\.rs$'. Files matching -P are listed too; may be given multiple times."),
+        )
+        .arg(
+            Arg::new("regex_exclude")
+                .long("regex-exclude")
+                .takes_value(true)
+                .value_name("REGEX")
+                .multiple_occurrences(true)
+                .help("Do not list files or directories whose name matches the regular expression, e.g. '\\.(pyc|o|class)$'; may be given multiple times."),
+        )
+        .arg(
+            Arg::new("contains")
+                .long("contains")
+                .takes_value(true)
+                .value_name("TEXT")
+                .multiple_occurrences(true)
+                .help("List only files containing TEXT, and the directories holding them; may be given multiple times to list files containing any of them."),
+        )
+        .arg(
+            Arg::new("contains_regex")
+                .long("contains-regex")
+                .takes_value(true)
+                .value_name("REGEX")
+                .multiple_occurrences(true)
+                .help("List only files with a line matching the regular expression, and the directories holding them, as with --contains."),
+        )
+        .arg(
+            Arg::new("full_match")
+                .long("full-match")
+                .help("Match -P and -I patterns containing a '/' against the path relative to the listed directory, e.g. -I 'src/**/generated', where '*' does not match '/' but '**' matches any number of directories. --regex and --regex-exclude are matched against that path too."),
+        )
+        .arg(
+            Arg::new("min_size")
+                .long("min-size")
+                .takes_value(true)
+                .value_name("SIZE")
+                .help("List only files of at least SIZE bytes, e.g. 10k, 5M or 1G (1024-based)."),
+        )
+        .arg(
+            Arg::new("max_size")
+                .long("max-size")
+                .takes_value(true)
+                .value_name("SIZE")
+                .help("List only files of at most SIZE bytes, e.g. 10k, 5M or 1G (1024-based)."),
+        )
+        .arg(
+            Arg::new("newer")
+                .long("newer")
+                .takes_value(true)
+                .value_name("DATE")
+                .help("List only files modified after DATE, e.g. 2024-05-01 or '2024-05-01 12:30' (local time). Directories left empty are not listed."),
+        )
+        .arg(
+            Arg::new("older")
+                .long("older")
+                .takes_value(true)
+                .value_name("DATE")
+                .help("List only files modified before DATE, e.g. 2024-05-01 or '2024-05-01 12:30' (local time). Directories left empty are not listed."),
+        )
+        .arg(
+            Arg::new("nonempty")
+                .long("nonempty")
+                .help("Do not list empty (zero-byte) files. Use with --prune to also leave out directories that end up empty."),
+        )
+        .arg(
+            Arg::new("prune")
+                .long("prune")
+                .help("Do not list directories with nothing listed below them, e.g. once -P, -I or --nonempty leave them empty."),
+        )
+        .arg(Arg::new("ctime_sort").short('c').help("Sort the output by last status change instead of alphabetically. Modifies the -D option (if also used) to print the last status change instead of modification time."),)
+        .arg(Arg::new("unsorted").short('U').help("Do not sort. Lists files in directory order. Same as --sort=none."),)
+        .arg(
+            Arg::new("sort")
+                .long("sort")
+                .takes_value(true)
+                .value_name("KEY")
+                .possible_values(["name", "size", "mtime", "ctime", "version", "extension", "none"])
+                .help("Sort the output by KEY. Sizes and times sort largest/newest first."),
+        )
+        .arg(
+            Arg::new("ignore_case_sort")
+                .long("ignore-case-sort")
+                .help("Compare names without regard to case when sorting, so README.md and readme.txt sort together instead of all uppercase names coming first."),
+        )
+        .arg(
+            Arg::new("external_sort")
+                .long("external-sort")
+                .takes_value(true)
+                .value_name("N")
+                .help("Sort directories of more than N entries in temporary files, holding at most N entries in memory, and print the tree as it is read. Unsorted listings (-U) are always printed as they are read."),
         )
         .arg(Arg::new("full_path").short('f').help("Prints the full path prefix for each file."),)
         .arg(Arg::new("dir_only").short('d').help("List directories only."),)
+        .arg(
+            Arg::new("type")
+                .long("type")
+                .takes_value(true)
+                .value_name("TYPE")
+                .multiple_occurrences(true)
+                .possible_values(["f", "d", "l", "x"])
+                .help("List only files (f), directories (d), symlinks (l) or executables (x); may be given multiple times. Directories are always listed so their contents can be shown, so '--type d' is the same as -d."),
+        )
+        .arg(
+            Arg::new("executables")
+                .long("executables")
+                .help("List only executable files: those with an execute permission bit on Unix, and on Windows those with an extension in PATHEXT. The same as '--type x'."),
+        )
+        .arg(Arg::new("follow_links").short('l').help("Follows symbolic links if they point to directories, as if they were directories. Links that would cause recursion are not followed, and are marked [recursive, not followed]."),)
         .arg(Arg::new("no_indent").short('i').help("Makes tree not print the indentation lines, useful when used in conjunction with the -f option."),)
+        .arg(
+            Arg::new("columns")
+                .long("columns")
+                .takes_value(true)
+                .value_name("LIST")
+                .help("Print these comma-separated columns for each entry, in order, e.g. 'perms,size,name'. Columns before the name are bracketed ahead of it. Available: inode, device, perms, attrs, group, date, git, name, target, size, bar, annotations."),
+        )
+        .arg(
+            Arg::new("charset")
+                .long("charset")
+                .takes_value(true)
+                .value_name("CHARSET")
+                .help("Draw the tree lines with the utf8 (default) or ascii glyphs, or a custom set given as 'branch,last branch,vertical', e.g. '+-- ,+-- ,|   '."),
+        )
+        .arg(
+            Arg::new("line_style")
+                .long("line-style")
+                .takes_value(true)
+                .value_name("STYLE")
+                .possible_values(LINE_STYLES)
+                .conflicts_with("charset")
+                .help("Draw the tree lines in a box-drawing style: default, rounded, double, heavy or dots."),
+        )
         .arg(Arg::new("print_size").short('s').help("Print the size of each file in bytes along with the name."),)
         .arg(Arg::new("human_readable").short('h').help("Print the size of each file but in a more human readable way, e.g. appending a size letter for kilobytes (K), megabytes (M), gigabytes (G), and so forth."),)
+        .arg(
+            Arg::new("inodes")
+                .long("inodes")
+                .help("Print the inode number of each file or directory."),
+        )
+        .arg(
+            Arg::new("device")
+                .long("device")
+                .help("Print the device ID number to which each file or directory belongs."),
+        )
+        .arg(
+            Arg::new("si")
+                .long("si")
+                .help("Like -h, but use SI units (powers of 1000: kB, MB, GB) instead of powers of 1024."),
+        )
+        .arg(
+            Arg::new("size_precision")
+                .long("size-precision")
+                .takes_value(true)
+                .value_name("DIGITS")
+                .help("Number of decimal places in human readable sizes, from 0 to 9 (default 1)."),
+        )
+        .arg(
+            Arg::new("size_rounding")
+                .long("size-rounding")
+                .takes_value(true)
+                .value_name("MODE")
+                .possible_values(["nearest", "up", "down"])
+                .help("How human readable sizes are rounded (default nearest)."),
+        )
+        .arg(
+            Arg::new("du")
+                .long("du")
+                .help("For each directory report its size as the accumulation of sizes of all its files and sub-directories (implies -s). The total amount of used space is also given in the final report."),
+        )
+        .arg(
+            Arg::new("threads")
+                .long("threads")
+                .takes_value(true)
+                .value_name("N")
+                .help("Total sizes for --du on N threads (default: one per CPU core)."),
+        )
+        .arg(
+            Arg::new("bars")
+                .long("bars")
+                .requires("du")
+                .help("With --du, show each entry's share of its directory's size as a bar and a percentage, e.g. [######....  58%]."),
+        )
+        .arg(
+            Arg::new("permissions")
+                .short('p')
+                .help("Print the protections for each file, e.g. [drwxr-xr-x]."),
+        )
+        .arg(
+            Arg::new("mode")
+                .long("mode")
+                .takes_value(true)
+                .value_name("FORMAT")
+                .possible_values(["symbolic", "octal", "both"])
+                .help("How -p prints protections: symbolic (drwxr-xr-x, the default), octal (0755) or both (implies -p)."),
+        )
+        .arg(
+            Arg::new("attributes")
+                .long("attributes")
+                .help("Print the Windows attributes of each file or directory as attrib letters: R read-only, H hidden, S system, A archive, e.g. [R--A]. Windows only."),
+        )
+        .arg(
+            Arg::new("group")
+                .short('g')
+                .long("group")
+                .help("Print the group name, or GID # if no group name is available, of each file."),
+        )
+        .arg(
+            Arg::new("dirsfirst")
+                .long("dirsfirst")
+                .help("List directories before files."),
+        )
+        .arg(
+            Arg::new("filesfirst")
+                .long("filesfirst")
+                .conflicts_with("dirsfirst")
+                .help("List files before directories."),
+        )
+        .arg(
+            Arg::new("filelimit")
+                .long("filelimit")
+                .takes_value(true)
+                .value_name("N")
+                .help("Do not descend directories that contain more than N entries, noting their entry count instead."),
+        )
+        .arg(
+            Arg::new("auto_limit")
+                .long("auto-limit")
+                .takes_value(true)
+                .value_name("N")
+                .help("In directories with more than N entries, list only the first N followed by how many more there are, and warn about the directory on stderr."),
+        )
+        .arg(
+            Arg::new("limit")
+                .long("limit")
+                .takes_value(true)
+                .value_name("N")
+                .help("List only the first N entries of each directory, followed by a '… and N more' line for the rest."),
+        )
+        .arg(
+            Arg::new("group_by")
+                .long("group-by")
+                .takes_value(true)
+                .value_name("GROUPING")
+                .possible_values(["type", "ext"])
+                .help("Within each directory, list entries in sections headed by type (Directories, Code, Docs, Media, Other) or by extension."),
+        )
+        .arg(
+            Arg::new("date")
+                .short('D')
+                .help("Print the date of the last modification time or if -c is used, the last status change time for the file listed."),
+        )
+        .arg(
+            Arg::new("timefmt")
+                .long("timefmt")
+                .takes_value(true)
+                .value_name("FORMAT")
+                .help("Prints (implies -D) and formats the date according to the strftime-like format string, e.g. '%Y-%m-%d' or '%+' for RFC 3339."),
+        )
+        .arg(
+            Arg::new("relative_date")
+                .long("relative-date")
+                .conflicts_with("timefmt")
+                .help("Print dates (implies -D) as ages such as '3 hours ago' or '2 days ago'."),
+        )
         .arg(
             Arg::new("color")
                 .short('C')
-                .help("Turn colorization on using built-in color defaults."),
+                .long("color")
+                .takes_value(true)
+                .value_name("WHEN")
+                .min_values(0)
+                .require_equals(true)
+                .default_missing_value("always")
+                .possible_values(["auto", "always", "never"])
+                .overrides_with_all(&["color", "no_color"])
+                .help("Colorize output: 'always', 'never', or with 'auto' (the default) only when writing to a terminal and not to a file with -o. -C and a bare --color mean 'always'."),
         )
         .arg(
             Arg::new("no_color")
                 .short('n')
-                .help("Turn colorization off, overridden by -C."),
+                .overrides_with_all(&["color", "no_color"])
+                .help("Turn colorization off, the same as --color=never. The last of -C and -n given wins."),
+        )
+        .arg(
+            Arg::new("theme")
+                .long("theme")
+                .takes_value(true)
+                .value_name("THEME")
+                .help("Colorize (implies -C unless --color is given) with a bundled theme: default, light (for light backgrounds), mono (bold and underlined text only) or solarized. Or give the path of a TOML theme file of 'key = \"style\"' lines, e.g. 'directory = \"bold #268bd2\"'; keys are base, directory, symlink, broken_link, executable, archive, image, git_staged, git_changed and git_ignored."),
+        )
+        .arg(
+            Arg::new("describe")
+                .long("describe")
+                .help("Follow the report with a short plain-text description of the tree: its top level, most common file types and largest directories."),
+        )
+        .arg(
+            Arg::new("explain_filters")
+                .long("explain-filters")
+                .help("Instead of the tree, print how many entries each active filter (hidden files, -L, -P, -I, --gitignore, -d, --type, sizes, dates) left out."),
+        )
+        .arg(
+            Arg::new("info")
+                .long("info")
+                .help("Print the comments from each directory's .info file beneath the entries they match. In a .info file, lines are wild-card patterns and the tab-indented lines after them are their comment."),
+        )
+        .arg(
+            Arg::new("dir_descriptions")
+                .long("dir-descriptions")
+                .help("Show a short description after each directory name: the first line of its .dirinfo file, or else the first heading of its README.md."),
+        )
+        .arg(
+            Arg::new("dir_counts")
+                .long("dir-counts")
+                .help("Show how many files and directories are listed below each directory after its name, e.g. '(12 files, 3 dirs)'."),
+        )
+        .arg(
+            Arg::new("replace_nonprintable")
+                .short('q')
+                .help("Print non-printable characters in filenames as '?', protecting the terminal from escape sequences in names."),
+        )
+        .arg(
+            Arg::new("quote_names")
+                .short('Q')
+                .help("Quote the names of files and symlink targets in double quotes, escaping '\"' and '\\', so names with spaces can be pasted into a shell."),
+        )
+        .arg(
+            Arg::new("head")
+                .long("head")
+                .takes_value(true)
+                .value_name("N")
+                .help("Stop printing the tree after N lines, followed by a notice that the listing is incomplete and the report of what was printed."),
+        )
+        .arg(
+            Arg::new("classify")
+                .short('F')
+                .long("classify")
+                .help("Append a character marking each entry's kind, as ls -F does: '/' for directories, '@' for symlinks, '=' for sockets, '|' for FIFOs, '>' for doors and '*' for executables. Symlinks whose target is missing are also marked [broken]."),
+        )
+        .arg(
+            Arg::new("emoji")
+                .long("emoji")
+                .help("Put an emoji marking each entry's kind before its name: 📁 directories, 📄 files, 🔗 symlinks and ⚙️ executables. Unlike icon fonts, these show up anywhere the output is pasted."),
+        )
+        .arg(
+            Arg::new("max_width")
+                .long("max-width")
+                .takes_value(true)
+                .value_name("COLS")
+                .help("Truncate lines wider than COLS terminal columns, ending them with '…'. 'auto' uses the width of the terminal, and leaves lines whole when output is not a terminal."),
+        )
+        .arg(
+            Arg::new("term_integration")
+                .long("term-integration")
+                .help("Emit terminal escape sequences marking directory entries as hyperlinks (OSC 8) and reporting the root as working directory (OSC 7)."),
+        )
+        .arg(
+            Arg::new("output")
+                .short('o')
+                .takes_value(true)
+                .value_name("FILE")
+                .help("Send output to FILE. The file itself is left out of the listing."),
+        )
+        .arg(
+            Arg::new("linemap")
+                .long("linemap")
+                .takes_value(true)
+                .value_name("FILE")
+                .help("Write a sidecar file mapping each printed line to its entry, one 'line<TAB>byte offset<TAB>path' record per line."),
+        )
+        .arg(
+            Arg::new("timeout")
+                .long("timeout")
+                .takes_value(true)
+                .value_name("DURATION")
+                .help("Stop traversing after DURATION (e.g. 10s, 500ms, 2m), print what was gathered and exit with status 124."),
+        )
+        .arg(
+            Arg::new("progress")
+                .long("progress")
+                .help("Show how many entries have been read on stderr while traversing, when it is a terminal."),
+        )
+        .arg(
+            Arg::new("exists")
+                .long("exists")
+                .takes_value(true)
+                .value_name("PATTERN")
+                .help("Instead of listing, exit with status 0 as soon as an entry matching the wild-card pattern is found, or 1 if there is none or the search fails (e.g. a path that does not exist, with an error message). Invalid arguments exit with 2."),
+        )
+        .arg(
+            Arg::new("print_match")
+                .long("print-match")
+                .requires("exists")
+                .help("With --exists, print the path of the entry found."),
+        )
+        .arg(
+            Arg::new("fromfile")
+                .long("fromfile")
+                .help("Reads the paths to list from the file given as the directory argument ('.' or '-' for stdin) instead of the filesystem. One path per line, a trailing '/' marking a directory, or 'path<TAB>size<TAB>mtime<TAB>mode' records (mtime in Unix seconds, mode in octal)."),
+        )
+        .arg(
+            Arg::new("input_format")
+                .long("input-format")
+                .takes_value(true)
+                .value_name("FORMAT")
+                .possible_values(["plain", "tsv"])
+                .requires("fromfile")
+                .help("Format of the --fromfile listing, detected by default."),
         )
         .get_matches();
 
-    let path = matches.value_of("directory").unwrap_or(".");
-    let level = matches
-        .value_of("level")
-        .and_then(|l| l.parse::<i32>().ok());
-    let pattern_glob: Option<Pattern> = matches.value_of("pattern").map(|pattern| {
-        Pattern::new(pattern).unwrap_or_else(|_| {
+    if matches.is_present("show_version") {
+        let verbose = matches.occurrences_of("show_version") > 1 || matches.is_present("verbose");
+        exit_on_error(print_lines(version_lines(verbose)));
+        return;
+    }
+
+    let smart_ignore_patterns: Vec<&str> = SMART_IGNORE_PATTERNS
+        .iter()
+        .copied()
+        .chain(
+            matches
+                .values_of("smart_ignore_add")
+                .into_iter()
+                .flatten()
+                .flat_map(|patterns| patterns.split('|')),
+        )
+        .collect();
+    if matches.is_present("print_smart_ignore") {
+        exit_on_error(print_lines(&smart_ignore_patterns));
+        return;
+    }
+
+    let paths: Vec<&str> = matches
+        .values_of("directory")
+        .map(|paths| paths.collect())
+        .unwrap_or_else(|| vec!["."]);
+    let level = matches.value_of("level").map(|level| {
+        level.parse::<i32>().unwrap_or_else(|_| {
+            eprintln!("Error: Invalid level '{}'.", level);
+            std::process::exit(USAGE_ERROR);
+        })
+    });
+    let min_depth = matches.value_of("min_depth").map(|min_depth| {
+        min_depth.parse::<usize>().unwrap_or_else(|_| {
+            eprintln!("Error: Invalid depth '{}'.", min_depth);
+            std::process::exit(USAGE_ERROR);
+        })
+    });
+    let parse_patterns = |patterns: &str| {
+        parse_pattern_alternatives(patterns).unwrap_or_else(|_| {
             eprintln!("Error: Invalid glob pattern.");
-            std::process::exit(1);
+            std::process::exit(USAGE_ERROR);
+        })
+    };
+    let mut pattern_globs: Vec<Pattern> = matches
+        .value_of("pattern")
+        .map(parse_patterns)
+        .unwrap_or_default();
+    // --ext is shorthand for a -P pattern per extension
+    if let Some(extensions) = matches.values_of("ext") {
+        for extension in extensions.flat_map(|extensions| extensions.split(',')) {
+            let extension = extension.trim().trim_start_matches('.');
+            if !extension.is_empty() {
+                pattern_globs
+                    .push(Pattern::new(&format!("*.{}", Pattern::escape(extension))).unwrap());
+            }
+        }
+    }
+    let mut exclude_patterns: Vec<Pattern> = matches
+        .values_of("exclude")
+        .map(|patterns| patterns.flat_map(parse_patterns).collect())
+        .unwrap_or_default();
+    if matches.is_present("smart_ignore") || matches.is_present("smart_ignore_add") {
+        exclude_patterns.extend(
+            smart_ignore_patterns
+                .iter()
+                .flat_map(|pattern| parse_patterns(pattern)),
+        );
+    }
+    let read_pattern_file = |path: &str| {
+        read_ignore_file(Path::new(path)).unwrap_or_else(|err| {
+            eprintln!("Error: {}: {}", path, err);
+            std::process::exit(USAGE_ERROR);
+        })
+    };
+    if let Some(paths) = matches.values_of("include_from") {
+        pattern_globs.extend(paths.flat_map(read_pattern_file));
+    }
+    if let Some(paths) = matches.values_of("exclude_from") {
+        exclude_patterns.extend(paths.flat_map(read_pattern_file));
+    }
+    if !matches.is_present("no_treeignore") {
+        // Listings from --fromfile have no root directory to hold one
+        let roots: &[&str] = if matches.is_present("fromfile") {
+            &[]
+        } else {
+            &paths
+        };
+        let ignore_files = home_dir()
+            .into_iter()
+            .chain(roots.iter().map(PathBuf::from))
+            .map(|dir| dir.join(".treeignore"));
+        for ignore_file in ignore_files {
+            match read_ignore_file(&ignore_file) {
+                Ok(patterns) => exclude_patterns.extend(patterns),
+                Err(err) if err.kind() == ErrorKind::NotFound => {}
+                Err(err) => eprintln!("Warning: {}: {}", ignore_file.display(), err),
+            }
+        }
+    }
+
+    let regexes = |arg: &str| -> Vec<Regex> {
+        matches
+            .values_of(arg)
+            .map(|regexes| {
+                regexes
+                    .map(|regex| {
+                        Regex::new(regex).unwrap_or_else(|e| {
+                            eprintln!("Error: Invalid regular expression: {}", e);
+                            std::process::exit(USAGE_ERROR);
+                        })
+                    })
+                    .collect()
+            })
+            .unwrap_or_default()
+    };
+    let include_regexes = regexes("regex");
+    let exclude_regexes = regexes("regex_exclude");
+    // Searched line by line in file contents, which need not be UTF-8
+    let content_patterns: Vec<BytesRegex> = matches
+        .values_of("contains")
+        .into_iter()
+        .flatten()
+        .map(regex::escape)
+        .chain(
+            matches
+                .values_of("contains_regex")
+                .into_iter()
+                .flatten()
+                .map(String::from),
+        )
+        .map(|regex| {
+            BytesRegex::new(&regex).unwrap_or_else(|e| {
+                eprintln!("Error: Invalid regular expression: {}", e);
+                std::process::exit(USAGE_ERROR);
+            })
+        })
+        .collect();
+
+    let size_limit = |arg: &str| {
+        matches.value_of(arg).map(|size| {
+            parse_size(size).unwrap_or_else(|| {
+                eprintln!("Error: Invalid size '{}'.", size);
+                std::process::exit(USAGE_ERROR);
+            })
+        })
+    };
+    let min_size = size_limit("min_size");
+    let max_size = size_limit("max_size");
+
+    let date_limit = |arg: &str| {
+        matches.value_of(arg).map(|date| {
+            parse_date(date).unwrap_or_else(|| {
+                eprintln!("Error: Invalid date '{}'.", date);
+                std::process::exit(USAGE_ERROR);
+            })
+        })
+    };
+    let newer_than = date_limit("newer");
+    let older_than = date_limit("older");
+
+    let head = matches.value_of("head").map(|head| {
+        head.parse().unwrap_or_else(|_| {
+            eprintln!("Error: Invalid line count.");
+            std::process::exit(USAGE_ERROR);
+        })
+    });
+
+    let max_width = matches.value_of("max_width").and_then(|width| {
+        if width == "auto" {
+            return terminal_width();
+        }
+        Some(width.parse().unwrap_or_else(|_| {
+            eprintln!("Error: Invalid width.");
+            std::process::exit(USAGE_ERROR);
+        }))
+    });
+
+    let timeout = matches.value_of("timeout").map(|timeout| {
+        parse_duration(timeout).unwrap_or_else(|| {
+            eprintln!("Error: Invalid timeout duration.");
+            std::process::exit(USAGE_ERROR);
+        })
+    });
+
+    let sort = if matches.is_present("unsorted") {
+        SortKey::None
+    } else if matches.is_present("ctime_sort") {
+        SortKey::ChangeTime
+    } else {
+        matches
+            .value_of("sort")
+            .map(|key| key.parse().unwrap())
+            .unwrap_or_default()
+    };
+
+    let time_format = matches.value_of("timefmt").map(|format| {
+        if !is_valid_time_format(format) {
+            eprintln!("Error: Invalid time format.");
+            std::process::exit(USAGE_ERROR);
+        }
+        format.to_string()
+    });
+
+    let file_limit = matches.value_of("filelimit").map(|limit| {
+        limit.parse::<usize>().unwrap_or_else(|_| {
+            eprintln!("Error: Invalid file limit.");
+            std::process::exit(USAGE_ERROR);
+        })
+    });
+
+    let threads = matches
+        .value_of("threads")
+        .map(|threads| match threads.parse::<usize>() {
+            Ok(threads) if threads > 0 => threads,
+            _ => {
+                eprintln!("Error: Invalid number of threads.");
+                std::process::exit(USAGE_ERROR);
+            }
+        });
+
+    let external_sort =
+        matches
+            .value_of("external_sort")
+            .map(|chunk| match chunk.parse::<usize>() {
+                Ok(chunk) if chunk > 0 => chunk,
+                _ => {
+                    eprintln!("Error: Invalid number of entries to sort in memory.");
+                    std::process::exit(USAGE_ERROR);
+                }
+            });
+
+    let limit = matches.value_of("limit").map(|limit| {
+        limit.parse::<usize>().unwrap_or_else(|_| {
+            eprintln!("Error: Invalid entry limit.");
+            std::process::exit(USAGE_ERROR);
+        })
+    });
+
+    let auto_limit = matches.value_of("auto_limit").map(|limit| {
+        limit.parse::<usize>().unwrap_or_else(|_| {
+            eprintln!("Error: Invalid auto limit.");
+            std::process::exit(USAGE_ERROR);
         })
     });
 
+    let columns = matches.value_of("columns").map(|columns| {
+        columns
+            .split(',')
+            .map(|column| column.trim().parse())
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap_or_else(|e| {
+                eprintln!("Error: {}.", e);
+                std::process::exit(USAGE_ERROR);
+            })
+    });
+
+    let line_style = matches.value_of("line_style").and_then(Charset::line_style);
+    let charset = matches
+        .value_of("charset")
+        .map(|charset| {
+            charset.parse().unwrap_or_else(|e| {
+                eprintln!("Error: {}.", e);
+                std::process::exit(USAGE_ERROR);
+            })
+        })
+        .or(line_style)
+        .unwrap_or_default();
+
+    let size_precision = matches.value_of("size_precision").map(|precision| {
+        precision
+            .parse::<usize>()
+            .ok()
+            .filter(|precision| *precision <= MAX_SIZE_PRECISION)
+            .unwrap_or_else(|| {
+                eprintln!(
+                    "Error: Invalid size precision, expected 0 to {}.",
+                    MAX_SIZE_PRECISION
+                );
+                std::process::exit(USAGE_ERROR);
+            })
+    });
+
+    let theme = matches.value_of("theme").map(|theme| {
+        Theme::named(theme).unwrap_or_else(|| {
+            let text = fs::read_to_string(theme).unwrap_or_else(|err| {
+                eprintln!(
+                    "Error: Unknown theme '{}' ({}); use one of {} or a theme file.",
+                    theme,
+                    err,
+                    THEME_NAMES.join(", ")
+                );
+                std::process::exit(USAGE_ERROR);
+            });
+            Theme::parse(&text).unwrap_or_else(|err| {
+                eprintln!("Error: {}: {}", theme, err);
+                std::process::exit(USAGE_ERROR);
+            })
+        })
+    });
+
+    // A theme asks for color unless told otherwise
+    let color_when = if matches.is_present("no_color") {
+        "never"
+    } else {
+        matches
+            .value_of("color")
+            .unwrap_or(if theme.is_some() { "always" } else { "auto" })
+    };
+    let color = match color_when {
+        "always" => true,
+        "never" => false,
+        _ => !matches.is_present("output") && io::stdout().is_terminal(),
+    };
+
+    let mut entry_types: Vec<EntryType> = matches
+        .values_of("type")
+        .map(|types| {
+            types
+                .map(|entry_type| entry_type.parse().unwrap())
+                .collect()
+        })
+        .unwrap_or_default();
+    if matches.is_present("executables") {
+        entry_types.push(EntryType::Executable);
+    }
+
+    // Directories that could not be read are warned about as the listing
+    // goes on, and make it exit with 1 once done
+    let unreadable = Arc::new(AtomicBool::new(false));
+    let unreadable_seen = Arc::clone(&unreadable);
     let options = TreeOptions {
         all_files: matches.is_present("all_files"),
         level,
+        min_depth,
         full_path: matches.is_present("full_path"),
         dir_only: matches.is_present("dir_only"),
+        entry_types,
+        follow_links: matches.is_present("follow_links"),
+        sort,
+        ignore_case_sort: matches.is_present("ignore_case_sort"),
+        dirs_first: matches.is_present("dirsfirst"),
+        files_first: matches.is_present("filesfirst"),
+        group_by: matches
+            .value_of("group_by")
+            .map(|group_by| group_by.parse().unwrap()),
+        external_sort,
+        file_limit,
+        auto_limit,
+        limit,
         no_indent: matches.is_present("no_indent"),
+        columns,
+        charset,
         print_size: matches.is_present("print_size"),
-        human_readable: matches.is_present("human_readable"),
-        pattern_glob,
-        color: matches.is_present("color"),
-        no_color: matches.is_present("no_color"),
+        human_readable: matches.is_present("human_readable") || matches.is_present("si"),
+        si_units: matches.is_present("si"),
+        size_precision,
+        size_rounding: matches
+            .value_of("size_rounding")
+            .map(|rounding| rounding.parse().unwrap())
+            .unwrap_or_default(),
+        disk_usage: matches.is_present("du"),
+        bars: matches.is_present("bars"),
+        threads,
+        show_permissions: matches.is_present("permissions") || matches.is_present("mode"),
+        permission_mode: matches
+            .value_of("mode")
+            .map(|mode| mode.parse().unwrap())
+            .unwrap_or_default(),
+        show_attributes: matches.is_present("attributes"),
+        show_group: matches.is_present("group"),
+        show_inodes: matches.is_present("inodes"),
+        show_device: matches.is_present("device"),
+        print_date: matches.is_present("date")
+            || time_format.is_some()
+            || matches.is_present("relative_date"),
+        time_format,
+        relative_date: matches.is_present("relative_date"),
+        pattern_globs,
+        exclude_patterns,
+        include_regexes,
+        exclude_regexes,
+        full_match: matches.is_present("full_match"),
+        content_patterns,
+        gitignore: matches.is_present("gitignore"),
+        git_status: matches.is_present("git_status"),
+        min_size,
+        max_size,
+        newer_than,
+        older_than,
+        nonempty: matches.is_present("nonempty"),
+        prune: matches.is_present("prune"),
+        color,
+        theme: theme.unwrap_or_default(),
+        describe: matches.is_present("describe"),
+        explain_filters: matches.is_present("explain_filters"),
+        print_info: matches.is_present("info"),
+        dir_descriptions: matches.is_present("dir_descriptions"),
+        dir_counts: matches.is_present("dir_counts"),
+        replace_nonprintable: matches.is_present("replace_nonprintable"),
+        quote_names: matches.is_present("quote_names"),
+        classify: matches.is_present("classify"),
+        emoji: matches.is_present("emoji"),
+        max_width,
+        head,
+        term_integration: matches.is_present("term_integration"),
+        output: matches.value_of("output").map(PathBuf::from),
+        linemap: matches.value_of("linemap").map(PathBuf::from),
+        timeout,
+        on_progress: (matches.is_present("progress") && io::stderr().is_terminal())
+            .then(|| Box::new(print_progress) as ProgressHandler),
+        on_error: Some(Box::new(move |path, err| {
+            unreadable_seen.store(true, Ordering::Relaxed);
+            eprintln!("Warning: {}: {}", path.display(), err);
+        })),
+        ..Default::default()
+    };
+
+    if let Some(patterns) = matches.value_of("exists") {
+        let patterns = parse_patterns(patterns);
+        let found = paths
+            .iter()
+            .map(|path| {
+                find_first(path, &patterns, &options).map_err(|e| format!("{}: {}", path, e))
+            })
+            .find(|result| !matches!(result, Ok(None)))
+            .unwrap_or(Ok(None));
+        match found {
+            Ok(Some(found)) => {
+                if matches.is_present("print_match") {
+                    exit_on_error(print_lines([found.display()]));
+                }
+                std::process::exit(0);
+            }
+            Ok(None) => std::process::exit(1),
+            // As when listing, apart from invalid arguments
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    let result = if matches.is_present("fromfile") {
+        let format = matches
+            .value_of("input_format")
+            .map(|format| format.parse().unwrap());
+        list_from_files(&paths, format, &options)
+    } else {
+        list_directories(&paths, &options)
     };
+    exit_on_error(result);
+    if unreadable.load(Ordering::Relaxed) {
+        std::process::exit(1);
+    }
+}
+
+/// Exits with the status for `result`'s error, if any: 124 for a timeout
+/// and 1 for other errors, after printing them.
+fn exit_on_error(result: io::Result<()>) {
+    if let Err(e) = result {
+        match e.kind() {
+            ErrorKind::TimedOut => std::process::exit(124),
+            // The reader went away (e.g. `tree | head`), nothing left to do
+            ErrorKind::BrokenPipe => {}
+            _ => {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+}
+
+/// Prints each of `lines` on stdout. Unlike `println!`, returns an error
+/// rather than panicking when stdout is closed, as by `tree -VV | head -1`.
+fn print_lines<I>(lines: I) -> io::Result<()>
+where
+    I: IntoIterator,
+    I::Item: Display,
+{
+    let mut out = io::stdout().lock();
+    for line in lines {
+        writeln!(out, "{}", line)?;
+    }
+    out.flush()
+}
+
+/// Shows `progress` on the last line of stderr, clearing it once the tree
+/// has been read so the listing starts on a clean line.
+fn print_progress(progress: &Progress) {
+    if progress.done {
+        eprint!("\r\x1b[K");
+        return;
+    }
+    let line = format!(
+        "{} entries read in {:.1}s, at {}",
+        progress.entries,
+        progress.elapsed.as_secs_f64(),
+        progress.current_dir.display()
+    );
+    // Kept to one line so that it can be redrawn in place
+    let width = terminal_width().unwrap_or(80).saturating_sub(1);
+    eprint!("\r\x1b[K{}", truncate_line(&line, width));
+}
+
+/// Lists the paths read from each of `files`, or stdin for `.` and `-`,
+/// under a root named after the file.
+fn list_from_files(
+    files: &[&str],
+    format: Option<InputFormat>,
+    options: &TreeOptions,
+) -> io::Result<()> {
+    let mut listings = Vec::with_capacity(files.len());
+    for file in files {
+        let text = if *file == "." || *file == "-" {
+            let mut text = String::new();
+            io::stdin().read_to_string(&mut text)?;
+            text
+        } else {
+            fs::read_to_string(file)?
+        };
+        listings.push(Listing::parse(&text, format)?);
+    }
+    let roots: Vec<ListingRoot> = files
+        .iter()
+        .zip(&listings)
+        .map(|(file, listing)| ListingRoot {
+            source: listing,
+            path: PathBuf::new(),
+            name: file.to_string(),
+        })
+        .collect();
+    list_roots(&roots, options)
+}
 
-    if let Err(e) = list_directory(path, &options) {
-        eprintln!("Error: {}", e);
+/// The lines `--version` prints, with the build's features, target and
+/// commit if `verbose`.
+fn version_lines(verbose: bool) -> Vec<String> {
+    let mut lines = vec![format!("tree {}", env!("CARGO_PKG_VERSION"))];
+    if verbose {
+        let or = |value: &'static str, default| if value.is_empty() { default } else { value };
+        lines.push(format!(
+            "features: {}",
+            or(env!("TREE_BUILD_FEATURES"), "none")
+        ));
+        lines.push(format!(
+            "target: {}",
+            or(env!("TREE_BUILD_TARGET"), "unknown")
+        ));
+        lines.push(format!(
+            "commit: {}",
+            or(env!("TREE_BUILD_COMMIT"), "unknown")
+        ));
     }
+    lines
 }
@@ -0,0 +1,190 @@
+use std::collections::HashMap;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use tokio::fs;
+
+use crate::rust_tree::entry::{EntryInfo, EntrySource, TreeNode};
+use crate::rust_tree::options::TreeOptions;
+use crate::rust_tree::traversal::{build_tree_from_source, skip_reason, Ancestors};
+use crate::rust_tree::utils::DirId;
+
+/// Whether listing with `options` reads the file named `name` from the
+/// directories it lists: gitignore rules, `.info` comments or directory
+/// descriptions.
+fn reads_file(name: &str, options: &TreeOptions) -> bool {
+    match name {
+        ".gitignore" => options.gitignore,
+        ".info" => options.print_info,
+        ".dirinfo" | "README.md" => options.dir_descriptions,
+        _ => false,
+    }
+}
+
+/// Reads the tree below `path` into nodes as `build_tree` does, reading the
+/// filesystem with `tokio::fs` so that async callers are not blocked.
+///
+/// The directories the listing needs are read first, then filtered and
+/// sorted in memory as `build_tree` would. A few options still touch the
+/// filesystem synchronously while doing so: `content_patterns` reads the
+/// files it matches, `git_status` runs git, `gitignore` reads the rules
+/// from above the root, and shown permissions look up ACLs.
+pub async fn build_tree_async<P: AsRef<Path>>(
+    path: P,
+    options: &TreeOptions,
+) -> io::Result<TreeNode> {
+    let root = path.as_ref();
+    let snapshot = Snapshot::read(root, options).await?;
+    build_tree_from_source(&snapshot, root, options)
+}
+
+/// The entries below a root, read ahead of building its tree.
+struct Snapshot {
+    root: EntryInfo,
+    /// The entries of each directory read, unfiltered.
+    dirs: HashMap<PathBuf, Vec<EntryInfo>>,
+    /// Why directories below the root could not be read, to report when
+    /// the tree is built.
+    errors: HashMap<PathBuf, (io::ErrorKind, String)>,
+    ids: HashMap<PathBuf, DirId>,
+    files: HashMap<PathBuf, String>,
+}
+
+impl Snapshot {
+    /// Reads the directories below `root` that listing it with `options`
+    /// opens: those not left out by a filter, and not reached through a
+    /// symlink loop.
+    async fn read(root: &Path, options: &TreeOptions) -> io::Result<Snapshot> {
+        let mut snapshot = Snapshot {
+            root: EntryInfo::from_metadata(root.to_path_buf(), &fs::metadata(root).await?),
+            dirs: HashMap::new(),
+            errors: HashMap::new(),
+            ids: HashMap::new(),
+            files: HashMap::new(),
+        };
+        snapshot
+            .ids
+            .extend(dir_id(root).await.map(|id| (root.to_path_buf(), id)));
+
+        // Directories to read, with the depth of their entries and the
+        // directories above them
+        let ancestors = Ancestors::below(&None, snapshot.ids.get(root).cloned());
+        let mut pending = vec![(root.to_path_buf(), 0, ancestors)];
+        while let Some((dir, depth, ancestors)) = pending.pop() {
+            // Building the tree stops on the flag as well
+            if options.is_cancelled() {
+                break;
+            }
+            let entries = match read_entries(&dir, options.shows_permissions()).await {
+                Ok(entries) => entries,
+                Err(err) if depth > 0 => {
+                    snapshot.errors.insert(dir, (err.kind(), err.to_string()));
+                    continue;
+                }
+                Err(err) => return Err(err),
+            };
+            for entry in &entries {
+                let name = entry.file_name.to_string_lossy();
+                if reads_file(&name, options) {
+                    if let Ok(text) = fs::read_to_string(&entry.path).await {
+                        snapshot.files.insert(entry.path.clone(), text);
+                    }
+                }
+                if !entry.is_listed_as_dir(options) || skip_reason(entry, options, depth).is_some()
+                {
+                    continue;
+                }
+                let mut id = entry.dir_id.as_ref().cloned();
+                if id.is_none() {
+                    id = dir_id(&entry.path).await;
+                }
+                if let Some(id) = id {
+                    if Ancestors::contains(&ancestors, &id) {
+                        continue;
+                    }
+                    snapshot.ids.insert(entry.path.clone(), id);
+                }
+                let below = Ancestors::below(&ancestors, snapshot.ids.get(&entry.path).cloned());
+                pending.push((entry.path.clone(), depth + 1, below));
+            }
+            snapshot.dirs.insert(dir, entries);
+        }
+        Ok(snapshot)
+    }
+}
+
+impl EntrySource for Snapshot {
+    fn read_entries(&self, dir: &Path) -> io::Result<Vec<EntryInfo>> {
+        if let Some((kind, message)) = self.errors.get(dir) {
+            return Err(io::Error::new(*kind, message.clone()));
+        }
+        self.dirs.get(dir).cloned().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("{} was not read", dir.display()),
+            )
+        })
+    }
+
+    fn dir_id(&self, path: &Path) -> Option<DirId> {
+        self.ids.get(path).cloned()
+    }
+
+    fn entry(&self, path: &Path) -> io::Result<EntryInfo> {
+        if path == self.root.path {
+            Ok(self.root.clone())
+        } else {
+            Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("{} was not read", path.display()),
+            ))
+        }
+    }
+
+    fn read_to_string(&self, path: &Path) -> io::Result<String> {
+        self.files.get(path).cloned().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("{} was not read", path.display()),
+            )
+        })
+    }
+}
+
+/// Reads the entries directly inside `dir`, as `Filesystem` does, or with
+/// `acls`, as `FilesystemAcls` does.
+async fn read_entries(dir: &Path, acls: bool) -> io::Result<Vec<EntryInfo>> {
+    let mut entries = Vec::new();
+    let mut read_dir = fs::read_dir(dir).await?;
+    while let Some(dir_entry) = read_dir.next_entry().await? {
+        // The entry may have vanished since the directory was read
+        let Ok(metadata) = dir_entry.metadata().await else {
+            continue;
+        };
+        let mut entry = EntryInfo::from_metadata(dir_entry.path(), &metadata);
+        entry.file_name = dir_entry.file_name();
+        if metadata.file_type().is_symlink() {
+            entry.set_target(fs::metadata(&entry.path).await);
+            entry.link_target = fs::read_link(&entry.path).await.ok();
+        }
+        if acls {
+            entry.read_acl();
+        }
+        entries.push(entry);
+    }
+    Ok(entries)
+}
+
+/// The identity of the directory `path` resolves to, as `utils::dir_id`.
+#[cfg(unix)]
+async fn dir_id(path: &Path) -> Option<DirId> {
+    use std::os::unix::fs::MetadataExt;
+
+    let metadata = fs::metadata(path).await.ok()?;
+    Some((metadata.dev(), metadata.ino()))
+}
+
+#[cfg(not(unix))]
+async fn dir_id(path: &Path) -> Option<DirId> {
+    fs::canonicalize(path).await.ok()
+}
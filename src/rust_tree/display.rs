@@ -1,24 +1,666 @@
-use ansi_term::Colour::{Blue, Cyan, Green, Red, Yellow};
-use is_executable::IsExecutable;
-use std::fs;
-
-pub fn colorize(entry: &fs::DirEntry, text: String) -> String {
-    let file_type = entry.file_type().unwrap();
-    let is_exec = entry.path().is_executable();
-
-    if file_type.is_dir() {
-        Blue.bold().paint(text).to_string()
-    } else if file_type.is_symlink() {
-        Cyan.paint(text).to_string()
-    } else if is_exec {
-        Green.paint(text).to_string()
-    } else if let Some(extension) = entry.path().extension() {
+#[cfg(feature = "color")]
+use ansi_term::{Colour, Style};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use unicode_width::UnicodeWidthStr;
+
+use crate::rust_tree::entry::{EntryInfo, EntryKind, TreeNode};
+use crate::rust_tree::options::{Column, GroupBy, PermissionMode, SortKey, TreeOptions};
+use crate::rust_tree::theme::Theme;
+#[cfg(feature = "color")]
+use crate::rust_tree::theme::{Colour as ThemeColour, Style as ThemeStyle};
+#[cfg(unix)]
+use crate::rust_tree::utils::group_name;
+use crate::rust_tree::utils::{
+    file_url, format_age, format_time, replace_nonprintable, DEFAULT_TIME_FORMAT,
+};
+
+#[cfg(feature = "color")]
+pub fn colorize(entry: &EntryInfo, text: String, theme: &Theme) -> String {
+    let style = if entry.kind == EntryKind::Directory {
+        &theme.directory
+    } else if entry.kind == EntryKind::Symlink {
+        if entry.broken_link {
+            &theme.broken_link
+        } else {
+            &theme.symlink
+        }
+    } else if entry.executable {
+        &theme.executable
+    } else if let Some(extension) = entry.path.extension() {
         match extension.to_string_lossy().to_lowercase().as_str() {
-            "tar" | "gz" | "xz" | "bz2" | "zip" | "7z" => Red.paint(text).to_string(),
-            "jpg" | "jpeg" | "bmp" | "gif" | "png" => Yellow.paint(text).to_string(),
-            _ => text,
+            "tar" | "gz" | "xz" | "bz2" | "zip" | "7z" => &theme.archive,
+            "jpg" | "jpeg" | "bmp" | "gif" | "png" => &theme.image,
+            _ => return text,
+        }
+    } else {
+        return text;
+    };
+    paint(style, &text)
+}
+
+/// Without the `color` feature, entries are printed as plain text.
+#[cfg(not(feature = "color"))]
+pub fn colorize(_entry: &EntryInfo, text: String, _theme: &Theme) -> String {
+    text
+}
+
+/// Colors a `git status` code as git does: changes in the index green,
+/// changes in the work tree and untracked entries red, ignored ones dimmed.
+#[cfg(feature = "color")]
+pub fn colorize_git_status(status: &str, theme: &Theme) -> String {
+    if status == "!!" {
+        return paint(&theme.git_ignored, status);
+    }
+    let paint_code = |code: &str, style: &ThemeStyle| match code {
+        " " => code.to_string(),
+        _ => paint(style, code),
+    };
+    let (index, work_tree) = status.split_at(1);
+    let index_style = if index == "?" {
+        &theme.git_changed
+    } else {
+        &theme.git_staged
+    };
+    format!(
+        "{}{}",
+        paint_code(index, index_style),
+        paint_code(work_tree, &theme.git_changed)
+    )
+}
+
+#[cfg(not(feature = "color"))]
+pub fn colorize_git_status(status: &str, _theme: &Theme) -> String {
+    status.to_string()
+}
+
+/// Colors a symlink's target as what it resolves to: a directory or a file
+/// going by its extension, or as a broken link.
+#[cfg(feature = "color")]
+pub fn colorize_link_target(
+    link: &EntryInfo,
+    target: &Path,
+    text: String,
+    theme: &Theme,
+) -> String {
+    if link.broken_link {
+        let style = ThemeStyle {
+            bold: false,
+            ..theme.broken_link
+        };
+        return paint(&style, &text);
+    }
+    let kind = if link.is_dir {
+        EntryKind::Directory
+    } else {
+        EntryKind::File
+    };
+    colorize(&EntryInfo::new(target.to_path_buf(), kind), text, theme)
+}
+
+#[cfg(not(feature = "color"))]
+pub fn colorize_link_target(
+    _link: &EntryInfo,
+    _target: &Path,
+    text: String,
+    _theme: &Theme,
+) -> String {
+    text
+}
+
+/// Draws `text` in a theme's style.
+#[cfg(feature = "color")]
+fn paint(style: &ThemeStyle, text: &str) -> String {
+    let mut ansi = Style::new();
+    if let Some(colour) = style.foreground {
+        ansi = ansi.fg(match colour {
+            ThemeColour::Black => Colour::Black,
+            ThemeColour::Red => Colour::Red,
+            ThemeColour::Green => Colour::Green,
+            ThemeColour::Yellow => Colour::Yellow,
+            ThemeColour::Blue => Colour::Blue,
+            ThemeColour::Purple => Colour::Purple,
+            ThemeColour::Cyan => Colour::Cyan,
+            ThemeColour::White => Colour::White,
+            ThemeColour::Fixed(index) => Colour::Fixed(index),
+            ThemeColour::Rgb(red, green, blue) => Colour::RGB(red, green, blue),
+        });
+    }
+    if style.bold {
+        ansi = ansi.bold();
+    }
+    if style.dimmed {
+        ansi = ansi.dimmed();
+    }
+    if style.italic {
+        ansi = ansi.italic();
+    }
+    if style.underline {
+        ansi = ansi.underline();
+    }
+    if ansi.is_plain() {
+        text.to_string()
+    } else {
+        ansi.paint(text).to_string()
+    }
+}
+
+/// The number of characters in a `Column::Bar` bar.
+const BAR_WIDTH: usize = 10;
+
+/// The columns making up each entry's line, in order: the `columns`
+/// selection if given, otherwise every column whose option is set.
+pub fn active_columns(options: &TreeOptions) -> Vec<Column> {
+    if let Some(columns) = &options.columns {
+        let mut columns = columns.clone();
+        if !columns.contains(&Column::Name) {
+            columns.push(Column::Name);
+        }
+        return columns;
+    }
+    [
+        (Column::Inode, options.show_inodes),
+        (Column::Device, options.show_device),
+        (Column::Permissions, options.show_permissions),
+        (Column::Attributes, options.show_attributes),
+        (Column::Group, options.show_group),
+        (Column::Date, options.print_date),
+        (Column::GitStatus, options.git_status),
+        (Column::Name, true),
+        (Column::Target, true),
+        (
+            Column::Size,
+            options.print_size || options.human_readable || options.disk_usage,
+        ),
+        (Column::Bar, options.bars),
+        (Column::Annotations, true),
+    ]
+    .into_iter()
+    .filter(|(_, enabled)| *enabled)
+    .map(|(column, _)| column)
+    .collect()
+}
+
+/// Formats each entry's line after its tree prefix, for `formatter`.
+pub trait EntryFormatter: Send + Sync {
+    /// Formats `node`'s line after its tree prefix. `dir_sizes` holds the
+    /// total size of each directory when `disk_usage` or `describe` is set.
+    fn format_entry(
+        &self,
+        node: &TreeNode,
+        options: &TreeOptions,
+        dir_sizes: &HashMap<PathBuf, u64>,
+    ) -> String;
+}
+
+/// The built-in line format of `format_entry_line`, with the columns
+/// `options` selects, for formatters that build on it.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct DefaultFormatter;
+
+impl EntryFormatter for DefaultFormatter {
+    fn format_entry(
+        &self,
+        node: &TreeNode,
+        options: &TreeOptions,
+        dir_sizes: &HashMap<PathBuf, u64>,
+    ) -> String {
+        format_entry_line(node, &active_columns(options), options, dir_sizes)
+    }
+}
+
+/// Formats an entry's line after its tree prefix, e.g.
+/// `[ 131074 drwxr-xr-x staff   ]  src (4.0 KB)` for `--inodes -p -g --du -h`.
+/// Columns before the name are bracketed ahead of it and the rest follow it.
+/// Columns without a value for the entry, including those unavailable on the
+/// platform, are left out; values the entry's source does not know show as
+/// `?`. Directory sizes are looked up in `dir_sizes`.
+pub fn format_entry_line(
+    node: &TreeNode,
+    columns: &[Column],
+    options: &TreeOptions,
+    dir_sizes: &HashMap<PathBuf, u64>,
+) -> String {
+    let name_index = columns
+        .iter()
+        .position(|column| *column == Column::Name)
+        .unwrap_or(columns.len());
+    let before: Vec<String> = columns[..name_index]
+        .iter()
+        .filter_map(|column| {
+            let cell = cell(*column, node, options, dir_sizes)?;
+            // Annotations carry their own spacing for use after the name
+            Some(match column {
+                Column::Annotations => cell.trim_start().to_string(),
+                _ => cell,
+            })
+        })
+        .collect();
+
+    let mut line = if before.is_empty() {
+        String::new()
+    } else {
+        format!("[{}]  ", before.join(" "))
+    };
+    line.push_str(&entry_name(node, options));
+    for column in columns.iter().skip(name_index + 1) {
+        if let Some(cell) = cell(*column, node, options, dir_sizes) {
+            match column {
+                Column::Target => line.push_str(&format!(" -> {}", cell)),
+                Column::Size => line.push_str(&format!(" ({})", cell)),
+                Column::Annotations => line.push_str(&cell),
+                _ => line.push_str(&format!(" [{}]", cell)),
+            }
         }
+    }
+    line
+}
+
+/// The entry's name as listed: its full path with `full_path`, made safe
+/// with `replace_nonprintable`, colored and linked as requested.
+fn entry_name(node: &TreeNode, options: &TreeOptions) -> String {
+    let entry = &node.entry;
+    let mut name = if options.full_path {
+        entry.path.display().to_string()
     } else {
-        text
+        node.name.clone()
+    };
+    if options.replace_nonprintable {
+        name = replace_nonprintable(&name);
+    }
+    if options.quote_names {
+        name = quote(&name);
+    }
+    if options.color {
+        name = colorize(entry, name, &options.theme);
+    }
+    if options.term_integration && entry.is_dir {
+        name = hyperlink(&entry.path, &name);
+    }
+    if options.classify {
+        name.extend(classify(entry));
+    }
+    if options.emoji {
+        name = format!("{} {}", emoji(entry), name);
+    }
+    name
+}
+
+/// The marker `emoji` puts before an entry's name.
+fn emoji(entry: &EntryInfo) -> &'static str {
+    match entry.kind {
+        EntryKind::Directory => "📁",
+        EntryKind::Symlink => "🔗",
+        _ if entry.executable => "⚙️",
+        _ => "📄",
+    }
+}
+
+/// The `ls -F` suffix marking what kind of entry `entry` is: `/` for
+/// directories, `@` for symlinks, `=` for sockets, `|` for FIFOs, `>` for
+/// doors and `*` for executables.
+fn classify(entry: &EntryInfo) -> Option<char> {
+    match entry.kind {
+        EntryKind::Directory => return Some('/'),
+        EntryKind::Symlink => return Some('@'),
+        _ => {}
+    }
+    // Sources other than the filesystem may know the type bits of the mode
+    match entry.mode.map(|mode| mode & 0o170000) {
+        Some(0o140000) => Some('='),
+        Some(0o010000) => Some('|'),
+        Some(0o150000) => Some('>'),
+        _ => entry.executable.then_some('*'),
+    }
+}
+
+/// Wraps a name in double quotes, escaping `"` and `\` as GNU tree's `-Q`
+/// does.
+fn quote(name: &str) -> String {
+    format!("\"{}\"", name.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// The value of one column for an entry, or `None` if it has none.
+fn cell(
+    column: Column,
+    node: &TreeNode,
+    options: &TreeOptions,
+    dir_sizes: &HashMap<PathBuf, u64>,
+) -> Option<String> {
+    let entry = &node.entry;
+    let sanitize = |text: String| {
+        if options.replace_nonprintable {
+            replace_nonprintable(&text)
+        } else {
+            text
+        }
+    };
+    match column {
+        #[cfg(unix)]
+        Column::Inode => Some(format!("{:>7}", known(entry.inode))),
+        #[cfg(unix)]
+        Column::Device => Some(format!("{:>4}", known(entry.device))),
+        #[cfg(unix)]
+        Column::Group => {
+            let group = entry.gid.map(group_name).unwrap_or_else(|| "?".to_string());
+            Some(format!("{:<8}", group))
+        }
+        #[cfg(not(unix))]
+        Column::Inode | Column::Device | Column::Group => None,
+        Column::Permissions => Some(permissions(entry, options.permission_mode)),
+        #[cfg(windows)]
+        Column::Attributes => Some(attributes(entry)),
+        #[cfg(not(windows))]
+        Column::Attributes => None,
+        Column::Date => {
+            let time = if options.sort == SortKey::ChangeTime {
+                entry.changed
+            } else {
+                entry.modified
+            };
+            let format = options
+                .time_format
+                .as_deref()
+                .unwrap_or(DEFAULT_TIME_FORMAT);
+            Some(match time {
+                // Padded to the width of the longest, e.g. `59 minutes ago`
+                Some(time) if options.relative_date => {
+                    format!("{:>14}", format_age(time, SystemTime::now()))
+                }
+                Some(time) => format_time(time, format),
+                None => "?".to_string(),
+            })
+        }
+        Column::GitStatus => {
+            let status = node.git_status.as_deref().unwrap_or("  ");
+            Some(if options.color {
+                colorize_git_status(status, &options.theme)
+            } else {
+                status.to_string()
+            })
+        }
+        Column::Name => Some(entry_name(node, options)),
+        Column::Target => match (&entry.kind, &entry.link_target) {
+            (EntryKind::Symlink, Some(target)) => {
+                let mut text = sanitize(target.display().to_string());
+                if options.quote_names {
+                    text = quote(&text);
+                }
+                Some(if options.color {
+                    colorize_link_target(entry, target, text, &options.theme)
+                } else {
+                    text
+                })
+            }
+            _ => None,
+        },
+        Column::Size => {
+            let size = if node.is_listed_as_dir(options) {
+                // A directory's own size says little, so only totals are shown
+                options
+                    .disk_usage
+                    .then(|| dir_sizes.get(&entry.path).copied().unwrap_or(0))
+            } else {
+                entry.size
+            };
+            size.map(|size| {
+                if options.human_readable {
+                    options.human_size(size)
+                } else {
+                    format!("{:5}B", size)
+                }
+            })
+        }
+        Column::Bar => {
+            let size = if node.is_listed_as_dir(options) {
+                dir_sizes.get(&entry.path).copied()
+            } else {
+                entry.size
+            }?;
+            let total = *dir_sizes.get(entry.path.parent()?)?;
+            let share = if total == 0 {
+                0.0
+            } else {
+                size as f64 / total as f64
+            };
+            let filled = (share * BAR_WIDTH as f64).round() as usize;
+            Some(format!(
+                "{}{} {:>3}%",
+                "#".repeat(filled),
+                ".".repeat(BAR_WIDTH - filled),
+                (share * 100.0).round()
+            ))
+        }
+        Column::Annotations => {
+            let mut annotations = String::new();
+            if let Some((files, dirs)) = node.counts {
+                annotations.push_str(&format!(
+                    " ({} file{}, {} dir{})",
+                    files,
+                    if files == 1 { "" } else { "s" },
+                    dirs,
+                    if dirs == 1 { "" } else { "s" }
+                ));
+            }
+            if let Some(count) = node.not_opened {
+                annotations.push_str(&format!(
+                    " [{} entries exceeds filelimit, not opening dir]",
+                    count
+                ));
+            }
+            if node.recursive {
+                annotations.push_str("  [recursive, not followed]");
+            }
+            if options.classify && entry.broken_link {
+                annotations.push_str("  [broken]");
+            }
+            if let Some(description) = &node.description {
+                annotations.push_str(&format!("  # {}", sanitize(description.clone())));
+            }
+            (!annotations.is_empty()).then_some(annotations)
+        }
+    }
+}
+
+/// Formats Windows attributes as `attrib` letters, with `-` for those not
+/// set: `R` read-only, `H` hidden, `S` system, `A` archive.
+#[cfg(windows)]
+fn attributes(entry: &EntryInfo) -> String {
+    const ATTRIBUTES: [(u32, char); 4] = [
+        (0x1, 'R'),  // FILE_ATTRIBUTE_READONLY
+        (0x2, 'H'),  // FILE_ATTRIBUTE_HIDDEN
+        (0x4, 'S'),  // FILE_ATTRIBUTE_SYSTEM
+        (0x20, 'A'), // FILE_ATTRIBUTE_ARCHIVE
+    ];
+    let Some(bits) = entry.attributes else {
+        return "????".to_string();
+    };
+    ATTRIBUTES
+        .iter()
+        .map(|&(flag, letter)| if bits & flag != 0 { letter } else { '-' })
+        .collect()
+}
+
+#[cfg(unix)]
+fn known(value: Option<u64>) -> String {
+    value
+        .map(|value| value.to_string())
+        .unwrap_or_else(|| "?".to_string())
+}
+
+/// Formats an entry's mode for `show_permissions`, or `?` if unknown.
+fn permissions(entry: &EntryInfo, mode: PermissionMode) -> String {
+    let Some(bits) = entry.mode else {
+        return "?".to_string();
+    };
+    let octal = format!("{:04o}", bits & 0o7777);
+    if mode == PermissionMode::Octal {
+        return octal;
+    }
+    let file_type = match bits & 0o170000 {
+        0o040000 => 'd',
+        0o120000 => 'l',
+        0o140000 => 's',
+        0o010000 => 'p',
+        0o020000 => 'c',
+        0o060000 => 'b',
+        0o100000 => '-',
+        // No type bits, e.g. a mode from a listing
+        _ => match entry.kind {
+            EntryKind::Directory => 'd',
+            EntryKind::Symlink => 'l',
+            _ => '-',
+        },
+    };
+    let mut symbolic = String::from(file_type);
+    // Owner, group and other triplets, with the setuid, setgid and sticky bit
+    // shown in place of their execute bit
+    for (shift, special, special_char) in [(6, 0o4000, 's'), (3, 0o2000, 's'), (0, 0o1000, 't')] {
+        let triplet = bits >> shift;
+        symbolic.push(if triplet & 0o4 != 0 { 'r' } else { '-' });
+        symbolic.push(if triplet & 0o2 != 0 { 'w' } else { '-' });
+        symbolic.push(match (bits & special != 0, triplet & 0o1 != 0) {
+            (true, true) => special_char,
+            (true, false) => special_char.to_ascii_uppercase(),
+            (false, true) => 'x',
+            (false, false) => '-',
+        });
+    }
+    // Marks extra access rules, as `ls -l` does
+    if entry.has_acl {
+        symbolic.push('+');
+    }
+    match mode {
+        PermissionMode::Both => format!("{} {}", symbolic, octal),
+        _ => symbolic,
+    }
+}
+
+/// Returns the section an entry belongs to for `--group-by`, as a sort rank
+/// and the header label.
+pub fn group_of(entry: &EntryInfo, group_by: GroupBy) -> (u8, String) {
+    if entry.is_dir {
+        return (0, "Directories".to_string());
+    }
+    let extension = entry
+        .path
+        .extension()
+        .map(|extension| extension.to_string_lossy().to_lowercase());
+    match group_by {
+        GroupBy::Extension => match extension {
+            Some(extension) => (1, format!(".{}", extension)),
+            None => (2, "No extension".to_string()),
+        },
+        GroupBy::Type => match extension.as_deref().unwrap_or("") {
+            "rs" | "c" | "h" | "cc" | "cpp" | "hpp" | "cs" | "go" | "java" | "kt" | "py" | "rb"
+            | "js" | "jsx" | "ts" | "tsx" | "php" | "swift" | "sh" | "ps1" | "lua" | "sql"
+            | "toml" | "yaml" | "yml" | "json" | "xml" | "css" | "scss" => (1, "Code".to_string()),
+            "md" | "markdown" | "txt" | "rst" | "adoc" | "pdf" | "doc" | "docx" | "odt" | "rtf"
+            | "html" | "htm" => (2, "Docs".to_string()),
+            "png" | "jpg" | "jpeg" | "gif" | "bmp" | "svg" | "webp" | "ico" | "mp3" | "wav"
+            | "flac" | "ogg" | "mp4" | "mov" | "mkv" | "avi" | "webm" => (3, "Media".to_string()),
+            _ => (4, "Other".to_string()),
+        },
+    }
+}
+
+/// Wraps `text` in an OSC 8 hyperlink to `path`, letting terminals offer
+/// open actions on it. Left unchanged if the path cannot be resolved.
+pub fn hyperlink(path: &Path, text: &str) -> String {
+    match file_url(path) {
+        Some(url) => format!("\x1b]8;;{}\x1b\\{}\x1b]8;;\x1b\\", url, text),
+        None => text.to_string(),
+    }
+}
+
+/// Shortens `line` to `width` terminal columns, ending it with `…` when
+/// anything is cut. Wide characters, such as CJK and most emoji, take two
+/// columns, and a width of 0 leaves no room even for the `…`. Terminal
+/// escape sequences take up no width and are kept whole, and colors and
+/// hyperlinks left open by the cut are closed.
+pub fn truncate_line(line: &str, width: usize) -> String {
+    // Measured as a whole, as emoji may be sequences of several characters
+    let mut shown = String::new();
+    let mut rest = line;
+    while let Some(c) = rest.chars().next() {
+        let length = escape_len(rest).unwrap_or_else(|| {
+            shown.push(c);
+            c.len_utf8()
+        });
+        rest = &rest[length..];
+    }
+    if shown.width() <= width {
+        return line.to_string();
+    }
+    if width == 0 {
+        return String::new();
+    }
+
+    let mut truncated = String::new();
+    let (mut styled, mut linked) = (false, false);
+    shown.clear();
+    let mut rest = line;
+    while let Some(c) = rest.chars().next() {
+        let length = match escape_len(rest) {
+            Some(length) => {
+                let sequence = &rest[..length];
+                if sequence.starts_with("\x1b]8;") {
+                    // Hyperlinks open with a URL and close without one
+                    linked = !sequence.starts_with("\x1b]8;;\x1b");
+                } else if sequence.starts_with("\x1b[") {
+                    styled = sequence != "\x1b[0m";
+                }
+                truncated.push_str(sequence);
+                length
+            }
+            None => {
+                // Leaving a column for the `…`
+                shown.push(c);
+                if shown.width() >= width {
+                    break;
+                }
+                truncated.push(c);
+                c.len_utf8()
+            }
+        };
+        rest = &rest[length..];
+    }
+    truncated.push('…');
+    if linked {
+        truncated.push_str("\x1b]8;;\x1b\\");
+    }
+    if styled {
+        truncated.push_str("\x1b[0m");
+    }
+    truncated
+}
+
+/// The length in bytes of the escape sequence `text` starts with, if any:
+/// CSI sequences such as colors, and OSC sequences such as hyperlinks.
+fn escape_len(text: &str) -> Option<usize> {
+    let body = text.strip_prefix('\x1b')?;
+    if let Some(parameters) = body.strip_prefix('[') {
+        let end = parameters.find(|c: char| ('@'..='~').contains(&c))?;
+        Some(2 + end + 1)
+    } else if let Some(command) = body.strip_prefix(']') {
+        // Ended by BEL or ST (`ESC \`)
+        let end = command.find(['\x07', '\x1b'])?;
+        let terminator = if command[end..].starts_with('\x07') {
+            1
+        } else {
+            2
+        };
+        Some(2 + end + terminator)
+    } else {
+        None
+    }
+}
+
+/// OSC 7 sequence reporting `path` as the working directory to the terminal.
+pub fn working_directory_marker(path: &Path) -> String {
+    match file_url(path) {
+        Some(url) => format!("\x1b]7;{}\x1b\\", url),
+        None => String::new(),
     }
 }
@@ -0,0 +1,384 @@
+use std::ffi::OsString;
+use std::fs;
+use std::io;
+#[cfg(unix)]
+use std::os::unix::fs::MetadataExt;
+#[cfg(windows)]
+use std::os::windows::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use crate::rust_tree::options::TreeOptions;
+#[cfg(not(unix))]
+use crate::rust_tree::utils::has_executable_extension;
+use crate::rust_tree::utils::{change_time, dir_id, has_acl, metadata_dir_id, DirId};
+
+/// What kind of filesystem object an entry is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum EntryKind {
+    File,
+    Directory,
+    Symlink,
+    /// Sockets, pipes, devices and the like.
+    Other,
+}
+
+impl EntryKind {
+    /// The kind of entry with the given file type.
+    pub fn of(file_type: fs::FileType) -> Self {
+        if file_type.is_dir() {
+            EntryKind::Directory
+        } else if file_type.is_symlink() {
+            EntryKind::Symlink
+        } else if file_type.is_file() {
+            EntryKind::File
+        } else {
+            EntryKind::Other
+        }
+    }
+}
+
+/// An entry to be listed, with whatever metadata its source knows about.
+/// Fields are `None` when the source cannot provide them, e.g. sizes in a
+/// plain `--fromfile` listing.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct EntryInfo {
+    pub path: PathBuf,
+    pub file_name: OsString,
+    pub kind: EntryKind,
+    /// Whether the entry is a directory or a symlink resolving to one.
+    pub is_dir: bool,
+    pub executable: bool,
+    pub link_target: Option<PathBuf>,
+    /// Whether the entry is a symlink to something that does not exist.
+    pub broken_link: bool,
+    pub size: Option<u64>,
+    pub modified: Option<SystemTime>,
+    pub changed: Option<SystemTime>,
+    /// Unix permission bits, with the file type bits if known.
+    pub mode: Option<u32>,
+    pub inode: Option<u64>,
+    pub device: Option<u64>,
+    pub gid: Option<u32>,
+    /// Windows file attributes, e.g. `FILE_ATTRIBUTE_HIDDEN`.
+    pub attributes: Option<u32>,
+    /// Whether the entry has an ACL granting more than its `mode`, only
+    /// looked up by `FilesystemAcls`.
+    pub has_acl: bool,
+    /// The identity of the directory the entry is or links to, when known
+    /// from the metadata read with it, so that detecting symlink loops
+    /// needs no further lookup.
+    pub dir_id: Option<DirId>,
+}
+
+impl EntryInfo {
+    /// An entry with only its path and kind known.
+    pub fn new(path: PathBuf, kind: EntryKind) -> Self {
+        EntryInfo {
+            file_name: path.file_name().unwrap_or(path.as_os_str()).to_owned(),
+            path,
+            kind,
+            is_dir: kind == EntryKind::Directory,
+            executable: false,
+            link_target: None,
+            broken_link: false,
+            size: None,
+            modified: None,
+            changed: None,
+            mode: None,
+            inode: None,
+            device: None,
+            gid: None,
+            attributes: None,
+            has_acl: false,
+            dir_id: None,
+        }
+    }
+
+    /// Whether the entry is listed as a directory: a directory, or with
+    /// `follow_links`, a symlink to one.
+    pub fn is_listed_as_dir(&self, options: &TreeOptions) -> bool {
+        self.kind == EntryKind::Directory
+            || (options.follow_links && self.kind == EntryKind::Symlink && self.is_dir)
+    }
+
+    /// Reads an entry's metadata without following symlinks.
+    pub fn from_dir_entry(entry: &fs::DirEntry) -> io::Result<Self> {
+        let mut info = EntryInfo::from_file_type(entry)?;
+        // The entry may have vanished since the directory was read
+        if let Ok(metadata) = entry.metadata() {
+            info.set_metadata(&metadata);
+        }
+        Ok(info)
+    }
+
+    /// Reads an entry's kind, and where a symlink points, leaving the rest
+    /// of its metadata unknown. Where reading the directory gave the kind,
+    /// as with `d_type` on Linux and macOS, only symlinks are looked up.
+    pub fn from_file_type(entry: &fs::DirEntry) -> io::Result<Self> {
+        let kind = EntryKind::of(entry.file_type()?);
+        let mut info = EntryInfo::new(entry.path(), kind);
+        info.file_name = entry.file_name();
+        if kind == EntryKind::Symlink {
+            info.set_target(fs::metadata(&info.path));
+            info.link_target = fs::read_link(&info.path).ok();
+        }
+        Ok(info)
+    }
+
+    /// Builds the entry at `path` from its metadata. For symlinks, whether
+    /// they point to a directory and where they point are left unknown.
+    pub fn from_metadata(path: PathBuf, metadata: &fs::Metadata) -> Self {
+        let mut info = EntryInfo::new(path, EntryKind::of(metadata.file_type()));
+        info.set_metadata(metadata);
+        info
+    }
+
+    /// Reads the entry at `path`, following symlinks.
+    pub fn from_path(path: &Path) -> io::Result<Self> {
+        Ok(EntryInfo::from_metadata(
+            path.to_path_buf(),
+            &fs::metadata(path)?,
+        ))
+    }
+
+    /// Looks up whether the entry has an ACL. Symlinks have none of their
+    /// own.
+    pub fn read_acl(&mut self) {
+        self.has_acl = self.kind != EntryKind::Symlink
+            && has_acl(&self.path, self.kind == EntryKind::Directory);
+    }
+
+    /// Records what a symlink resolves to from the metadata of its target.
+    pub(crate) fn set_target(&mut self, target: io::Result<fs::Metadata>) {
+        match target {
+            Ok(target) => {
+                self.is_dir = target.is_dir();
+                self.dir_id = metadata_dir_id(&target);
+            }
+            Err(_) => self.broken_link = true,
+        }
+    }
+
+    fn set_metadata(&mut self, metadata: &fs::Metadata) {
+        // A symlink's own metadata leaves its target's identity in place
+        if let Some(id) = metadata_dir_id(metadata) {
+            self.dir_id = Some(id);
+        }
+        self.size = Some(metadata.len());
+        self.modified = metadata.modified().ok();
+        self.changed = change_time(metadata);
+        #[cfg(unix)]
+        {
+            self.mode = Some(metadata.mode());
+            self.inode = Some(metadata.ino());
+            self.device = Some(metadata.dev());
+            self.gid = Some(metadata.gid());
+            self.executable = self.kind == EntryKind::File && metadata.mode() & 0o111 != 0;
+        }
+        #[cfg(windows)]
+        {
+            self.attributes = Some(metadata.file_attributes());
+        }
+        #[cfg(not(unix))]
+        {
+            self.executable = self.kind == EntryKind::File && has_executable_extension(&self.path);
+        }
+    }
+}
+
+/// A listed entry and the entries listed below it.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TreeNode {
+    /// The entry's name as listed, e.g. `main.rs`.
+    pub name: String,
+    /// The entry's kind, size, mtime and other metadata.
+    pub entry: EntryInfo,
+    /// Filtered and sorted as listed. Empty for files, and for directories
+    /// below the `level` limit or reached through a symlink loop.
+    pub children: Vec<TreeNode>,
+    /// The number of entries in a directory that was not opened because it
+    /// has more than `file_limit`.
+    pub not_opened: Option<usize>,
+    /// Whether the entry is a directory the listing is already inside,
+    /// reached again through a symlink. Its contents are not listed, as
+    /// following it would loop forever.
+    pub recursive: bool,
+    /// How many more entries the directory has than `children`, which
+    /// `auto_limit` left out.
+    pub omitted: usize,
+    /// The entry's comment from its directory's `.info` file, with
+    /// `print_info`.
+    pub comments: Vec<String>,
+    /// The directory's `.dirinfo` or `README.md` description, with
+    /// `dir_descriptions`.
+    pub description: Option<String>,
+    /// The entry's `git status` code, with `git_status`, or `None` if it is
+    /// unchanged.
+    pub git_status: Option<String>,
+    /// The numbers of files and directories listed anywhere below the
+    /// directory, with `dir_counts`. `None` where its contents are not
+    /// listed.
+    pub counts: Option<(u64, u64)>,
+}
+
+impl TreeNode {
+    pub fn new(entry: EntryInfo) -> Self {
+        TreeNode {
+            name: entry.file_name.to_string_lossy().to_string(),
+            entry,
+            children: Vec::new(),
+            not_opened: None,
+            recursive: false,
+            omitted: 0,
+            comments: Vec::new(),
+            description: None,
+            git_status: None,
+            counts: None,
+        }
+    }
+
+    /// Whether the entry is listed as a directory, as
+    /// `EntryInfo::is_listed_as_dir`.
+    pub fn is_listed_as_dir(&self, options: &TreeOptions) -> bool {
+        self.entry.is_listed_as_dir(options)
+    }
+}
+
+impl Drop for TreeNode {
+    /// Drops the nodes below one at a time, as dropping them recursively
+    /// could overflow the stack on very deep trees.
+    fn drop(&mut self) {
+        let mut below = std::mem::take(&mut self.children);
+        while let Some(mut node) = below.pop() {
+            below.append(&mut node.children);
+        }
+    }
+}
+
+/// The entries of a directory as they are read. Listings stop reading at
+/// the first error.
+pub type EntryStream<'a> = Box<dyn Iterator<Item = io::Result<EntryInfo>> + 'a>;
+
+/// Where a listing's entries come from. Shared between threads when
+/// totalling sizes for `disk_usage`.
+pub trait EntrySource: Sync {
+    /// Returns the entries directly inside `dir`, unfiltered and unsorted.
+    fn read_entries(&self, dir: &Path) -> io::Result<Vec<EntryInfo>>;
+
+    /// Returns the entries directly inside `dir` as `read_entries` does,
+    /// but one at a time as they are read, for listings printed as they
+    /// are read. Sources that read directories whole return them from
+    /// `read_entries`.
+    fn stream_entries(&self, dir: &Path) -> io::Result<EntryStream<'_>> {
+        Ok(Box::new(self.read_entries(dir)?.into_iter().map(Ok)))
+    }
+
+    /// Identifies the directory at `path`, to detect symlink loops. Sources
+    /// without links return `None`.
+    fn dir_id(&self, path: &Path) -> Option<DirId>;
+
+    /// Identifies the directory `entry` is or links to, from the metadata
+    /// read with it where possible.
+    fn entry_dir_id(&self, entry: &EntryInfo) -> Option<DirId> {
+        entry
+            .dir_id
+            .as_ref()
+            .cloned()
+            .or_else(|| self.dir_id(&entry.path))
+    }
+
+    /// Returns the entry at `path` itself, used for the root of a listing.
+    fn entry(&self, path: &Path) -> io::Result<EntryInfo>;
+
+    /// Reads a file such as a directory's `.info` comments.
+    fn read_to_string(&self, path: &Path) -> io::Result<String> {
+        fs::read_to_string(path)
+    }
+}
+
+/// Reads entries from the filesystem.
+pub struct Filesystem;
+
+impl EntrySource for Filesystem {
+    fn read_entries(&self, dir: &Path) -> io::Result<Vec<EntryInfo>> {
+        fs::read_dir(dir)?
+            .map(|entry| EntryInfo::from_dir_entry(&entry?))
+            .collect()
+    }
+
+    fn stream_entries(&self, dir: &Path) -> io::Result<EntryStream<'_>> {
+        let entries = fs::read_dir(dir)?.map(|entry| EntryInfo::from_dir_entry(&entry?));
+        Ok(Box::new(entries))
+    }
+
+    fn dir_id(&self, path: &Path) -> Option<DirId> {
+        dir_id(path)
+    }
+
+    fn entry(&self, path: &Path) -> io::Result<EntryInfo> {
+        EntryInfo::from_path(path)
+    }
+}
+
+/// Reads entries from the filesystem as `Filesystem` does, also looking up
+/// whether each has an ACL, for listings that show permissions. That takes
+/// a system call or two per entry, which other listings are spared.
+pub struct FilesystemAcls;
+
+impl EntrySource for FilesystemAcls {
+    fn read_entries(&self, dir: &Path) -> io::Result<Vec<EntryInfo>> {
+        let mut entries = Filesystem.read_entries(dir)?;
+        entries.iter_mut().for_each(EntryInfo::read_acl);
+        Ok(entries)
+    }
+
+    fn stream_entries(&self, dir: &Path) -> io::Result<EntryStream<'_>> {
+        let entries = Filesystem.stream_entries(dir)?.map(|entry| {
+            let mut entry = entry?;
+            entry.read_acl();
+            Ok(entry)
+        });
+        Ok(Box::new(entries))
+    }
+
+    fn dir_id(&self, path: &Path) -> Option<DirId> {
+        dir_id(path)
+    }
+
+    fn entry(&self, path: &Path) -> io::Result<EntryInfo> {
+        let mut entry = EntryInfo::from_path(path)?;
+        entry.read_acl();
+        Ok(entry)
+    }
+}
+
+/// Reads entries from the filesystem as `Filesystem` does, but with only
+/// their kinds and symlink targets, for listings that show nothing else.
+/// Saves looking up each entry's metadata where reading a directory gives
+/// the kinds of its entries.
+pub struct FilesystemKinds;
+
+impl EntrySource for FilesystemKinds {
+    fn read_entries(&self, dir: &Path) -> io::Result<Vec<EntryInfo>> {
+        fs::read_dir(dir)?
+            .map(|entry| EntryInfo::from_file_type(&entry?))
+            .collect()
+    }
+
+    fn stream_entries(&self, dir: &Path) -> io::Result<EntryStream<'_>> {
+        let entries = fs::read_dir(dir)?.map(|entry| EntryInfo::from_file_type(&entry?));
+        Ok(Box::new(entries))
+    }
+
+    fn dir_id(&self, path: &Path) -> Option<DirId> {
+        dir_id(path)
+    }
+
+    fn entry(&self, path: &Path) -> io::Result<EntryInfo> {
+        EntryInfo::from_path(path)
+    }
+}
@@ -0,0 +1,346 @@
+//! Sorting the entries of directories too large to sort in memory, for
+//! `external_sort`: in runs written to temporary files, then merged.
+
+use std::cmp::Ordering;
+use std::env;
+use std::ffi::OsString;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering as AtomicOrdering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::rust_tree::entry::{EntryInfo, EntryKind};
+use crate::rust_tree::utils::DirId;
+
+/// The most runs merged at once. Beyond that, runs are first merged into
+/// longer ones, so that the number of open files stays bounded.
+const MERGE_WIDTH: usize = 64;
+
+/// Numbers the temporary files of this process.
+static RUN_FILES: AtomicUsize = AtomicUsize::new(0);
+
+/// How the entries of a directory are ordered.
+pub(crate) type EntryOrder<'a> = dyn Fn(&EntryInfo, &EntryInfo) -> Ordering + 'a;
+
+/// A directory's entries in the order listed.
+pub(crate) enum SortedEntries<'a> {
+    /// Held in memory, already in order.
+    Memory(std::vec::IntoIter<EntryInfo>),
+    /// Merged from runs on disk as they are read.
+    Merged {
+        runs: Vec<Run>,
+        order: &'a EntryOrder<'a>,
+    },
+}
+
+impl<'a> SortedEntries<'a> {
+    /// Sorts `entries` by `order`, in memory if there are at most `chunk`
+    /// of them, and otherwise in runs of `chunk` entries written to
+    /// temporary files. Equal entries keep the order they came in.
+    pub(crate) fn sort(
+        mut entries: impl Iterator<Item = EntryInfo>,
+        chunk: usize,
+        order: &'a EntryOrder<'a>,
+    ) -> io::Result<Self> {
+        let chunk = chunk.max(1);
+        let mut runs = Vec::new();
+        loop {
+            let mut sorted: Vec<EntryInfo> = entries.by_ref().take(chunk).collect();
+            sorted.sort_by(|a, b| order(a, b));
+            if runs.is_empty() && sorted.len() < chunk {
+                return Ok(SortedEntries::Memory(sorted.into_iter()));
+            }
+            if sorted.is_empty() {
+                break;
+            }
+            let full = sorted.len() == chunk;
+            runs.push(Run::write(sorted)?);
+            if !full {
+                break;
+            }
+        }
+        // Merging the first runs keeps those of equal entries in order
+        while runs.len() > MERGE_WIDTH {
+            let rest = runs.split_off(MERGE_WIDTH);
+            let merged = Run::write_from(merge(runs, order))?;
+            runs = vec![merged];
+            runs.extend(rest);
+        }
+        Ok(SortedEntries::Merged { runs, order })
+    }
+}
+
+impl Iterator for SortedEntries<'_> {
+    type Item = io::Result<EntryInfo>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            SortedEntries::Memory(entries) => entries.next().map(Ok),
+            SortedEntries::Merged { runs, order } => next_merged(runs, *order).transpose(),
+        }
+    }
+}
+
+/// The entries of `runs`, merged in `order`.
+fn merge<'a>(
+    mut runs: Vec<Run>,
+    order: &'a EntryOrder<'a>,
+) -> impl Iterator<Item = io::Result<EntryInfo>> + 'a {
+    std::iter::from_fn(move || next_merged(&mut runs, order).transpose())
+}
+
+/// Takes the first entry in `order` of those at the head of `runs`, the
+/// earliest run's on ties.
+fn next_merged(runs: &mut [Run], order: &EntryOrder) -> io::Result<Option<EntryInfo>> {
+    let mut first: Option<usize> = None;
+    for index in 0..runs.len() {
+        let Some(head) = runs[index].head.as_ref() else {
+            continue;
+        };
+        let earlier = first.and_then(|first| runs[first].head.as_ref());
+        if earlier.is_none_or(|earlier| order(head, earlier) == Ordering::Less) {
+            first = Some(index);
+        }
+    }
+    match first {
+        Some(first) => runs[first].advance(),
+        None => Ok(None),
+    }
+}
+
+/// A sorted run of entries in a temporary file, read back one at a time.
+pub(crate) struct Run {
+    reader: BufReader<File>,
+    path: PathBuf,
+    /// The run's next entry, `None` once it has all been read.
+    head: Option<EntryInfo>,
+}
+
+impl Run {
+    /// Writes `entries`, already sorted, to a new temporary file.
+    fn write(entries: Vec<EntryInfo>) -> io::Result<Self> {
+        Run::write_from(entries.into_iter().map(Ok))
+    }
+
+    fn write_from(entries: impl Iterator<Item = io::Result<EntryInfo>>) -> io::Result<Self> {
+        let path = env::temp_dir().join(format!(
+            "rust_tree-{}-{}.run",
+            process::id(),
+            RUN_FILES.fetch_add(1, AtomicOrdering::Relaxed)
+        ));
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(&path)?;
+        // Removed along with the run, including on errors from here on
+        let mut run = Run {
+            reader: BufReader::new(file.try_clone()?),
+            path,
+            head: None,
+        };
+        let mut writer = BufWriter::new(file);
+        for entry in entries {
+            write_entry(&mut writer, &entry?)?;
+        }
+        writer.flush()?;
+        run.reader.seek(SeekFrom::Start(0))?;
+        run.head = read_entry(&mut run.reader)?;
+        Ok(run)
+    }
+
+    /// Returns the head of the run, reading the entry after it.
+    fn advance(&mut self) -> io::Result<Option<EntryInfo>> {
+        let next = read_entry(&mut self.reader)?;
+        Ok(std::mem::replace(&mut self.head, next))
+    }
+}
+
+impl Drop for Run {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+fn write_entry(out: &mut impl Write, entry: &EntryInfo) -> io::Result<()> {
+    write_bytes(out, entry.path.as_os_str().as_encoded_bytes())?;
+    write_bytes(out, entry.file_name.as_encoded_bytes())?;
+    let kind = match entry.kind {
+        EntryKind::File => 0,
+        EntryKind::Directory => 1,
+        EntryKind::Symlink => 2,
+        EntryKind::Other => 3,
+    };
+    let flags = [
+        entry.is_dir,
+        entry.executable,
+        entry.broken_link,
+        entry.has_acl,
+    ]
+    .iter()
+    .enumerate()
+    .fold(0u8, |flags, (bit, &set)| flags | (u8::from(set) << bit));
+    out.write_all(&[kind, flags])?;
+    match &entry.link_target {
+        Some(target) => {
+            out.write_all(&[1])?;
+            write_bytes(out, target.as_os_str().as_encoded_bytes())?;
+        }
+        None => out.write_all(&[0])?,
+    }
+    write_option(out, entry.size)?;
+    write_time(out, entry.modified)?;
+    write_time(out, entry.changed)?;
+    write_option(out, entry.mode.map(u64::from))?;
+    write_option(out, entry.inode)?;
+    write_option(out, entry.device)?;
+    write_option(out, entry.gid.map(u64::from))?;
+    write_option(out, entry.attributes.map(u64::from))?;
+    write_dir_id(out, entry.dir_id.as_ref())
+}
+
+/// Reads an entry written by `write_entry`, or `None` at the end of the
+/// file.
+fn read_entry(input: &mut impl BufRead) -> io::Result<Option<EntryInfo>> {
+    if input.fill_buf()?.is_empty() {
+        return Ok(None);
+    }
+    let path = PathBuf::from(read_os_string(input)?);
+    let file_name = read_os_string(input)?;
+    let [kind, flags] = read_array(input)?;
+    let kind = match kind {
+        0 => EntryKind::File,
+        1 => EntryKind::Directory,
+        2 => EntryKind::Symlink,
+        _ => EntryKind::Other,
+    };
+    let mut entry = EntryInfo::new(path, kind);
+    entry.file_name = file_name;
+    let flag = |bit: u8| flags & (1 << bit) != 0;
+    (
+        entry.is_dir,
+        entry.executable,
+        entry.broken_link,
+        entry.has_acl,
+    ) = (flag(0), flag(1), flag(2), flag(3));
+    if read_array::<1>(input)? == [1] {
+        entry.link_target = Some(PathBuf::from(read_os_string(input)?));
+    }
+    entry.size = read_option(input)?;
+    entry.modified = read_time(input)?;
+    entry.changed = read_time(input)?;
+    entry.mode = read_option(input)?.map(|mode| mode as u32);
+    entry.inode = read_option(input)?;
+    entry.device = read_option(input)?;
+    entry.gid = read_option(input)?.map(|gid| gid as u32);
+    entry.attributes = read_option(input)?.map(|attributes| attributes as u32);
+    entry.dir_id = read_dir_id(input)?;
+    Ok(Some(entry))
+}
+
+fn write_bytes(out: &mut impl Write, bytes: &[u8]) -> io::Result<()> {
+    out.write_all(&(bytes.len() as u64).to_le_bytes())?;
+    out.write_all(bytes)
+}
+
+fn read_os_string(input: &mut impl Read) -> io::Result<OsString> {
+    let len = u64::from_le_bytes(read_array(input)?) as usize;
+    let mut bytes = vec![0; len];
+    input.read_exact(&mut bytes)?;
+    // SAFETY: the bytes were written by this process, from
+    // `as_encoded_bytes`.
+    Ok(unsafe { OsString::from_encoded_bytes_unchecked(bytes) })
+}
+
+fn write_option(out: &mut impl Write, value: Option<u64>) -> io::Result<()> {
+    match value {
+        Some(value) => {
+            out.write_all(&[1])?;
+            out.write_all(&value.to_le_bytes())
+        }
+        None => out.write_all(&[0]),
+    }
+}
+
+fn read_option(input: &mut impl Read) -> io::Result<Option<u64>> {
+    Ok(match read_array::<1>(input)? {
+        [1] => Some(u64::from_le_bytes(read_array(input)?)),
+        _ => None,
+    })
+}
+
+/// Writes a time as its distance from the epoch, with a sign.
+fn write_time(out: &mut impl Write, time: Option<SystemTime>) -> io::Result<()> {
+    let (sign, distance) = match time.map(|time| time.duration_since(UNIX_EPOCH)) {
+        Some(Ok(after)) => (1, after),
+        Some(Err(before)) => (2, before.duration()),
+        None => return out.write_all(&[0]),
+    };
+    out.write_all(&[sign])?;
+    out.write_all(&distance.as_secs().to_le_bytes())?;
+    out.write_all(&distance.subsec_nanos().to_le_bytes())
+}
+
+fn read_time(input: &mut impl Read) -> io::Result<Option<SystemTime>> {
+    let [sign] = read_array(input)?;
+    if sign == 0 {
+        return Ok(None);
+    }
+    let secs = u64::from_le_bytes(read_array(input)?);
+    let nanos = u32::from_le_bytes(read_array(input)?);
+    let distance = Duration::new(secs, nanos);
+    Ok(Some(if sign == 1 {
+        UNIX_EPOCH + distance
+    } else {
+        UNIX_EPOCH - distance
+    }))
+}
+
+#[cfg(unix)]
+fn write_dir_id(out: &mut impl Write, id: Option<&DirId>) -> io::Result<()> {
+    match id {
+        Some(&(device, inode)) => {
+            out.write_all(&[1])?;
+            out.write_all(&device.to_le_bytes())?;
+            out.write_all(&inode.to_le_bytes())
+        }
+        None => out.write_all(&[0]),
+    }
+}
+
+#[cfg(unix)]
+fn read_dir_id(input: &mut impl Read) -> io::Result<Option<DirId>> {
+    if read_array::<1>(input)? != [1] {
+        return Ok(None);
+    }
+    let device = u64::from_le_bytes(read_array(input)?);
+    let inode = u64::from_le_bytes(read_array(input)?);
+    Ok(Some((device, inode)))
+}
+
+#[cfg(not(unix))]
+fn write_dir_id(out: &mut impl Write, id: Option<&DirId>) -> io::Result<()> {
+    match id {
+        Some(path) => {
+            out.write_all(&[1])?;
+            write_bytes(out, path.as_os_str().as_encoded_bytes())
+        }
+        None => out.write_all(&[0]),
+    }
+}
+
+#[cfg(not(unix))]
+fn read_dir_id(input: &mut impl Read) -> io::Result<Option<DirId>> {
+    if read_array::<1>(input)? != [1] {
+        return Ok(None);
+    }
+    Ok(Some(PathBuf::from(read_os_string(input)?)))
+}
+
+fn read_array<const N: usize>(input: &mut impl Read) -> io::Result<[u8; N]> {
+    let mut bytes = [0; N];
+    input.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
@@ -0,0 +1,138 @@
+use std::ffi::{c_char, c_void, CStr};
+use std::panic;
+use std::ptr;
+
+use crate::rust_tree::options::TreeOptions;
+use crate::rust_tree::traversal::list_directory_as_string;
+use crate::rust_tree::utils::parse_pattern_alternatives;
+
+extern "C" {
+    fn malloc(size: usize) -> *mut c_void;
+}
+
+/// Options for `rust_tree_render`, covering the most used of `TreeOptions`.
+#[repr(C)]
+pub struct RustTreeOptions {
+    /// List hidden entries (`-a`).
+    pub all_files: bool,
+    /// How many levels deep to list (`-L`), or 0 for no limit.
+    pub level: i32,
+    /// List directories only (`-d`).
+    pub dir_only: bool,
+    /// Print the full path of each entry (`-f`).
+    pub full_path: bool,
+    /// Print sizes (`-s`), in human readable units with `human_readable`
+    /// (`-h`).
+    pub print_size: bool,
+    pub human_readable: bool,
+    /// List directories before files (`--dirsfirst`).
+    pub dirs_first: bool,
+    /// Color names with ANSI escape sequences (`-C`).
+    pub color: bool,
+    /// Wild-card patterns of the files to list, separated by `|` (`-P`), or
+    /// NULL to list all.
+    pub pattern: *const c_char,
+    /// Wild-card patterns of the entries to leave out, separated by `|`
+    /// (`-I`), or NULL.
+    pub exclude: *const c_char,
+}
+
+/// Options with everything off and no level limit, as `tree` without flags.
+#[no_mangle]
+pub extern "C" fn rust_tree_default_options() -> RustTreeOptions {
+    RustTreeOptions {
+        all_files: false,
+        level: 0,
+        dir_only: false,
+        full_path: false,
+        print_size: false,
+        human_readable: false,
+        dirs_first: false,
+        color: false,
+        pattern: ptr::null(),
+        exclude: ptr::null(),
+    }
+}
+
+/// Renders the tree below `path` as `tree` prints it, into a NUL-terminated
+/// string allocated with `malloc` that the caller releases with `free`.
+/// `options` may be NULL for the defaults. Returns NULL when the path or a
+/// pattern is not valid UTF-8, a pattern is invalid, or the root cannot be
+/// read.
+///
+/// # Safety
+///
+/// `path` must point to a NUL-terminated string. `options` must be NULL or
+/// point to a `RustTreeOptions` whose `pattern` and `exclude` are each NULL
+/// or point to a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn rust_tree_render(
+    path: *const c_char,
+    options: *const RustTreeOptions,
+) -> *mut c_char {
+    if path.is_null() {
+        return ptr::null_mut();
+    }
+    // SAFETY: the caller guarantees the pointers are NULL or valid.
+    let (path, options) = unsafe { (CStr::from_ptr(path), options.as_ref()) };
+    // Panics must not unwind into the C caller
+    let text = panic::catch_unwind(|| {
+        // SAFETY: as guaranteed by the caller
+        let options = unsafe { tree_options(options)? };
+        list_directory_as_string(path.to_str().ok()?, &options).ok()
+    });
+    match text {
+        Ok(Some(text)) => malloc_string(&text),
+        _ => ptr::null_mut(),
+    }
+}
+
+/// Converts `options` to `TreeOptions`, or `None` if a pattern is not
+/// valid.
+///
+/// # Safety
+///
+/// As for `rust_tree_render`.
+unsafe fn tree_options(options: Option<&RustTreeOptions>) -> Option<TreeOptions> {
+    let Some(options) = options else {
+        return Some(TreeOptions::default());
+    };
+    let patterns = |patterns: *const c_char| {
+        if patterns.is_null() {
+            return Some(Vec::new());
+        }
+        // SAFETY: non-NULL patterns point to NUL-terminated strings.
+        let patterns = unsafe { CStr::from_ptr(patterns) }.to_str().ok()?;
+        parse_pattern_alternatives(patterns).ok()
+    };
+    Some(TreeOptions {
+        all_files: options.all_files,
+        level: (options.level > 0).then_some(options.level),
+        dir_only: options.dir_only,
+        full_path: options.full_path,
+        print_size: options.print_size,
+        human_readable: options.human_readable,
+        dirs_first: options.dirs_first,
+        color: options.color,
+        pattern_globs: patterns(options.pattern)?,
+        exclude_patterns: patterns(options.exclude)?,
+        ..Default::default()
+    })
+}
+
+/// Copies `text` into a NUL-terminated string allocated with `malloc`, or
+/// returns NULL if the allocation fails.
+fn malloc_string(text: &str) -> *mut c_char {
+    let bytes = text.as_bytes();
+    // SAFETY: the allocation is checked, and is one byte longer than the
+    // text for the NUL.
+    unsafe {
+        let buffer = malloc(bytes.len() + 1) as *mut u8;
+        if buffer.is_null() {
+            return ptr::null_mut();
+        }
+        ptr::copy_nonoverlapping(bytes.as_ptr(), buffer, bytes.len());
+        *buffer.add(bytes.len()) = 0;
+        buffer as *mut c_char
+    }
+}
@@ -0,0 +1,85 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// The `git status` of the entries below a listing root, for `git_status`.
+///
+/// Statuses are the two-letter codes of `git status --porcelain`: the state
+/// in the index and in the work tree, e.g. `M ` for a staged change, ` M`
+/// for an unstaged one, `??` for untracked and `!!` for ignored entries.
+pub struct GitStatuses {
+    root: PathBuf,
+    canonical_root: PathBuf,
+    /// Statuses by canonical path.
+    entries: HashMap<PathBuf, String>,
+    /// Untracked or ignored directories, which git reports as a whole and
+    /// whose contents share their status.
+    dirs: HashMap<PathBuf, String>,
+}
+
+impl GitStatuses {
+    /// Runs `git status` for the repository holding `root`. Fails if git
+    /// cannot be run or `root` is not inside a repository.
+    pub fn load(root: &Path) -> io::Result<Self> {
+        let canonical_root = fs::canonicalize(root)?;
+        let top_level = git(&canonical_root, &["rev-parse", "--show-toplevel"])?;
+        // Canonical like the root, so that the paths below both compare equal
+        // (git prints `C:/…` on Windows where canonicalize gives `\\?\C:\…`)
+        let top_level = fs::canonicalize(top_level.trim_end_matches(['\n', '\r']))?;
+        let status = git(
+            &canonical_root,
+            &["status", "--porcelain=v1", "-z", "--ignored", "--", "."],
+        )?;
+
+        let mut entries = HashMap::new();
+        let mut dirs = HashMap::new();
+        let mut records = status.split('\0');
+        while let Some(record) = records.next() {
+            if record.len() < 4 {
+                continue;
+            }
+            let (code, path) = record.split_at(2);
+            // Renames and copies are followed by the path they came from
+            if code.contains(['R', 'C']) {
+                records.next();
+            }
+            let path = &path[1..];
+            match path.strip_suffix('/') {
+                Some(dir) => dirs.insert(top_level.join(dir), code.to_string()),
+                None => entries.insert(top_level.join(path), code.to_string()),
+            };
+        }
+        Ok(GitStatuses {
+            root: root.to_path_buf(),
+            canonical_root,
+            entries,
+            dirs,
+        })
+    }
+
+    /// The status of the entry at `path`, below the root, or `None` if it
+    /// is tracked and unchanged.
+    pub fn status(&self, path: &Path) -> Option<&str> {
+        let relative = path.strip_prefix(&self.root).ok()?;
+        let path = self.canonical_root.join(relative);
+        if let Some(status) = self.entries.get(&path).or_else(|| self.dirs.get(&path)) {
+            return Some(status);
+        }
+        path.ancestors()
+            .skip(1)
+            .find_map(|dir| self.dirs.get(dir))
+            .map(String::as_str)
+    }
+}
+
+/// Runs git in `dir` and returns what it printed.
+fn git(dir: &Path, args: &[&str]) -> io::Result<String> {
+    let output = Command::new("git").arg("-C").arg(dir).args(args).output()?;
+    if !output.status.success() {
+        let message = String::from_utf8_lossy(&output.stderr);
+        return Err(io::Error::other(message.trim().to_string()));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
@@ -0,0 +1,150 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+
+use ignore::gitignore::{gitconfig_excludes_path, Gitignore, GitignoreBuilder};
+use ignore::Match;
+
+use crate::rust_tree::entry::EntrySource;
+
+/// The gitignore rules that apply below a listing root, for `gitignore`.
+///
+/// As in git, the `.gitignore` files of the directory holding an entry and
+/// of its parents are consulted nearest first, then the repository's
+/// `.git/info/exclude`, then the user's `core.excludesFile`. The
+/// `.gitignore` files of directories above the root count as long as they
+/// are inside the same repository.
+pub struct GitIgnores {
+    root: PathBuf,
+    /// Matchers of the root and the directories below it, `None` for those
+    /// without a `.gitignore`. Filled in by `load_dir` as the traversal
+    /// reaches each directory, possibly from several threads.
+    dirs: RwLock<HashMap<PathBuf, Option<Gitignore>>>,
+    /// Matchers from outside the listing, each with the directory its
+    /// patterns are relative to, in order of precedence.
+    outer: Vec<(PathBuf, Gitignore)>,
+    /// The canonical root, which `outer` directories are ancestors of.
+    canonical_root: Option<PathBuf>,
+}
+
+impl GitIgnores {
+    /// Loads the rules for listing `root`. Rules from outside the listing
+    /// are only read for roots on the filesystem.
+    pub fn new(root: &Path) -> Self {
+        let canonical_root = fs::canonicalize(root).ok();
+        let mut outer = Vec::new();
+        if let Some(canonical_root) = &canonical_root {
+            let repository = canonical_root
+                .ancestors()
+                .find(|dir| dir.join(".git").exists());
+            if let Some(repository) = repository {
+                // Directories above the root, up to the top of the repository
+                if repository != canonical_root.as_path() {
+                    for dir in canonical_root.ancestors().skip(1) {
+                        if let Some(gitignore) = read_gitignore(&dir.join(".gitignore")) {
+                            outer.push((dir.to_path_buf(), gitignore));
+                        }
+                        if dir == repository {
+                            break;
+                        }
+                    }
+                }
+                let exclude = repository.join(".git").join("info").join("exclude");
+                if let Some(gitignore) = read_gitignore(&exclude) {
+                    outer.push((repository.to_path_buf(), gitignore));
+                }
+            }
+            let base = repository.unwrap_or(canonical_root);
+            if let Some(gitignore) =
+                gitconfig_excludes_path().and_then(|path| read_gitignore(&path))
+            {
+                outer.push((base.to_path_buf(), gitignore));
+            }
+        }
+        GitIgnores {
+            root: root.to_path_buf(),
+            dirs: RwLock::default(),
+            outer,
+            canonical_root,
+        }
+    }
+
+    /// Reads the `.gitignore` of `dir`, a directory at or below the root,
+    /// unless it has been read already.
+    pub fn load_dir(&self, source: &dyn EntrySource, dir: &Path) {
+        if self.dirs.read().unwrap().contains_key(dir) {
+            return;
+        }
+        let gitignore = source
+            .read_to_string(&dir.join(".gitignore"))
+            .ok()
+            .and_then(|text| {
+                let mut builder = new_builder();
+                for line in text.lines() {
+                    // Lines with invalid globs are skipped, as git does
+                    let _ = builder.add_line(None, line);
+                }
+                builder.build().ok()
+            });
+        self.dirs
+            .write()
+            .unwrap()
+            .insert(dir.to_path_buf(), gitignore);
+    }
+
+    /// Whether the entry at `path`, below the root, is ignored. The
+    /// directories holding it must have been loaded with `load_dir`.
+    pub fn is_ignored(&self, path: &Path, is_dir: bool) -> bool {
+        let dirs = self.dirs.read().unwrap();
+        for dir in path.ancestors().skip(1) {
+            if let (Some(Some(gitignore)), Ok(relative)) = (dirs.get(dir), path.strip_prefix(dir)) {
+                match gitignore.matched(relative, is_dir) {
+                    Match::Ignore(_) => return true,
+                    Match::Whitelist(_) => return false,
+                    Match::None => {}
+                }
+            }
+            if dir == self.root {
+                break;
+            }
+        }
+
+        let (Some(canonical_root), Ok(relative)) =
+            (&self.canonical_root, path.strip_prefix(&self.root))
+        else {
+            return false;
+        };
+        for (dir, gitignore) in &self.outer {
+            let Ok(root_in_dir) = canonical_root.strip_prefix(dir) else {
+                continue;
+            };
+            match gitignore.matched(root_in_dir.join(relative), is_dir) {
+                Match::Ignore(_) => return true,
+                Match::Whitelist(_) => return false,
+                Match::None => {}
+            }
+        }
+        false
+    }
+}
+
+/// Builds a matcher from the ignore file at `path`, or `None` if it is
+/// missing or empty.
+fn read_gitignore(path: &Path) -> Option<Gitignore> {
+    if !path.is_file() {
+        return None;
+    }
+    let mut builder = new_builder();
+    builder.add(path);
+    builder
+        .build()
+        .ok()
+        .filter(|gitignore| !gitignore.is_empty())
+}
+
+/// Matchers are given paths relative to the directory their patterns are
+/// relative to, so they are built without a root to strip from them.
+fn new_builder() -> GitignoreBuilder {
+    GitignoreBuilder::new("")
+}
@@ -0,0 +1,95 @@
+use glob::Pattern;
+use std::path::Path;
+
+use crate::rust_tree::entry::EntrySource;
+
+/// The longest directory description shown, in characters.
+const DESCRIPTION_MAX_CHARS: usize = 60;
+
+/// Comments from a directory's `.info` file, as used by `--info`.
+///
+/// The format follows GNU tree: lines not starting with a tab are wild-card
+/// patterns, and the tab-indented lines that follow are the comment for all
+/// patterns since the previous comment. Lines starting with `#` are ignored.
+/// A pattern ending in `/` only matches directories.
+#[derive(Debug, Default)]
+pub struct InfoComments {
+    entries: Vec<(Vec<Pattern>, Vec<String>)>,
+}
+
+impl InfoComments {
+    pub fn parse(text: &str) -> Self {
+        let mut info = InfoComments::default();
+        let mut patterns = Vec::new();
+        let mut comment = Vec::new();
+        for line in text.lines() {
+            if line.starts_with('#') {
+                continue;
+            }
+            if let Some(text) = line.strip_prefix('\t') {
+                comment.push(text.to_string());
+                continue;
+            }
+            if !comment.is_empty() {
+                info.entries
+                    .push((std::mem::take(&mut patterns), std::mem::take(&mut comment)));
+            }
+            if let Ok(pattern) = Pattern::new(line.trim_end()) {
+                patterns.push(pattern);
+            }
+        }
+        if !comment.is_empty() {
+            info.entries.push((patterns, comment));
+        }
+        info
+    }
+
+    /// Returns the comment lines for the first pattern matching an entry.
+    pub fn comments_for(&self, name: &str, is_dir: bool) -> Option<&[String]> {
+        self.entries
+            .iter()
+            .find(|(patterns, _)| {
+                patterns
+                    .iter()
+                    .any(|pattern| match pattern.as_str().strip_suffix('/') {
+                        Some(dir_pattern) => {
+                            is_dir && Pattern::new(dir_pattern).is_ok_and(|p| p.matches(name))
+                        }
+                        None => pattern.matches(name),
+                    })
+            })
+            .map(|(_, comment)| comment.as_slice())
+    }
+}
+
+/// A short description of the directory at `dir`, for `dir_descriptions`:
+/// the first non-empty line of its `.dirinfo` file, or else the first
+/// heading of its `README.md`. Longer descriptions are cut at
+/// `DESCRIPTION_MAX_CHARS`.
+pub fn dir_description(source: &dyn EntrySource, dir: &Path) -> Option<String> {
+    let dirinfo = source.read_to_string(&dir.join(".dirinfo")).ok();
+    let description = match dirinfo {
+        Some(text) => text
+            .lines()
+            .map(str::trim)
+            .find(|line| !line.is_empty())?
+            .to_string(),
+        None => {
+            let readme = source.read_to_string(&dir.join("README.md")).ok()?;
+            readme
+                .lines()
+                .find_map(|line| line.strip_prefix('#'))
+                .map(|heading| heading.trim_start_matches('#').trim().to_string())
+                .filter(|heading| !heading.is_empty())?
+        }
+    };
+    if description.chars().count() > DESCRIPTION_MAX_CHARS {
+        let cut: String = description
+            .chars()
+            .take(DESCRIPTION_MAX_CHARS - 3)
+            .collect();
+        Some(format!("{}...", cut.trim_end()))
+    } else {
+        Some(description)
+    }
+}
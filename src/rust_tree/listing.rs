@@ -0,0 +1,230 @@
+use std::collections::HashMap;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use std::str::FromStr;
+use std::time::{Duration, SystemTime};
+
+use crate::rust_tree::entry::{EntryInfo, EntryKind, EntrySource};
+use crate::rust_tree::utils::DirId;
+
+/// File type bits of a Unix mode, and the values for directories and
+/// symlinks.
+const MODE_TYPE_MASK: u32 = 0o170000;
+const MODE_DIRECTORY: u32 = 0o040000;
+const MODE_SYMLINK: u32 = 0o120000;
+
+/// The layout of a `--fromfile` listing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputFormat {
+    /// One path per line. A trailing `/` marks a directory.
+    Plain,
+    /// One `path<TAB>size<TAB>mtime<TAB>mode` record per line, with the size
+    /// in bytes, the mtime in seconds since the Unix epoch and the mode in
+    /// octal (e.g. `644`, or `40755` to mark a directory). Empty or `-`
+    /// fields are unknown.
+    Tsv,
+}
+
+impl FromStr for InputFormat {
+    type Err = String;
+
+    fn from_str(format: &str) -> Result<Self, Self::Err> {
+        match format {
+            "plain" => Ok(InputFormat::Plain),
+            "tsv" => Ok(InputFormat::Tsv),
+            _ => Err(format!("unknown input format '{}'", format)),
+        }
+    }
+}
+
+impl InputFormat {
+    /// Guesses the format of a listing: TSV if every line has exactly four
+    /// tab separated fields, plain otherwise.
+    pub fn detect(text: &str) -> InputFormat {
+        let mut lines = text.lines().filter(|line| !line.is_empty()).peekable();
+        if lines.peek().is_some() && lines.all(|line| line.split('\t').count() == 4) {
+            InputFormat::Tsv
+        } else {
+            InputFormat::Plain
+        }
+    }
+}
+
+/// A tree of entries read from a listing rather than the filesystem. Entry
+/// paths are relative to the empty root path, as written in the listing.
+#[derive(Debug, Default)]
+pub struct Listing {
+    entries: HashMap<PathBuf, EntryInfo>,
+    children: HashMap<PathBuf, Vec<PathBuf>>,
+}
+
+impl Listing {
+    /// Parses `text` in the given format, or the detected one if `None`.
+    /// Directories leading up to a listed path are added if missing.
+    pub fn parse(text: &str, format: Option<InputFormat>) -> io::Result<Listing> {
+        let format = format.unwrap_or_else(|| InputFormat::detect(text));
+        let mut listing = Listing::default();
+        for (index, line) in text.lines().enumerate() {
+            if line.is_empty() {
+                continue;
+            }
+            let invalid = |what: &str| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("line {}: invalid {}", index + 1, what),
+                )
+            };
+            let mut fields = line.split('\t');
+            let raw_path = match format {
+                InputFormat::Plain => line,
+                InputFormat::Tsv => fields.next().unwrap_or_default(),
+            };
+            let path =
+                normalize(raw_path).ok_or_else(|| invalid("path, leads outside the root"))?;
+            if path.as_os_str().is_empty() {
+                continue;
+            }
+            let mut kind = if raw_path.ends_with('/') {
+                EntryKind::Directory
+            } else {
+                EntryKind::File
+            };
+            let mut entry = EntryInfo::new(path, kind);
+            if format == InputFormat::Tsv {
+                let mut field = || {
+                    fields
+                        .next()
+                        .filter(|field| !field.is_empty() && *field != "-")
+                };
+                entry.size = field()
+                    .map(|size| size.parse().map_err(|_| invalid("size")))
+                    .transpose()?;
+                entry.modified = field()
+                    .map(|mtime| parse_mtime(mtime).ok_or_else(|| invalid("mtime")))
+                    .transpose()?;
+                entry.mode = field()
+                    .map(|mode| u32::from_str_radix(mode, 8).map_err(|_| invalid("mode")))
+                    .transpose()?;
+                if fields.next().is_some() {
+                    return Err(invalid("record, expected 4 fields"));
+                }
+            }
+            if let Some(mode) = entry.mode {
+                match mode & MODE_TYPE_MASK {
+                    MODE_DIRECTORY => kind = EntryKind::Directory,
+                    MODE_SYMLINK => kind = EntryKind::Symlink,
+                    _ => {}
+                }
+                entry.executable = kind == EntryKind::File && mode & 0o111 != 0;
+            }
+            entry.kind = kind;
+            entry.is_dir = kind == EntryKind::Directory;
+            listing.insert(entry);
+        }
+        Ok(listing)
+    }
+
+    /// Adds `entry`, and the directories leading up to it if missing.
+    fn insert(&mut self, entry: EntryInfo) {
+        let missing: Vec<PathBuf> = entry
+            .path
+            .ancestors()
+            .skip(1)
+            .take_while(|dir| !dir.as_os_str().is_empty() && !self.entries.contains_key(*dir))
+            .map(Path::to_path_buf)
+            .collect();
+        // Outermost first, so that each has its parent to be added to
+        for dir in missing.into_iter().rev() {
+            self.insert_one(EntryInfo::new(dir, EntryKind::Directory));
+        }
+        self.insert_one(entry);
+    }
+
+    /// Adds `entry`, whose parent is already listed.
+    fn insert_one(&mut self, mut entry: EntryInfo) {
+        let parent = entry.path.parent().unwrap_or(Path::new("")).to_path_buf();
+        if let Some(parent_entry) = self.entries.get_mut(&parent) {
+            // Anything with children is a directory
+            parent_entry.kind = EntryKind::Directory;
+            parent_entry.is_dir = true;
+        }
+        if self.children.contains_key(&entry.path) {
+            entry.kind = EntryKind::Directory;
+            entry.is_dir = true;
+        }
+        if !self.entries.contains_key(&entry.path) {
+            self.children
+                .entry(parent)
+                .or_default()
+                .push(entry.path.clone());
+        }
+        self.entries.insert(entry.path.clone(), entry);
+    }
+}
+
+impl EntrySource for Listing {
+    fn read_entries(&self, dir: &Path) -> io::Result<Vec<EntryInfo>> {
+        Ok(self
+            .children
+            .get(dir)
+            .into_iter()
+            .flatten()
+            .map(|path| self.entries[path].clone())
+            .collect())
+    }
+
+    fn dir_id(&self, _path: &Path) -> Option<DirId> {
+        None
+    }
+
+    fn entry(&self, path: &Path) -> io::Result<EntryInfo> {
+        match self.entries.get(path) {
+            Some(entry) => Ok(entry.clone()),
+            None if path.as_os_str().is_empty() => {
+                Ok(EntryInfo::new(PathBuf::new(), EntryKind::Directory))
+            }
+            None => Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("{} is not in the listing", path.display()),
+            )),
+        }
+    }
+
+    /// Listings only hold paths, so there are no files to read.
+    fn read_to_string(&self, path: &Path) -> io::Result<String> {
+        Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("{} is not readable from a listing", path.display()),
+        ))
+    }
+}
+
+/// Strips `.` and root components from a listed path and resolves `..`
+/// against the components before it, e.g. `./src/` and `lib/../src` both
+/// become `src`. The path is empty if it names the root itself, and `None`
+/// if it leads outside of it, as `../src` does.
+fn normalize(path: &str) -> Option<PathBuf> {
+    let mut normalized = PathBuf::new();
+    for component in Path::new(path).components() {
+        match component {
+            Component::Normal(name) => normalized.push(name),
+            Component::ParentDir => {
+                if !normalized.pop() {
+                    return None;
+                }
+            }
+            Component::CurDir | Component::RootDir | Component::Prefix(_) => {}
+        }
+    }
+    Some(normalized)
+}
+
+/// Parses seconds since the Unix epoch, possibly fractional.
+fn parse_mtime(mtime: &str) -> Option<SystemTime> {
+    let seconds: f64 = mtime.parse().ok()?;
+    if seconds < 0.0 {
+        SystemTime::UNIX_EPOCH.checked_sub(Duration::try_from_secs_f64(-seconds).ok()?)
+    } else {
+        SystemTime::UNIX_EPOCH.checked_add(Duration::try_from_secs_f64(seconds).ok()?)
+    }
+}
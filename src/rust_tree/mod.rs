@@ -1,4 +1,16 @@
+#[cfg(feature = "tokio")]
+pub mod async_tree;
 pub mod display;
+pub mod entry;
+mod external_sort;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod git_status;
+pub mod gitignore;
+pub mod info;
+pub mod listing;
 pub mod options;
+pub mod summary;
+pub mod theme;
 pub mod traversal;
 pub mod utils;
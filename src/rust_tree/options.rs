@@ -1,14 +1,684 @@
 use glob::Pattern;
+use regex::bytes::Regex as BytesRegex;
+use regex::Regex;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
 
+use crate::rust_tree::display::EntryFormatter;
+use crate::rust_tree::entry::{EntryInfo, EntryKind};
+use crate::rust_tree::theme::Theme;
+use crate::rust_tree::utils::{format_human_size, format_si_size};
+
+/// Why an entry was left out of the listing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum SkipReason {
+    /// Hidden entry and `all_files` is not set.
+    Hidden,
+    /// Deeper than the `level` limit.
+    Depth,
+    /// File less than `min_depth` levels deep.
+    Shallow,
+    /// File did not match any of the `pattern_globs` or `include_regexes`.
+    NotMatched,
+    /// File smaller than `min_size`, larger than `max_size`, or of unknown
+    /// size while either is set.
+    Size,
+    /// File modified before `newer_than`, after `older_than`, or at an
+    /// unknown time while either is set.
+    Date,
+    /// Zero-byte file and `nonempty` is set.
+    Empty,
+    /// Directory with nothing left below it, with `prune` or once
+    /// `newer_than` and `older_than` are applied.
+    Pruned,
+    /// Matched one of the `exclude_patterns` or `exclude_regexes`.
+    Excluded,
+    /// Ignored by git, with `gitignore`.
+    GitIgnored,
+    /// Not a directory and `dir_only` is set.
+    NotDirectory,
+    /// Not a directory and not of any of the `entry_types`.
+    WrongType,
+    /// Rejected by the `filter` closure.
+    Filtered,
+    /// File without a line matching any of the `content_patterns`.
+    NoContentMatch,
+    /// A file the listing is being written to (`output` or `linemap`).
+    OutputFile,
+}
+
+/// A kind of entry to list with `entry_types`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EntryType {
+    File,
+    Directory,
+    Symlink,
+    /// Files with an execute permission bit set.
+    Executable,
+}
+
+impl FromStr for EntryType {
+    type Err = String;
+
+    fn from_str(entry_type: &str) -> Result<Self, Self::Err> {
+        match entry_type {
+            "f" | "file" => Ok(EntryType::File),
+            "d" | "dir" | "directory" => Ok(EntryType::Directory),
+            "l" | "link" | "symlink" => Ok(EntryType::Symlink),
+            "x" | "executable" => Ok(EntryType::Executable),
+            _ => Err(format!("unknown entry type '{}'", entry_type)),
+        }
+    }
+}
+
+impl EntryType {
+    pub fn matches(self, entry: &EntryInfo) -> bool {
+        match self {
+            EntryType::File => entry.kind == EntryKind::File,
+            EntryType::Directory => entry.is_dir,
+            EntryType::Symlink => entry.kind == EntryKind::Symlink,
+            EntryType::Executable => entry.executable,
+        }
+    }
+}
+
+/// How the entries of each directory are ordered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SortKey {
+    /// Alphabetically by name.
+    #[default]
+    Name,
+    /// Largest first.
+    Size,
+    /// Most recently modified first.
+    ModifiedTime,
+    /// Most recent status change first. Also makes `print_date` show the
+    /// status change time.
+    ChangeTime,
+    /// By name, with embedded numbers compared numerically (`v2` < `v10`).
+    Version,
+    /// By extension, then name.
+    Extension,
+    /// In the order the filesystem returns entries.
+    None,
+}
+
+impl FromStr for SortKey {
+    type Err = String;
+
+    fn from_str(key: &str) -> Result<Self, Self::Err> {
+        match key {
+            "name" => Ok(SortKey::Name),
+            "size" => Ok(SortKey::Size),
+            "mtime" => Ok(SortKey::ModifiedTime),
+            "ctime" => Ok(SortKey::ChangeTime),
+            "version" => Ok(SortKey::Version),
+            "extension" => Ok(SortKey::Extension),
+            "none" => Ok(SortKey::None),
+            _ => Err(format!("unknown sort key '{}'", key)),
+        }
+    }
+}
+
+/// How the entries of each directory are split into headed sections.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GroupBy {
+    /// Directories, Code, Docs, Media and Other.
+    Type,
+    /// Directories, then one section per file extension.
+    Extension,
+}
+
+impl FromStr for GroupBy {
+    type Err = String;
+
+    fn from_str(group_by: &str) -> Result<Self, Self::Err> {
+        match group_by {
+            "type" => Ok(GroupBy::Type),
+            "ext" => Ok(GroupBy::Extension),
+            _ => Err(format!("unknown grouping '{}'", group_by)),
+        }
+    }
+}
+
+/// How human readable sizes are rounded to their decimal places.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SizeRounding {
+    #[default]
+    Nearest,
+    Up,
+    Down,
+}
+
+impl FromStr for SizeRounding {
+    type Err = String;
+
+    fn from_str(rounding: &str) -> Result<Self, Self::Err> {
+        match rounding {
+            "nearest" => Ok(SizeRounding::Nearest),
+            "up" => Ok(SizeRounding::Up),
+            "down" => Ok(SizeRounding::Down),
+            _ => Err(format!("unknown rounding '{}'", rounding)),
+        }
+    }
+}
+
+/// How `show_permissions` prints an entry's mode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PermissionMode {
+    /// `drwxr-xr-x`, as `ls -l` prints it.
+    #[default]
+    Symbolic,
+    /// `0755`.
+    Octal,
+    /// `drwxr-xr-x 0755`.
+    Both,
+}
+
+impl FromStr for PermissionMode {
+    type Err = String;
+
+    fn from_str(mode: &str) -> Result<Self, Self::Err> {
+        match mode {
+            "symbolic" => Ok(PermissionMode::Symbolic),
+            "octal" => Ok(PermissionMode::Octal),
+            "both" => Ok(PermissionMode::Both),
+            _ => Err(format!("unknown permission mode '{}'", mode)),
+        }
+    }
+}
+
+/// A piece of an entry's line. Columns before `Name` are printed in
+/// brackets ahead of it, e.g. `[drwxr-xr-x staff]  src`, and the others
+/// follow it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Column {
+    Inode,
+    Device,
+    Permissions,
+    /// The entry's read-only, hidden, system and archive attributes, on
+    /// Windows.
+    Attributes,
+    Group,
+    Date,
+    /// The entry's two-letter `git status` code, e.g. ` M` or `??`.
+    GitStatus,
+    Name,
+    /// A symlink's ` -> target`.
+    Target,
+    Size,
+    /// The entry's share of its directory's size as a bar and a percentage,
+    /// e.g. `######....  58%`, with `disk_usage`.
+    Bar,
+    /// Notes such as directories not opened for `file_limit`, and directory
+    /// descriptions.
+    Annotations,
+}
+
+impl FromStr for Column {
+    type Err = String;
+
+    fn from_str(column: &str) -> Result<Self, Self::Err> {
+        match column {
+            "inode" => Ok(Column::Inode),
+            "device" => Ok(Column::Device),
+            "perms" | "permissions" => Ok(Column::Permissions),
+            "attrs" | "attributes" => Ok(Column::Attributes),
+            "group" => Ok(Column::Group),
+            "date" => Ok(Column::Date),
+            "git" => Ok(Column::GitStatus),
+            "name" => Ok(Column::Name),
+            "target" => Ok(Column::Target),
+            "size" => Ok(Column::Size),
+            "bar" => Ok(Column::Bar),
+            "annotations" => Ok(Column::Annotations),
+            _ => Err(format!("unknown column '{}'", column)),
+        }
+    }
+}
+
+/// The names of the box-drawing styles, for `Charset::line_style`.
+pub const LINE_STYLES: &[&str] = &["default", "rounded", "double", "heavy", "dots"];
+
+/// The glyphs tree lines are drawn with.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Charset {
+    /// Prefix of an entry with siblings after it, e.g. `├── `.
+    pub branch: String,
+    /// Prefix of the last entry of a directory, e.g. `└── `.
+    pub last_branch: String,
+    /// Indentation below an entry with siblings after it, e.g. `│   `.
+    pub vertical: String,
+}
+
+impl Charset {
+    pub fn utf8() -> Self {
+        Charset::new("├── ", "└── ", "│   ")
+    }
+
+    pub fn ascii() -> Self {
+        Charset::new("|-- ", "`-- ", "|   ")
+    }
+
+    /// One of the box-drawing styles listed in `LINE_STYLES`:
+    /// - `default`: thin lines, as `utf8`.
+    /// - `rounded`: thin lines with a rounded last branch, `╰── `.
+    /// - `double`: double lines, `╠══ `.
+    /// - `heavy`: thick lines, `┣━━ `.
+    /// - `dots`: dotted lines, `├┈┈ `.
+    pub fn line_style(name: &str) -> Option<Self> {
+        let charset = match name {
+            "default" => Charset::utf8(),
+            "rounded" => Charset::new("├── ", "╰── ", "│   "),
+            "double" => Charset::new("╠══ ", "╚══ ", "║   "),
+            "heavy" => Charset::new("┣━━ ", "┗━━ ", "┃   "),
+            "dots" => Charset::new("├┈┈ ", "└┈┈ ", "┊   "),
+            _ => return None,
+        };
+        Some(charset)
+    }
+
+    fn new(branch: &str, last_branch: &str, vertical: &str) -> Self {
+        Charset {
+            branch: branch.to_string(),
+            last_branch: last_branch.to_string(),
+            vertical: vertical.to_string(),
+        }
+    }
+
+    /// Indentation below the last entry of a directory, as wide as
+    /// `vertical`.
+    pub fn blank(&self) -> String {
+        " ".repeat(self.vertical.chars().count())
+    }
+}
+
+impl Default for Charset {
+    fn default() -> Self {
+        Charset::utf8()
+    }
+}
+
+impl FromStr for Charset {
+    type Err = String;
+
+    /// Parses a charset name (`utf8`, `ascii`, ...) or a custom set given
+    /// as `branch,last branch,vertical`, e.g. `+-- ,'-- ,|   `.
+    fn from_str(charset: &str) -> Result<Self, Self::Err> {
+        match charset.to_lowercase().as_str() {
+            "utf8" | "utf-8" => return Ok(Charset::utf8()),
+            "ascii" | "us-ascii" | "iso-8859-1" | "latin1" => return Ok(Charset::ascii()),
+            _ => {}
+        }
+        match charset.split(',').collect::<Vec<_>>()[..] {
+            [branch, last_branch, vertical] => Ok(Charset::new(branch, last_branch, vertical)),
+            _ => Err(format!("unknown charset '{}'", charset)),
+        }
+    }
+}
+
+/// Called with each entry left out of the listing and the reason why.
+pub type SkipHandler = Box<dyn Fn(&Path, SkipReason) + Send + Sync>;
+/// Called with each path that could not be read during traversal.
+pub type ErrorHandler = Box<dyn Fn(&Path, &io::Error) + Send + Sync>;
+/// Whether to list an entry, found at the given depth, for `filter`.
+pub type EntryFilter = Box<dyn Fn(&EntryInfo, usize) -> bool + Send + Sync>;
+/// Orders two entries of the same directory, for `sort_by`.
+pub type EntryComparator = Box<dyn Fn(&EntryInfo, &EntryInfo) -> std::cmp::Ordering + Send + Sync>;
+/// Called with each problem met during traversal that did not stop it.
+pub type WarningHandler = Box<dyn Fn(&TraversalWarning) + Send + Sync>;
+/// Called periodically with how far traversal has got.
+pub type ProgressHandler = Box<dyn Fn(&Progress) + Send + Sync>;
+
+/// A problem met during traversal that did not stop it, passed to
+/// `on_warning`.
+#[derive(Debug)]
+pub enum TraversalWarning {
+    /// A directory below the root that could not be read, listed as empty.
+    Unreadable { path: PathBuf, error: io::Error },
+    /// The git status of a root that could not be loaded, with
+    /// `git_status`, e.g. outside a repository; the root is listed without
+    /// status codes.
+    GitStatus { path: PathBuf, error: io::Error },
+    /// A directory with more entries than `auto_limit`, of which only the
+    /// first `listed` are listed.
+    Truncated {
+        path: PathBuf,
+        entries: usize,
+        listed: usize,
+    },
+}
+
+impl TraversalWarning {
+    /// The path the warning is about.
+    pub fn path(&self) -> &Path {
+        match self {
+            TraversalWarning::Unreadable { path, .. }
+            | TraversalWarning::GitStatus { path, .. }
+            | TraversalWarning::Truncated { path, .. } => path,
+        }
+    }
+}
+
+impl fmt::Display for TraversalWarning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TraversalWarning::Unreadable { path, error }
+            | TraversalWarning::GitStatus { path, error } => {
+                write!(f, "{}: {}", path.display(), error)
+            }
+            TraversalWarning::Truncated {
+                path,
+                entries,
+                listed,
+            } => write!(
+                f,
+                "{}: {} entries, listing the first {}",
+                path.display(),
+                entries,
+                listed
+            ),
+        }
+    }
+}
+
+/// How far a traversal has got, passed to `on_progress`.
+#[derive(Debug, Clone, Copy)]
+pub struct Progress<'a> {
+    /// Entries read so far, including those left out by filters.
+    pub entries: u64,
+    /// The directory most recently read.
+    pub current_dir: &'a Path,
+    /// Time since traversal started.
+    pub elapsed: Duration,
+    /// Whether this is the last call for the tree being read, made once it
+    /// has been read and before it is printed.
+    pub done: bool,
+}
+
+#[derive(Default)]
 pub struct TreeOptions {
     pub all_files: bool,
     pub level: Option<i32>,
+    /// Entries less than this many levels deep are not listed, and the
+    /// contents of such directories are listed in their place, named by
+    /// their path from the root. Entries of the root are at level 1.
+    pub min_depth: Option<usize>,
     pub full_path: bool,
     pub dir_only: bool,
+    /// List only entries of these types, e.g. only symlinks. Directories
+    /// are always listed so the entries inside them can be reached, making
+    /// `[EntryType::Directory]` the same as `dir_only`.
+    pub entry_types: Vec<EntryType>,
+    /// Lists only the entries for which this returns `true`, given each
+    /// entry and its depth, after the other filters except
+    /// `content_patterns`. Directories it rejects are left out with
+    /// everything below them.
+    pub filter: Option<EntryFilter>,
+    pub follow_links: bool,
+    pub sort: SortKey,
+    /// Orders the entries of each directory in place of `sort`.
+    /// `dirs_first`, `files_first` and `group_by` still apply on top of it.
+    pub sort_by: Option<EntryComparator>,
+    /// Compare names without regard to case when sorting, so `README.md`
+    /// and `readme.txt` sort together.
+    pub ignore_case_sort: bool,
+    /// List directories before files, keeping the sort order within each.
+    pub dirs_first: bool,
+    /// List files before directories, keeping the sort order within each.
+    pub files_first: bool,
+    pub group_by: Option<GroupBy>,
+    /// Sort directories of more than this many listed entries on disk, in
+    /// runs of this many kept in temporary files and merged as they are
+    /// listed, so that no more than this many are held in memory at once.
+    /// Takes effect where the listing `streams`.
+    pub external_sort: Option<usize>,
+    /// Do not descend into directories with more entries than this.
+    pub file_limit: Option<usize>,
+    /// List only the first this many entries of wider directories, noting
+    /// how many more there are and warning on stderr.
+    pub auto_limit: Option<usize>,
+    /// List only the first this many entries of each directory, noting how
+    /// many more there are, as `auto_limit` does without the warning.
+    pub limit: Option<usize>,
     pub no_indent: bool,
+    /// The columns printed for each entry, in order, instead of those the
+    /// other options enable. The name is added at the end if missing.
+    pub columns: Option<Vec<Column>>,
+    pub charset: Charset,
     pub print_size: bool,
     pub human_readable: bool,
-    pub pattern_glob: Option<Pattern>,
+    /// Use 1000-based SI units (kB, MB, ...) for `human_readable` sizes.
+    pub si_units: bool,
+    /// Decimal places of human readable sizes, 1 when unset.
+    pub size_precision: Option<usize>,
+    pub size_rounding: SizeRounding,
+    /// Show each directory's size as the total of its contents, and the
+    /// grand total in the report.
+    pub disk_usage: bool,
+    /// With `disk_usage`, show each entry's share of its directory's size.
+    pub bars: bool,
+    /// How many threads total the sizes for `disk_usage`, all available
+    /// cores when unset. Without the `parallel` feature sizes are totalled
+    /// on the calling thread.
+    pub threads: Option<usize>,
+    /// Print each entry's permissions, formatted as `permission_mode`.
+    pub show_permissions: bool,
+    pub permission_mode: PermissionMode,
+    /// Print each entry's Windows attributes, e.g. `R-SA`. Windows only.
+    pub show_attributes: bool,
+    pub show_group: bool,
+    pub show_inodes: bool,
+    pub show_device: bool,
+    pub print_date: bool,
+    /// strftime-like format for `print_date`, e.g. `%Y-%m-%d`.
+    pub time_format: Option<String>,
+    /// Print dates as ages, e.g. `3 hours ago`, instead of `time_format`.
+    pub relative_date: bool,
+    pub pattern_globs: Vec<Pattern>,
+    pub exclude_patterns: Vec<Pattern>,
+    /// Regular expressions a file name may match instead of the
+    /// `pattern_globs` to be listed.
+    pub include_regexes: Vec<Regex>,
+    /// Entries whose name matches any of these regular expressions are
+    /// left out, as with `exclude_patterns`.
+    pub exclude_regexes: Vec<Regex>,
+    /// Match the patterns and regular expressions against the path below
+    /// the root, e.g. `src/main.rs`, rather than the name alone. Globs
+    /// without a `/` still match the name.
+    pub full_match: bool,
+    /// Only list files with a line matching one of these, and the
+    /// directories holding them.
+    pub content_patterns: Vec<BytesRegex>,
+    /// Leave out what git ignores: entries matching the `.gitignore` files
+    /// of their directory and its parents, the repository's
+    /// `.git/info/exclude` and the user's `core.excludesFile`.
+    pub gitignore: bool,
+    /// Print each entry's `git status` code, for roots inside a repository.
+    pub git_status: bool,
+    /// Only list files of at least this many bytes.
+    pub min_size: Option<u64>,
+    /// Only list files of at most this many bytes.
+    pub max_size: Option<u64>,
+    /// Only list files modified after this time. Directories left without
+    /// any listed files are pruned.
+    pub newer_than: Option<SystemTime>,
+    /// Only list files modified before this time, pruning directories as
+    /// with `newer_than`.
+    pub older_than: Option<SystemTime>,
+    /// Leave out zero-byte files.
+    pub nonempty: bool,
+    /// Leave out directories with nothing listed below them.
+    pub prune: bool,
+    /// Colorize names with `theme`. The command line sets this from
+    /// `--color=WHEN`.
     pub color: bool,
-    pub no_color: bool,
+    /// The styles used with `color`.
+    pub theme: Theme,
+    /// Follow the report with a plain-text paragraph describing the tree.
+    pub describe: bool,
+    /// Instead of the tree, print how many entries each active filter left
+    /// out.
+    pub explain_filters: bool,
+    /// Print the comments from each directory's `.info` file beneath the
+    /// entries they match.
+    pub print_info: bool,
+    /// Show a short description after directory names, from the directory's
+    /// `.dirinfo` file or the first heading of its `README.md`.
+    pub dir_descriptions: bool,
+    /// Show how many files and directories are listed below each directory
+    /// after its name.
+    pub dir_counts: bool,
+    /// Print control and other non-printable characters in names as `?`.
+    pub replace_nonprintable: bool,
+    /// Print names and symlink targets in double quotes, with `"` and `\`
+    /// escaped.
+    pub quote_names: bool,
+    /// Append a character marking each entry's kind to its name, as
+    /// `ls -F` does, e.g. `/` for directories, and mark symlinks whose
+    /// target is missing `[broken]`.
+    pub classify: bool,
+    /// Put an emoji marking each entry's kind before its name, e.g. `📁`
+    /// for directories.
+    pub emoji: bool,
+    /// Formats each entry's line after its tree prefix in place of the
+    /// built-in columns. Tree prefixes, `.info` comments, `max_width` and
+    /// `head` still apply.
+    pub formatter: Option<Box<dyn EntryFormatter>>,
+    /// Truncate lines wider than this many terminal columns, ending them with
+    /// `…`.
+    pub max_width: Option<usize>,
+    /// Stop printing the tree after this many lines, following it with a
+    /// notice and the report.
+    pub head: Option<usize>,
+    /// Emit terminal escape sequences (OSC 8 hyperlinks on directories,
+    /// OSC 7 for the root) so terminals can act on directory lines.
+    pub term_integration: bool,
+    /// Writes the listing to this file instead of stdout.
+    pub output: Option<PathBuf>,
+    /// Writes a sidecar file mapping each printed line to the entry it shows.
+    pub linemap: Option<PathBuf>,
+    /// Stops traversal once this much time has passed, listing what was
+    /// gathered and returning an error of kind `TimedOut`.
+    pub timeout: Option<Duration>,
+    /// Stops traversal once set to `true`, e.g. from another thread, listing
+    /// what was gathered and returning an error of kind `Interrupted`.
+    pub cancel: Option<Arc<AtomicBool>>,
+    pub on_skip: Option<SkipHandler>,
+    /// Called with unreadable paths, before `on_warning`.
+    pub on_error: Option<ErrorHandler>,
+    /// When neither this nor `on_error` handles a warning, it is printed on
+    /// stderr.
+    pub on_warning: Option<WarningHandler>,
+    /// Called as directories are read, at most every 100ms, and once more
+    /// when each tree has been read.
+    pub on_progress: Option<ProgressHandler>,
+}
+
+impl TreeOptions {
+    /// Formats a size for `human_readable` output with the configured
+    /// units, precision and rounding.
+    pub fn human_size(&self, bytes: u64) -> String {
+        let precision = self.size_precision.unwrap_or(1);
+        if self.si_units {
+            format_si_size(bytes, precision, self.size_rounding)
+        } else {
+            format_human_size(bytes, precision, self.size_rounding)
+        }
+    }
+
+    /// Whether the `cancel` flag has been set.
+    pub fn is_cancelled(&self) -> bool {
+        self.cancel
+            .as_ref()
+            .is_some_and(|cancel| cancel.load(Ordering::Relaxed))
+    }
+
+    /// Whether files are filtered by modification time, so that directories
+    /// left empty are pruned.
+    pub fn has_date_filter(&self) -> bool {
+        self.newer_than.is_some() || self.older_than.is_some()
+    }
+
+    /// Whether directories with nothing listed below them are left out.
+    pub fn prunes_empty_dirs(&self) -> bool {
+        self.prune || self.has_date_filter() || !self.content_patterns.is_empty()
+    }
+
+    /// Whether any bracketed metadata column is printed before entry names.
+    pub fn has_info_columns(&self) -> bool {
+        self.show_inodes
+            || self.show_device
+            || self.show_permissions
+            || self.show_attributes
+            || self.show_group
+            || self.print_date
+    }
+
+    /// Whether each entry's permissions are printed, with
+    /// `show_permissions` or a `Column::Permissions` in `columns`.
+    pub fn shows_permissions(&self) -> bool {
+        match &self.columns {
+            Some(columns) => columns.contains(&Column::Permissions),
+            None => self.show_permissions,
+        }
+    }
+
+    /// Whether the listing is printed as each directory is read, holding
+    /// only the directories being printed in memory rather than the whole
+    /// tree: when entries are listed in the order read, or sorted on disk
+    /// with `external_sort`, and no option needs all of a directory's
+    /// entries, or the whole tree, before printing any of them.
+    pub fn streams(&self) -> bool {
+        let ordered = self.external_sort.is_some()
+            || (self.sort == SortKey::None
+                && self.sort_by.is_none()
+                && !self.dirs_first
+                && !self.files_first);
+        ordered
+            && self.group_by.is_none()
+            && self.min_depth.is_none()
+            && self.file_limit.is_none()
+            && self.auto_limit.is_none()
+            && self.limit.is_none()
+            && !self.prunes_empty_dirs()
+            && !self.dir_counts
+            && !self.describe
+            && !self.explain_filters
+    }
+
+    /// Whether listing looks at more of an entry than its name and kind:
+    /// its size, times, permissions or anything else from its metadata,
+    /// including for coloring and `classify`, or whatever `filter`,
+    /// `sort_by` and `formatter` might read.
+    pub fn needs_metadata(&self) -> bool {
+        self.print_size
+            || self.human_readable
+            || self.disk_usage
+            || self.bars
+            || self.describe
+            || self.has_info_columns()
+            || self.columns.is_some()
+            || matches!(
+                self.sort,
+                SortKey::Size | SortKey::ModifiedTime | SortKey::ChangeTime
+            )
+            || self.min_size.is_some()
+            || self.max_size.is_some()
+            || self.nonempty
+            || self.has_date_filter()
+            || self.entry_types.contains(&EntryType::Executable)
+            || self.color
+            || self.classify
+            || self.emoji
+            || self.filter.is_some()
+            || self.sort_by.is_some()
+            || self.formatter.is_some()
+    }
 }
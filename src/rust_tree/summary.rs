@@ -0,0 +1,143 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use crate::rust_tree::entry::TreeNode;
+use crate::rust_tree::options::TreeOptions;
+
+/// How many top-level entries, file types and directories a summary names.
+const SUMMARY_ITEMS: usize = 3;
+
+/// Counts gathered from the entries below a node.
+#[derive(Debug, Default)]
+struct Stats {
+    directories: u64,
+    files: u64,
+    /// Files per lowercased extension, `None` for files without one.
+    extensions: HashMap<Option<String>, u64>,
+}
+
+impl Stats {
+    fn gather(node: &TreeNode, options: &TreeOptions) -> Self {
+        let mut stats = Stats::default();
+        // Directories still to count the contents of, rather than recursing,
+        // so that deep trees do not overflow the stack
+        let mut pending = vec![node];
+        while let Some(node) = pending.pop() {
+            for child in &node.children {
+                if child.is_listed_as_dir(options) {
+                    stats.directories += 1;
+                    pending.push(child);
+                } else {
+                    stats.files += 1;
+                    let extension = Path::new(&child.name)
+                        .extension()
+                        .map(|extension| extension.to_string_lossy().to_lowercase());
+                    *stats.extensions.entry(extension).or_default() += 1;
+                }
+            }
+        }
+        stats
+    }
+}
+
+/// Describes a listed tree in a short paragraph of plain text: how much it
+/// holds, what is at the top level, the most common file types and the
+/// largest directories. Sizes are looked up in `dir_sizes`, the totals
+/// gathered for `disk_usage`.
+pub fn describe(
+    tree: &TreeNode,
+    dir_sizes: &HashMap<PathBuf, u64>,
+    options: &TreeOptions,
+) -> String {
+    let stats = Stats::gather(tree, options);
+    let size_of = |node: &TreeNode| dir_sizes.get(&node.entry.path).copied().unwrap_or(0);
+    let mut sentences = vec![format!(
+        "{} holds {} {} and {} {} ({} in total).",
+        tree.name,
+        stats.directories,
+        plural(stats.directories, "directory", "directories"),
+        stats.files,
+        plural(stats.files, "file", "files"),
+        options.human_size(size_of(tree))
+    )];
+
+    if !tree.children.is_empty() {
+        let (directories, files): (Vec<&TreeNode>, Vec<&TreeNode>) = tree
+            .children
+            .iter()
+            .partition(|child| child.is_listed_as_dir(options));
+        let names: Vec<String> = directories
+            .iter()
+            .map(|directory| format!("{}/", directory.name))
+            .chain(files.iter().map(|file| file.name.clone()))
+            .collect();
+        sentences.push(format!("At the top level: {}.", shortened_list(&names)));
+    }
+
+    let mut extensions: Vec<(&Option<String>, &u64)> = stats.extensions.iter().collect();
+    extensions.sort_by(|a, b| b.1.cmp(a.1).then_with(|| a.0.cmp(b.0)));
+    let extensions: Vec<String> = extensions
+        .iter()
+        .take(SUMMARY_ITEMS)
+        .map(|(extension, count)| match extension {
+            Some(extension) => format!(".{} ({})", extension, count),
+            None => format!("no extension ({})", count),
+        })
+        .collect();
+    if !extensions.is_empty() {
+        sentences.push(format!(
+            "The most common file types are {}.",
+            join_list(&extensions)
+        ));
+    }
+
+    let mut directories: Vec<(&TreeNode, u64)> = tree
+        .children
+        .iter()
+        .filter(|child| child.is_listed_as_dir(options))
+        .map(|child| (child, size_of(child)))
+        .collect();
+    directories.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.name.cmp(&b.0.name)));
+    let directories: Vec<String> = directories
+        .iter()
+        .take(SUMMARY_ITEMS)
+        .map(|(directory, size)| format!("{}/ ({})", directory.name, options.human_size(*size)))
+        .collect();
+    if !directories.is_empty() {
+        let subject = if directories.len() == 1 {
+            "The only directory is"
+        } else {
+            "The largest directories are"
+        };
+        sentences.push(format!("{} {}.", subject, join_list(&directories)));
+    }
+
+    sentences.join(" ")
+}
+
+fn plural<'a>(count: u64, singular: &'a str, plural: &'a str) -> &'a str {
+    if count == 1 {
+        singular
+    } else {
+        plural
+    }
+}
+
+/// Joins the first few items, noting how many more there are.
+fn shortened_list(items: &[String]) -> String {
+    if items.len() <= SUMMARY_ITEMS + 1 {
+        return join_list(items);
+    }
+    let mut shown = items[..SUMMARY_ITEMS].to_vec();
+    shown.push(format!("{} more", items.len() - SUMMARY_ITEMS));
+    join_list(&shown)
+}
+
+/// Joins items as in prose: `a`, `a and b`, `a, b and c`.
+fn join_list(items: &[String]) -> String {
+    match items {
+        [] => String::new(),
+        [item] => item.clone(),
+        [rest @ .., last] => format!("{} and {}", rest.join(", "), last),
+    }
+}
@@ -0,0 +1,269 @@
+use std::str::FromStr;
+
+/// The names of the bundled themes, for `Theme::named`.
+pub const THEME_NAMES: &[&str] = &["default", "light", "mono", "solarized"];
+
+/// A terminal color: one of the eight basic colors, one of the 256 indexed
+/// colors, or a 24-bit color.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Colour {
+    Black,
+    Red,
+    Green,
+    Yellow,
+    Blue,
+    Purple,
+    Cyan,
+    White,
+    Fixed(u8),
+    Rgb(u8, u8, u8),
+}
+
+/// How some text is drawn: a foreground color and attributes. The default
+/// style leaves text as it is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Style {
+    pub foreground: Option<Colour>,
+    pub bold: bool,
+    pub dimmed: bool,
+    pub italic: bool,
+    pub underline: bool,
+}
+
+impl Style {
+    fn fg(colour: Colour) -> Self {
+        Style {
+            foreground: Some(colour),
+            ..Style::default()
+        }
+    }
+
+    fn bold(self) -> Self {
+        Style { bold: true, ..self }
+    }
+}
+
+/// Parses space-separated words: attributes (`bold`, `dimmed`, `italic`,
+/// `underline`) and at most one color, given by name (e.g. `blue`), by index
+/// (`0` to `255`) or as `#rrggbb`. `plain` is the default style.
+impl FromStr for Style {
+    type Err = String;
+
+    fn from_str(style: &str) -> Result<Self, Self::Err> {
+        let mut parsed = Style::default();
+        for word in style.split_whitespace() {
+            let word = word.to_lowercase();
+            let colour = match word.as_str() {
+                "plain" | "none" => None,
+                "bold" => {
+                    parsed.bold = true;
+                    None
+                }
+                "dimmed" | "dim" => {
+                    parsed.dimmed = true;
+                    None
+                }
+                "italic" => {
+                    parsed.italic = true;
+                    None
+                }
+                "underline" => {
+                    parsed.underline = true;
+                    None
+                }
+                "black" => Some(Colour::Black),
+                "red" => Some(Colour::Red),
+                "green" => Some(Colour::Green),
+                "yellow" => Some(Colour::Yellow),
+                "blue" => Some(Colour::Blue),
+                "purple" | "magenta" => Some(Colour::Purple),
+                "cyan" => Some(Colour::Cyan),
+                "white" => Some(Colour::White),
+                _ => Some(
+                    parse_colour_code(&word)
+                        .ok_or_else(|| format!("unknown color or attribute '{}'", word))?,
+                ),
+            };
+            if let Some(colour) = colour {
+                if parsed.foreground.is_some() {
+                    return Err(format!("more than one color in '{}'", style));
+                }
+                parsed.foreground = Some(colour);
+            }
+        }
+        Ok(parsed)
+    }
+}
+
+/// The contents of the basic string (`"…"`, without escapes) or literal
+/// string (`'…'`) `value` starts with, if nothing but a comment follows it.
+fn parse_string(value: &str) -> Option<&str> {
+    let quote = value
+        .chars()
+        .next()
+        .filter(|quote| matches!(quote, '"' | '\''))?;
+    let (string, rest) = value[1..].split_once(quote)?;
+    let rest = rest.trim_start();
+    if (quote == '"' && string.contains('\\')) || !(rest.is_empty() || rest.starts_with('#')) {
+        return None;
+    }
+    Some(string)
+}
+
+/// Parses an indexed color such as `208` or a 24-bit one such as `#268bd2`.
+fn parse_colour_code(code: &str) -> Option<Colour> {
+    if let Some(hex) = code.strip_prefix('#') {
+        if hex.len() != 6 || !hex.is_ascii() {
+            return None;
+        }
+        let channel = |index: usize| u8::from_str_radix(&hex[index..index + 2], 16).ok();
+        return Some(Colour::Rgb(channel(0)?, channel(2)?, channel(4)?));
+    }
+    code.parse().ok().map(Colour::Fixed)
+}
+
+/// The styles entries and `git_status` codes are colored with.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Theme {
+    pub directory: Style,
+    pub symlink: Style,
+    /// Symlinks to something that does not exist, and their targets.
+    pub broken_link: Style,
+    pub executable: Style,
+    /// Files such as `.tar.gz` and `.zip`.
+    pub archive: Style,
+    /// Files such as `.png` and `.jpg`.
+    pub image: Style,
+    /// Changes in the index.
+    pub git_staged: Style,
+    /// Changes in the work tree, and untracked entries.
+    pub git_changed: Style,
+    pub git_ignored: Style,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Theme {
+            directory: Style::fg(Colour::Blue).bold(),
+            symlink: Style::fg(Colour::Cyan),
+            broken_link: Style::fg(Colour::Red).bold(),
+            executable: Style::fg(Colour::Green),
+            archive: Style::fg(Colour::Red),
+            image: Style::fg(Colour::Yellow),
+            git_staged: Style::fg(Colour::Green),
+            git_changed: Style::fg(Colour::Red),
+            git_ignored: Style {
+                dimmed: true,
+                ..Style::default()
+            },
+        }
+    }
+}
+
+impl Theme {
+    /// One of the bundled themes, listed in `THEME_NAMES`:
+    /// - `default`: the colors of GNU tree and `ls`.
+    /// - `light`: darker colors that stay readable on light backgrounds.
+    /// - `mono`: no colors, only bold, italic and underlined text.
+    /// - `solarized`: the Solarized palette, in 24-bit color.
+    pub fn named(name: &str) -> Option<Self> {
+        let theme = match name {
+            "default" => Theme::default(),
+            "light" => Theme {
+                directory: Style::fg(Colour::Fixed(19)).bold(),
+                symlink: Style::fg(Colour::Fixed(90)),
+                broken_link: Style::fg(Colour::Fixed(124)).bold(),
+                executable: Style::fg(Colour::Fixed(22)).bold(),
+                archive: Style::fg(Colour::Fixed(124)),
+                image: Style::fg(Colour::Fixed(94)),
+                git_staged: Style::fg(Colour::Fixed(22)),
+                git_changed: Style::fg(Colour::Fixed(124)),
+                ..Theme::default()
+            },
+            "mono" => Theme {
+                directory: Style::default().bold(),
+                symlink: Style {
+                    italic: true,
+                    ..Style::default()
+                },
+                broken_link: Style {
+                    underline: true,
+                    ..Style::default().bold()
+                },
+                executable: Style::default().bold(),
+                archive: Style::default(),
+                image: Style::default(),
+                git_staged: Style::default().bold(),
+                git_changed: Style::default().bold(),
+                ..Theme::default()
+            },
+            "solarized" => Theme {
+                directory: Style::fg(Colour::Rgb(0x26, 0x8b, 0xd2)).bold(),
+                symlink: Style::fg(Colour::Rgb(0x2a, 0xa1, 0x98)),
+                broken_link: Style::fg(Colour::Rgb(0xdc, 0x32, 0x2f)).bold(),
+                executable: Style::fg(Colour::Rgb(0x85, 0x99, 0x00)),
+                archive: Style::fg(Colour::Rgb(0xcb, 0x4b, 0x16)),
+                image: Style::fg(Colour::Rgb(0xb5, 0x89, 0x00)),
+                git_staged: Style::fg(Colour::Rgb(0x85, 0x99, 0x00)),
+                git_changed: Style::fg(Colour::Rgb(0xdc, 0x32, 0x2f)),
+                git_ignored: Style::fg(Colour::Rgb(0x58, 0x6e, 0x75)),
+            },
+            _ => return None,
+        };
+        Some(theme)
+    }
+
+    /// Reads a custom theme written in TOML, one `key = "style"` pair per
+    /// line, e.g. `directory = "bold #268bd2"`, optionally under a `[colors]`
+    /// table. Keys are the fields of `Theme`, and a `base` key names the
+    /// bundled theme the others override (`default` if not given). Styles
+    /// are parsed as by `Style::from_str`.
+    ///
+    /// Only this subset of TOML is read: bare keys, and values that are
+    /// basic strings without escapes (`"bold"`) or literal strings
+    /// (`'bold'`). Comments may follow a value or the table header.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut pairs = Vec::new();
+        let mut base = "default";
+        for (index, line) in text.lines().enumerate() {
+            let error = |message: String| format!("line {}: {}", index + 1, message);
+            let line = line.trim();
+            let line = line.strip_prefix("[colors]").unwrap_or(line).trim_start();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (key, value) = line
+                .split_once('=')
+                .ok_or_else(|| error("expected key = \"style\"".to_string()))?;
+            let value = value.trim();
+            let value = parse_string(value)
+                .ok_or_else(|| error(format!("expected a quoted string, found {}", value)))?;
+            match key.trim() {
+                "base" => base = value,
+                key => pairs.push((index, key, value)),
+            }
+        }
+
+        let mut theme =
+            Theme::named(base).ok_or_else(|| format!("unknown base theme '{}'", base))?;
+        for (index, key, value) in pairs {
+            let style = value
+                .parse()
+                .map_err(|message| format!("line {}: {}", index + 1, message))?;
+            let field = match key {
+                "directory" => &mut theme.directory,
+                "symlink" => &mut theme.symlink,
+                "broken_link" => &mut theme.broken_link,
+                "executable" => &mut theme.executable,
+                "archive" => &mut theme.archive,
+                "image" => &mut theme.image,
+                "git_staged" => &mut theme.git_staged,
+                "git_changed" => &mut theme.git_changed,
+                "git_ignored" => &mut theme.git_ignored,
+                _ => return Err(format!("line {}: unknown key '{}'", index + 1, key)),
+            };
+            *field = style;
+        }
+        Ok(theme)
+    }
+}
@@ -1,153 +1,2036 @@
-use std::collections::HashSet;
-use std::fs;
-use std::path::Path;
+use std::cell::RefCell;
+use std::cmp::{Ordering, Reverse};
+use std::collections::{HashMap, HashSet};
+use std::ffi::OsStr;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
-use crate::rust_tree::display::colorize;
-use crate::rust_tree::options::TreeOptions;
-use crate::rust_tree::utils::bytes_to_human_readable;
+use glob::{MatchOptions, Pattern};
+use regex::bytes::Regex as BytesRegex;
 
-pub fn traverse_directory<P: AsRef<Path>>(
-    root_path: P,
+use crate::rust_tree::display::{
+    active_columns, format_entry_line, group_of, hyperlink, truncate_line, working_directory_marker,
+};
+use crate::rust_tree::entry::{
+    EntryInfo, EntryKind, EntrySource, EntryStream, Filesystem, FilesystemAcls, FilesystemKinds,
+    TreeNode,
+};
+use crate::rust_tree::external_sort::{EntryOrder, SortedEntries};
+use crate::rust_tree::git_status::GitStatuses;
+use crate::rust_tree::gitignore::GitIgnores;
+use crate::rust_tree::info::{dir_description, InfoComments};
+use crate::rust_tree::options::{
+    Column, Progress, SkipReason, SortKey, TraversalWarning, TreeOptions,
+};
+use crate::rust_tree::summary::describe;
+use crate::rust_tree::utils::{dir_id, replace_nonprintable, version_cmp, DirId};
+
+/// The least time between two calls to `on_progress`.
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Bookkeeping carried through a traversal.
+pub struct TraversalState {
+    /// (directories, files) listed so far.
+    pub stats: (u64, u64),
+    /// Depths at which the entry being traversed is the last of its siblings.
+    pub last_entry_depths: HashSet<usize>,
+    /// Directories on the current descent path, used to avoid following
+    /// symlinks into a loop.
+    pub ancestors: HashSet<DirId>,
+    /// Where the rendered tree is written.
+    pub out: Box<dyn Write>,
+    /// Lines and bytes written to `out` so far.
+    pub lines: usize,
+    pub bytes: u64,
+    /// Receives a `line<TAB>byte offset<TAB>path` record for every entry line.
+    pub linemap: Option<Box<dyn Write>>,
+    /// When set, traversal stops once this instant has passed.
+    pub deadline: Option<Instant>,
+    pub timed_out: bool,
+    /// Whether traversal stopped because `cancel` was set.
+    pub cancelled: bool,
+    /// When `on_progress` was first called, and how many entries traversal
+    /// has read. The clock is only read for `on_progress` and `timeout`, as
+    /// some targets such as wasm32-unknown-unknown have none.
+    pub started: Option<Instant>,
+    pub visited: u64,
+    /// When `on_progress` was last called.
+    pub last_progress: Option<Instant>,
+    /// Whether the tree was cut short after `head` lines.
+    pub truncated: bool,
+    /// Total size of the contents of each directory, for `disk_usage`.
+    pub dir_sizes: HashMap<PathBuf, u64>,
+    /// How many entries each filter left out, for `explain_filters`.
+    pub skipped: HashMap<SkipReason, u64>,
+    /// Directory descriptions already read, for `dir_descriptions`.
+    pub descriptions: HashMap<PathBuf, Option<String>>,
+    /// The gitignore rules of the root being listed, with `gitignore`.
+    pub git_ignores: Option<GitIgnores>,
+    /// The `git status` of the root being listed, with `git_status`.
+    pub git_statuses: Option<GitStatuses>,
+}
+
+impl Default for TraversalState {
+    fn default() -> Self {
+        TraversalState {
+            stats: (0, 0),
+            last_entry_depths: HashSet::new(),
+            ancestors: HashSet::new(),
+            out: Box::new(io::stdout()),
+            lines: 0,
+            bytes: 0,
+            linemap: None,
+            deadline: None,
+            timed_out: false,
+            cancelled: false,
+            started: None,
+            visited: 0,
+            last_progress: None,
+            truncated: false,
+            dir_sizes: HashMap::new(),
+            skipped: HashMap::new(),
+            descriptions: HashMap::new(),
+            git_ignores: None,
+            git_statuses: None,
+        }
+    }
+}
+
+impl TraversalState {
+    /// Writes one line of output, recording it in the line map when it
+    /// renders an entry.
+    pub fn write_line(&mut self, line: &str, entry: Option<&Path>) -> io::Result<()> {
+        if let (Some(linemap), Some(path)) = (self.linemap.as_mut(), entry) {
+            writeln!(
+                linemap,
+                "{}\t{}\t{}",
+                self.lines + 1,
+                self.bytes,
+                path.display()
+            )?;
+        }
+        writeln!(self.out, "{}", line)?;
+        self.lines += 1;
+        self.bytes += line.len() as u64 + 1;
+        Ok(())
+    }
+
+    /// Calls `on_progress` with the entries read so far, unless it was
+    /// called less than `PROGRESS_INTERVAL` ago and reading is not `done`.
+    fn report_progress(&mut self, current_dir: &Path, options: &TreeOptions, done: bool) {
+        let Some(on_progress) = &options.on_progress else {
+            return;
+        };
+        let now = Instant::now();
+        if !done
+            && self
+                .last_progress
+                .is_some_and(|previous| now - previous < PROGRESS_INTERVAL)
+        {
+            return;
+        }
+        self.last_progress = Some(now);
+        let started = *self.started.get_or_insert(now);
+        on_progress(&Progress {
+            entries: self.visited,
+            current_dir,
+            elapsed: now - started,
+            done,
+        });
+    }
+
+    /// Whether traversal has stopped early, checking the deadline and the
+    /// `cancel` flag and noting which stopped it.
+    fn should_stop(&mut self, options: &TreeOptions) -> bool {
+        if !self.stopped() {
+            if self
+                .deadline
+                .is_some_and(|deadline| Instant::now() >= deadline)
+            {
+                self.timed_out = true;
+            } else if options.is_cancelled() {
+                self.cancelled = true;
+            }
+        }
+        self.stopped()
+    }
+
+    /// Whether traversal stopped early, on the deadline or cancelled.
+    fn stopped(&self) -> bool {
+        self.timed_out || self.cancelled
+    }
+
+    /// The error to return for a traversal that stopped early.
+    fn stop_error(&self) -> Option<io::Error> {
+        if self.timed_out {
+            Some(timed_out())
+        } else if self.cancelled {
+            Some(cancelled())
+        } else {
+            None
+        }
+    }
+
+    /// Writes one line of the tree, truncated to `max_width`. Once `head`
+    /// lines have been written, writes nothing and sets `truncated`.
+    fn write_tree_line(
+        &mut self,
+        line: &str,
+        entry: Option<&Path>,
+        options: &TreeOptions,
+    ) -> io::Result<()> {
+        if self.truncated || options.head.is_some_and(|head| self.lines >= head) {
+            self.truncated = true;
+            return Ok(());
+        }
+        match options.max_width {
+            Some(width) => self.write_line(&truncate_line(line, width), entry),
+            None => self.write_line(line, entry),
+        }
+    }
+}
+
+/// The numbers of files and directories listed in `nodes` and below them.
+fn count_listed(nodes: &[TreeNode], options: &TreeOptions) -> (u64, u64) {
+    nodes.iter().fold((0, 0), |(files, dirs), node| {
+        if node.is_listed_as_dir(options) {
+            let (below_files, below_dirs) = node.counts.unwrap_or_default();
+            (files + below_files, dirs + 1 + below_dirs)
+        } else {
+            (files + 1, dirs)
+        }
+    })
+}
+
+/// Why the filters of `options` leave out `entry`, found at `depth`, if
+/// they do. Gitignore rules are not consulted.
+pub fn skip_reason(entry: &EntryInfo, options: &TreeOptions, depth: usize) -> Option<SkipReason> {
+    should_skip_entry(entry, options, depth, None)
+}
+
+fn should_skip_entry(
+    entry: &EntryInfo,
+    options: &TreeOptions,
+    depth: usize,
+    git_ignores: Option<&GitIgnores>,
+) -> Option<SkipReason> {
+    let file_name = entry.file_name.to_string_lossy();
+    // With full_match, patterns see the path below the root, e.g. `src/main.rs`
+    let relative = options
+        .full_match
+        .then(|| relative_path(&entry.path, depth));
+    let match_name = relative.as_deref().unwrap_or(&file_name);
+
+    // Check if hidden files and directories are allowed
+    if !options.all_files && file_name.starts_with('.') {
+        return Some(SkipReason::Hidden);
+    }
+    if let Some(level) = options.level {
+        if depth >= level as usize {
+            return Some(SkipReason::Depth);
+        }
+    }
+    if (!options.pattern_globs.is_empty() || !options.include_regexes.is_empty())
+        && !entry.is_dir
+        && !options
+            .pattern_globs
+            .iter()
+            .any(|pattern| glob_matches(pattern, &file_name, relative.as_deref()))
+        && !options
+            .include_regexes
+            .iter()
+            .any(|regex| regex.is_match(match_name))
+    {
+        return Some(SkipReason::NotMatched);
+    }
+    if (options.min_size.is_some() || options.max_size.is_some()) && !entry.is_dir {
+        let in_range = entry.size.is_some_and(|size| {
+            options.min_size.is_none_or(|min| size >= min)
+                && options.max_size.is_none_or(|max| size <= max)
+        });
+        if !in_range {
+            return Some(SkipReason::Size);
+        }
+    }
+    if options.nonempty && !entry.is_dir && entry.size == Some(0) {
+        return Some(SkipReason::Empty);
+    }
+    if options.has_date_filter() && !entry.is_dir {
+        let in_range = entry.modified.is_some_and(|modified| {
+            options.newer_than.is_none_or(|newer| modified > newer)
+                && options.older_than.is_none_or(|older| modified < older)
+        });
+        if !in_range {
+            return Some(SkipReason::Date);
+        }
+    }
+    if options
+        .exclude_patterns
+        .iter()
+        .any(|pattern| glob_matches(pattern, &file_name, relative.as_deref()))
+        || options
+            .exclude_regexes
+            .iter()
+            .any(|regex| regex.is_match(match_name))
+    {
+        return Some(SkipReason::Excluded);
+    }
+    if git_ignores.is_some_and(|git_ignores| git_ignores.is_ignored(&entry.path, entry.is_dir)) {
+        return Some(SkipReason::GitIgnored);
+    }
+    if options.dir_only && !entry.is_dir {
+        return Some(SkipReason::NotDirectory);
+    }
+    if !options.entry_types.is_empty()
+        && !entry.is_dir
+        && !options
+            .entry_types
+            .iter()
+            .any(|entry_type| entry_type.matches(entry))
+    {
+        return Some(SkipReason::WrongType);
+    }
+    if options
+        .filter
+        .as_ref()
+        .is_some_and(|filter| !filter(entry, depth))
+    {
+        return Some(SkipReason::Filtered);
+    }
+    // Last, as it reads the file
+    if !options.content_patterns.is_empty()
+        && !entry.is_dir
+        && !contents_match(entry, &options.content_patterns)
+    {
+        return Some(SkipReason::NoContentMatch);
+    }
+    for written in [&options.output, &options.linemap].into_iter().flatten() {
+        // Only names that match are worth resolving
+        if written.file_name() == Some(entry.file_name.as_os_str())
+            && fs::canonicalize(&entry.path).ok() == fs::canonicalize(written).ok()
+        {
+            return Some(SkipReason::OutputFile);
+        }
+    }
+    None
+}
+
+/// Whether a line of the file matches any of `patterns`. Only regular files,
+/// or links to them, are read, since opening a pipe or device could block.
+fn contents_match(entry: &EntryInfo, patterns: &[BytesRegex]) -> bool {
+    let is_file = match entry.kind {
+        EntryKind::File => true,
+        EntryKind::Symlink => entry.path.is_file(),
+        _ => false,
+    };
+    if !is_file {
+        return false;
+    }
+    let Ok(file) = File::open(&entry.path) else {
+        return false;
+    };
+    let mut reader = BufReader::new(file);
+    let mut line = Vec::new();
+    while matches!(reader.read_until(b'\n', &mut line), Ok(1..)) {
+        // Without its line ending, so that `$` matches at the end of a line
+        let text = line.strip_suffix(b"\n").unwrap_or(&line);
+        let text = text.strip_suffix(b"\r").unwrap_or(text);
+        if patterns.iter().any(|pattern| pattern.is_match(text)) {
+            return true;
+        }
+        line.clear();
+    }
+    false
+}
+
+/// The path of an entry `depth` levels below the root, relative to the root
+/// and separated by `/` on every platform.
+fn relative_path(path: &Path, depth: usize) -> String {
+    let components: Vec<_> = path.components().collect();
+    components[components.len().saturating_sub(depth + 1)..]
+        .iter()
+        .map(|component| component.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Matches a `-P` or `-I` glob against an entry's name. Given the entry's
+/// path below the root, globs containing a `/` are matched against that
+/// instead, with `*` stopping at `/` so that only `**` reaches into
+/// subdirectories, as in `.gitignore` files.
+fn glob_matches(pattern: &Pattern, file_name: &str, relative: Option<&str>) -> bool {
+    match relative {
+        Some(relative) if pattern.as_str().contains('/') => pattern.matches_with(
+            relative,
+            MatchOptions {
+                require_literal_separator: true,
+                ..MatchOptions::new()
+            },
+        ),
+        _ => pattern.matches(file_name),
+    }
+}
+
+/// Orders entries by `sort`, breaking ties by name, or `None` to keep the
+/// order they were read in.
+fn sort_order(sort: SortKey, ignore_case: bool) -> Option<Box<EntryOrder<'static>>> {
+    let fold = move |name: &OsStr| {
+        let name = name.to_string_lossy();
+        if ignore_case {
+            name.to_lowercase()
+        } else {
+            name.into_owned()
+        }
+    };
+    // Ties between names differing only in case are broken by the exact name
+    let by_name = move |a: &EntryInfo, b: &EntryInfo| {
+        if ignore_case {
+            fold(&a.file_name)
+                .cmp(&fold(&b.file_name))
+                .then_with(|| a.file_name.cmp(&b.file_name))
+        } else {
+            a.file_name.cmp(&b.file_name)
+        }
+    };
+    Some(match sort {
+        SortKey::Name => Box::new(by_name),
+        SortKey::Size => Box::new(move |a: &EntryInfo, b: &EntryInfo| {
+            Reverse(a.size.unwrap_or(0))
+                .cmp(&Reverse(b.size.unwrap_or(0)))
+                .then_with(|| by_name(a, b))
+        }),
+        SortKey::ModifiedTime => Box::new(move |a: &EntryInfo, b: &EntryInfo| {
+            Reverse(a.modified)
+                .cmp(&Reverse(b.modified))
+                .then_with(|| by_name(a, b))
+        }),
+        SortKey::ChangeTime => Box::new(move |a: &EntryInfo, b: &EntryInfo| {
+            Reverse(a.changed)
+                .cmp(&Reverse(b.changed))
+                .then_with(|| by_name(a, b))
+        }),
+        SortKey::Version => Box::new(move |a: &EntryInfo, b: &EntryInfo| {
+            version_cmp(&fold(&a.file_name), &fold(&b.file_name)).then_with(|| by_name(a, b))
+        }),
+        SortKey::Extension => Box::new(move |a: &EntryInfo, b: &EntryInfo| {
+            let extension = |entry: &EntryInfo| Path::new(&entry.file_name).extension().map(fold);
+            extension(a).cmp(&extension(b)).then_with(|| by_name(a, b))
+        }),
+        SortKey::None => return None,
+    })
+}
+
+/// How `options` orders the entries of a directory, before `group_by`: by
+/// `sort_by` or `sort`, with directories or files first. `None` when they
+/// are listed in the order read.
+fn entry_order(options: &TreeOptions) -> Option<Box<EntryOrder<'_>>> {
+    let sort: Option<Box<EntryOrder>> = match &options.sort_by {
+        Some(compare) => Some(Box::new(|a: &EntryInfo, b: &EntryInfo| compare(a, b))),
+        None => sort_order(options.sort, options.ignore_case_sort),
+    };
+    if !options.dirs_first && !options.files_first {
+        return sort;
+    }
+    // Directories and files each keep the sort order
+    let dirs_first = options.dirs_first;
+    Some(Box::new(move |a: &EntryInfo, b: &EntryInfo| {
+        (a.is_dir != dirs_first)
+            .cmp(&(b.is_dir != dirs_first))
+            .then_with(|| sort.as_ref().map_or(Ordering::Equal, |sort| sort(a, b)))
+    }))
+}
+
+/// The directories above one being read, innermost first, to tell symlink
+/// loops. Shared by the directories below.
+pub(crate) struct Ancestors {
+    id: DirId,
+    parent: Option<Arc<Ancestors>>,
+}
+
+impl Ancestors {
+    /// `ancestors` with `id`, if known, added below them.
+    pub(crate) fn below(
+        ancestors: &Option<Arc<Ancestors>>,
+        id: Option<DirId>,
+    ) -> Option<Arc<Ancestors>> {
+        match id {
+            Some(id) => Some(Arc::new(Ancestors {
+                id,
+                parent: ancestors.clone(),
+            })),
+            None => ancestors.clone(),
+        }
+    }
+
+    pub(crate) fn contains(mut ancestors: &Option<Arc<Ancestors>>, id: &DirId) -> bool {
+        while let Some(ancestor) = ancestors {
+            if &ancestor.id == id {
+                return true;
+            }
+            ancestors = &ancestor.parent;
+        }
+        false
+    }
+}
+
+impl Drop for Ancestors {
+    /// Drops the ancestors no other directory shares one at a time, as
+    /// dropping them recursively could overflow the stack.
+    fn drop(&mut self) {
+        let mut parent = self.parent.take();
+        while let Some(ancestor) = parent {
+            parent = match Arc::try_unwrap(ancestor) {
+                Ok(mut ancestor) => ancestor.parent.take(),
+                Err(_) => None,
+            };
+        }
+    }
+}
+
+/// A directory for `directory_sizes` to read.
+struct SizeTask {
+    path: PathBuf,
+    /// Where its parent is in `DirSizes::dirs`.
+    parent: Option<usize>,
+    depth: usize,
+    ancestors: Option<Arc<Ancestors>>,
+}
+
+/// What `directory_sizes` reads directories with, and has read so far.
+struct DirSizes<'a> {
+    source: &'a dyn EntrySource,
+    options: &'a TreeOptions,
+    git_ignores: Option<&'a GitIgnores>,
+    /// Each directory read: its path, where its parent is, and the total
+    /// size of the files directly inside it. Parents come before the
+    /// directories inside them.
+    dirs: Mutex<Vec<(PathBuf, Option<usize>, u64)>>,
+}
+
+impl DirSizes<'_> {
+    /// Reads the directory of `task`, recording it along with the sizes of
+    /// the files inside it, and returns the directories inside it to read.
+    fn read(&self, task: SizeTask) -> Vec<SizeTask> {
+        // The listing that follows stops on the flag too
+        if self.options.is_cancelled() {
+            return Vec::new();
+        }
+        let Ok(entries) = self.source.read_entries(&task.path) else {
+            return Vec::new();
+        };
+        if let Some(git_ignores) = self.git_ignores {
+            git_ignores.load_dir(self.source, &task.path);
+        }
+        let mut files = 0;
+        let mut below = Vec::new();
+        for entry in entries {
+            if matches!(
+                should_skip_entry(&entry, self.options, task.depth, self.git_ignores),
+                Some(
+                    SkipReason::Hidden
+                        | SkipReason::Excluded
+                        | SkipReason::GitIgnored
+                        | SkipReason::NotMatched
+                        | SkipReason::Size
+                        | SkipReason::Empty
+                        | SkipReason::Date
+                        | SkipReason::NoContentMatch
+                        | SkipReason::OutputFile
+                        | SkipReason::Filtered
+                )
+            ) {
+                continue;
+            }
+            if entry.kind == EntryKind::Directory || (self.options.follow_links && entry.is_dir) {
+                let id = self.source.entry_dir_id(&entry);
+                if id
+                    .as_ref()
+                    .is_some_and(|id| Ancestors::contains(&task.ancestors, id))
+                {
+                    continue;
+                }
+                below.push((entry.path, id));
+            } else {
+                files += entry.size.unwrap_or(0);
+            }
+        }
+        let mut dirs = self.dirs.lock().unwrap();
+        dirs.push((task.path, task.parent, files));
+        let index = dirs.len() - 1;
+        below
+            .into_iter()
+            .map(|(path, id)| SizeTask {
+                path,
+                parent: Some(index),
+                depth: task.depth + 1,
+                ancestors: Ancestors::below(&task.ancestors, id),
+            })
+            .collect()
+    }
+}
+
+/// Reads the directories of `task` and below on `scope`'s thread pool, each
+/// as a task of its own that any idle thread may take up.
+#[cfg(feature = "parallel")]
+fn read_sizes_in<'scope>(
+    scope: &rayon::Scope<'scope>,
+    dir_sizes: &'scope DirSizes<'scope>,
+    task: SizeTask,
+) {
+    for below in dir_sizes.read(task) {
+        scope.spawn(move |scope| read_sizes_in(scope, dir_sizes, below));
+    }
+}
+
+/// Sums the sizes of the files below `root` into `sizes`, one total per
+/// directory, and returns the total for `root`. Entries are filtered as in
+/// the listing, except that the depth limit is ignored so totals stay
+/// complete.
+///
+/// With the `parallel` feature, directories are read on a pool of
+/// `options.threads` threads, all available cores by default. Either way
+/// the directories still to read are queued rather than recursed into, so
+/// that any depth of nesting can be totalled.
+fn directory_sizes(
+    source: &dyn EntrySource,
+    root: &Path,
+    options: &TreeOptions,
+    git_ignores: Option<&GitIgnores>,
+    sizes: &mut HashMap<PathBuf, u64>,
+) -> u64 {
+    let dir_sizes = DirSizes {
+        source,
+        options,
+        git_ignores,
+        dirs: Mutex::default(),
+    };
+    let task = SizeTask {
+        path: root.to_path_buf(),
+        parent: None,
+        depth: 0,
+        ancestors: Ancestors::below(&None, source.dir_id(root)),
+    };
+    #[cfg(feature = "parallel")]
+    {
+        let read_all = || rayon::scope(|scope| read_sizes_in(scope, &dir_sizes, task));
+        let pool = options.threads.and_then(|threads| {
+            rayon::ThreadPoolBuilder::new()
+                .num_threads(threads)
+                .build()
+                .ok()
+        });
+        match pool {
+            Some(pool) => pool.install(read_all),
+            None => read_all(),
+        }
+    }
+    #[cfg(not(feature = "parallel"))]
+    {
+        let mut pending = vec![task];
+        while let Some(task) = pending.pop() {
+            pending.extend(dir_sizes.read(task));
+        }
+    }
+
+    // Directories come after their parents, so each total is complete by
+    // the time it is added to its parent's
+    let dirs = dir_sizes.dirs.into_inner().unwrap();
+    let mut totals: Vec<u64> = dirs.iter().map(|(_, _, files)| *files).collect();
+    for (index, (_, parent, _)) in dirs.iter().enumerate().rev() {
+        if let Some(parent) = parent {
+            totals[*parent] += totals[index];
+        }
+    }
+    let root_total = totals.first().copied().unwrap_or(0);
+    sizes.extend(dirs.into_iter().map(|(path, _, _)| path).zip(totals));
+    root_total
+}
+
+/// Passes `warning` to `on_error` and `on_warning`, or prints it on stderr
+/// when neither is set to handle it.
+fn report_warning(warning: TraversalWarning, options: &TreeOptions) {
+    let mut handled = false;
+    if let (Some(on_error), TraversalWarning::Unreadable { path, error }) =
+        (&options.on_error, &warning)
+    {
+        on_error(path, error);
+        handled = true;
+    }
+    match &options.on_warning {
+        Some(on_warning) => on_warning(&warning),
+        None if !handled => eprintln!("Warning: {}", warning),
+        None => {}
+    }
+}
+
+/// Reports `error` from reading `path` as a `TraversalWarning`.
+fn report_error(path: &Path, error: io::Error, options: &TreeOptions) {
+    let path = path.to_path_buf();
+    report_warning(TraversalWarning::Unreadable { path, error }, options);
+}
+
+/// The filesystem as the source of a listing with `options`, looking up
+/// each entry's metadata only if the listing needs it, and its ACL only if
+/// permissions are shown.
+fn filesystem(options: &TreeOptions) -> &'static dyn EntrySource {
+    if options.shows_permissions() {
+        &FilesystemAcls
+    } else if options.needs_metadata() {
+        &Filesystem
+    } else {
+        &FilesystemKinds
+    }
+}
+
+/// Runs `git status` for `root` with `git_status`, reporting why when it
+/// cannot, e.g. outside a repository.
+fn load_git_statuses(root: &Path, options: &TreeOptions) -> Option<GitStatuses> {
+    if !options.git_status {
+        return None;
+    }
+    GitStatuses::load(root)
+        .map_err(|error| {
+            let path = root.to_path_buf();
+            report_warning(TraversalWarning::GitStatus { path, error }, options);
+        })
+        .ok()
+}
+
+/// Prints the entries below `current_path`, whose own entries are at
+/// `depth`, to `state.out`, without a root line or report.
+pub fn traverse_directory(
     current_path: &Path,
     options: &TreeOptions,
     depth: usize,
-    _is_last: bool,
-    stats: &mut (u64, u64),
-    last_entry_depths: &mut HashSet<usize>,
+    state: &mut TraversalState,
 ) -> std::io::Result<()> {
-    let mut entries: Vec<_> = fs::read_dir(current_path)?.collect();
-    entries.sort_by_key(|entry| entry.as_ref().unwrap().file_name().to_owned());
-
-    let last_index = entries.len().saturating_sub(1);
-
-    for (index, entry) in entries.into_iter().enumerate() {
-        let entry = entry?;
-        let path = entry.path();
-        let is_entry_last = index == last_index;
-
-        // Check if hidden files and directories are allowed
-        let is_hidden = path
-            .file_name()
-            .map(|name| name.to_string_lossy().starts_with('.'))
-            .unwrap_or(false);
-        if !options.all_files && is_hidden {
-            continue;
+    if options.gitignore && state.git_ignores.is_none() {
+        state.git_ignores = Some(GitIgnores::new(current_path));
+    }
+    if state.git_statuses.is_none() {
+        state.git_statuses = load_git_statuses(current_path, options);
+    }
+    let source = filesystem(options);
+    let entries = source.read_entries(current_path);
+    let (nodes, omitted) = build_children(source, current_path, entries, options, depth, state)?;
+    render_children(&nodes, omitted, options, depth, state)
+}
+
+/// Filters and sorts `entries`, as read from `current_path`, as they are
+/// listed, and returns them along with the number of entries left out by
+/// `auto_limit` and `limit`.
+fn listed_entries(
+    source: &dyn EntrySource,
+    current_path: &Path,
+    entries: io::Result<Vec<EntryInfo>>,
+    options: &TreeOptions,
+    depth: usize,
+    state: &mut TraversalState,
+) -> io::Result<(Vec<EntryInfo>, usize)> {
+    let mut entries = match entries {
+        Ok(entries) => entries,
+        // Keep listing the rest of the tree when a subdirectory is unreadable
+        Err(err) if depth > 0 => {
+            report_error(current_path, err, options);
+            return Ok((Vec::new(), 0));
         }
-        if options.level.is_some() && depth >= options.level.unwrap() as usize {
-            continue;
+        Err(err) => return Err(err),
+    };
+    state.visited += entries.len() as u64;
+    state.report_progress(current_path, options, false);
+    if let Some(git_ignores) = &state.git_ignores {
+        git_ignores.load_dir(source, current_path);
+    }
+    entries.retain(|entry| is_listed(entry, options, depth, state));
+    if let Some(order) = entry_order(options) {
+        entries.sort_by(|a, b| order(a, b));
+    }
+    if let Some(group_by) = options.group_by {
+        // Stable, so each section keeps the requested sort order
+        entries.sort_by_cached_key(|entry| group_of(entry, group_by));
+    }
+    let mut omitted = 0;
+    if let Some(limit) = options.auto_limit {
+        if entries.len() > limit {
+            omitted = entries.len() - limit;
+            entries.truncate(limit);
+            let warning = TraversalWarning::Truncated {
+                path: current_path.to_path_buf(),
+                entries: limit + omitted,
+                listed: limit,
+            };
+            report_warning(warning, options);
+        }
+    }
+    if let Some(limit) = options.limit {
+        if entries.len() > limit {
+            omitted += entries.len() - limit;
+            entries.truncate(limit);
         }
-        if options.pattern_glob.is_some() && !path.is_dir() {
-            let pattern_glob = options.pattern_glob.as_ref().unwrap();
-            let file_name = path.file_name().unwrap().to_string_lossy();
-            if !pattern_glob.matches(&file_name) {
+    }
+    Ok((entries, omitted))
+}
+
+/// Whether `entry`, at `depth`, is listed, counting it against the filter
+/// that left it out if not.
+fn is_listed(
+    entry: &EntryInfo,
+    options: &TreeOptions,
+    depth: usize,
+    state: &mut TraversalState,
+) -> bool {
+    let git_ignores = state.git_ignores.as_ref();
+    let Some(reason) = should_skip_entry(entry, options, depth, git_ignores) else {
+        return true;
+    };
+    if let Some(on_skip) = &options.on_skip {
+        on_skip(&entry.path, reason);
+    }
+    *state.skipped.entry(reason).or_default() += 1;
+    false
+}
+
+/// The comments of the `.info` file in `dir`, with `print_info`.
+fn info_comments(
+    source: &dyn EntrySource,
+    dir: &Path,
+    options: &TreeOptions,
+) -> Option<InfoComments> {
+    if !options.print_info {
+        return None;
+    }
+    source
+        .read_to_string(&dir.join(".info"))
+        .ok()
+        .map(|text| InfoComments::parse(&text))
+}
+
+/// The node listing `entry`, with its git status, its comment from the
+/// `.info` comments of its directory, and a directory's description, as
+/// `options` ask.
+fn new_node(
+    source: &dyn EntrySource,
+    entry: EntryInfo,
+    info_comments: Option<&InfoComments>,
+    options: &TreeOptions,
+    state: &mut TraversalState,
+) -> TreeNode {
+    let mut node = TreeNode::new(entry);
+    if let Some(git_statuses) = &state.git_statuses {
+        node.git_status = git_statuses.status(&node.entry.path).map(String::from);
+    }
+    if let Some(comments) =
+        info_comments.and_then(|info| info.comments_for(&node.name, node.entry.is_dir))
+    {
+        node.comments = comments.to_vec();
+    }
+    if options.dir_descriptions && node.is_listed_as_dir(options) {
+        // Cached, as several roots may list the same directories
+        node.description = state
+            .descriptions
+            .entry(node.entry.path.clone())
+            .or_insert_with(|| dir_description(source, &node.entry.path))
+            .clone();
+    }
+    node
+}
+
+/// A directory `build_children` is turning into nodes.
+struct PendingDir {
+    /// The directory's own node, to receive the nodes as its children, and
+    /// its identity. `None` for the directory `build_children` started at.
+    node: Option<(TreeNode, Option<DirId>)>,
+    /// Its listed entries not yet turned into nodes.
+    entries: std::vec::IntoIter<EntryInfo>,
+    info_comments: Option<InfoComments>,
+    nodes: Vec<TreeNode>,
+    omitted: usize,
+    depth: usize,
+}
+
+impl PendingDir {
+    /// Filters and sorts `entries`, as read from `path`, whose entries are
+    /// at `depth`, ready to be turned into nodes.
+    fn open(
+        source: &dyn EntrySource,
+        path: &Path,
+        entries: io::Result<Vec<EntryInfo>>,
+        options: &TreeOptions,
+        depth: usize,
+        state: &mut TraversalState,
+    ) -> io::Result<Self> {
+        let (entries, omitted) = listed_entries(source, path, entries, options, depth, state)?;
+        let info_comments = info_comments(source, path, options);
+        Ok(PendingDir {
+            node: None,
+            nodes: Vec::with_capacity(entries.len()),
+            entries: entries.into_iter(),
+            info_comments,
+            omitted,
+            depth,
+        })
+    }
+}
+
+/// Turns `entries`, as read from `current_path`, into nodes, filtered and
+/// sorted as they are listed, descending into directories, along with the
+/// number of entries left out by `auto_limit` and `limit`. Stops early, setting
+/// `state.timed_out` or `state.cancelled`, once the deadline has passed or
+/// the scan is cancelled.
+///
+/// Directories being descended into are kept on a stack of their own
+/// rather than the call stack, so that any depth of nesting can be listed.
+fn build_children(
+    source: &dyn EntrySource,
+    current_path: &Path,
+    entries: io::Result<Vec<EntryInfo>>,
+    options: &TreeOptions,
+    depth: usize,
+    state: &mut TraversalState,
+) -> io::Result<(Vec<TreeNode>, usize)> {
+    let mut stack = vec![PendingDir::open(
+        source,
+        current_path,
+        entries,
+        options,
+        depth,
+        state,
+    )?];
+    while let Some(dir) = stack.last_mut() {
+        let entry = if state.should_stop(options) {
+            None
+        } else {
+            dir.entries.next()
+        };
+        let Some(entry) = entry else {
+            // All of the directory's entries are nodes, so it is done
+            let PendingDir {
+                node,
+                nodes,
+                omitted,
+                depth,
+                ..
+            } = stack.pop().expect("the directory is on the stack");
+            let (nodes, omitted) = hoist_shallow(nodes, omitted, depth, options, state);
+            let Some((mut node, id)) = node else {
+                return Ok((nodes, omitted));
+            };
+            if let Some(id) = &id {
+                state.ancestors.remove(id);
+            }
+            (node.children, node.omitted) = (nodes, omitted);
+            // Below the level limit the directory's contents are not listed
+            let contents_listed = options.level.is_none_or(|level| depth < level as usize);
+            if options.dir_counts && contents_listed {
+                node.counts = Some(count_listed(&node.children, options));
+            }
+            if options.prunes_empty_dirs() && node.children.is_empty() && node.omitted == 0 {
+                if let Some(on_skip) = &options.on_skip {
+                    on_skip(&node.entry.path, SkipReason::Pruned);
+                }
+                *state.skipped.entry(SkipReason::Pruned).or_default() += 1;
                 continue;
             }
-        }
-        if options.dir_only && !path.is_dir() {
+            if let Some(parent) = stack.last_mut() {
+                parent.nodes.push(node);
+            }
             continue;
+        };
+        let mut node = new_node(source, entry, dir.info_comments.as_ref(), options, state);
+        if node.is_listed_as_dir(options) {
+            // A directory we are already inside, reached again through a
+            // symlink, would loop forever
+            let id = source.entry_dir_id(&node.entry);
+            node.recursive = id.as_ref().is_some_and(|id| state.ancestors.contains(id));
+            // Read once, both to count against the file limit and to list
+            let mut entries = None;
+            if let Some(limit) = options.file_limit {
+                let read = source.read_entries(&node.entry.path);
+                let count = read.as_ref().map_or(0, Vec::len);
+                node.not_opened = (count > limit).then_some(count);
+                entries = Some(read);
+            }
+            // Otherwise listed without its contents
+            if node.not_opened.is_none() && !node.recursive {
+                let entries = entries.unwrap_or_else(|| source.read_entries(&node.entry.path));
+                state.ancestors.extend(id.iter().cloned());
+                let depth = dir.depth + 1;
+                let mut below =
+                    PendingDir::open(source, &node.entry.path, entries, options, depth, state)?;
+                below.node = Some((node, id));
+                stack.push(below);
+                continue;
+            }
+        }
+        dir.nodes.push(node);
+    }
+    unreachable!("the first directory returns its nodes once done")
+}
+
+/// With `min_depth`, replaces the directories among `nodes`, the entries of
+/// a directory at `depth`, with their contents where they are too shallow
+/// to be listed, and adds what they omitted to `omitted`.
+fn hoist_shallow(
+    nodes: Vec<TreeNode>,
+    omitted: usize,
+    depth: usize,
+    options: &TreeOptions,
+    state: &mut TraversalState,
+) -> (Vec<TreeNode>, usize) {
+    if options
+        .min_depth
+        .is_none_or(|min_depth| depth + 1 >= min_depth)
+    {
+        return (nodes, omitted);
+    }
+    // Directories give way to their contents, which are named by their
+    // path from here
+    let mut hoisted = Vec::new();
+    let mut omitted = omitted;
+    for mut node in nodes {
+        if node.is_listed_as_dir(options) {
+            omitted += node.omitted;
+            let children = std::mem::take(&mut node.children);
+            hoisted.extend(children.into_iter().map(|mut child| {
+                child.name = format!("{}/{}", node.name, child.name);
+                child
+            }));
+        } else {
+            if let Some(on_skip) = &options.on_skip {
+                on_skip(&node.entry.path, SkipReason::Shallow);
+            }
+            *state.skipped.entry(SkipReason::Shallow).or_default() += 1;
+        }
+    }
+    (hoisted, omitted)
+}
+
+/// A directory `render_children` is printing the entries of.
+struct RenderingDir<'a> {
+    nodes: &'a [TreeNode],
+    /// The index in `nodes` of the next entry to print.
+    next: usize,
+    omitted: usize,
+    depth: usize,
+    /// The `--group-by` section of each of `nodes`.
+    groups: Option<Vec<(u8, String)>>,
+}
+
+impl<'a> RenderingDir<'a> {
+    fn new(nodes: &'a [TreeNode], omitted: usize, depth: usize, options: &TreeOptions) -> Self {
+        let groups = options.group_by.map(|group_by| {
+            nodes
+                .iter()
+                .map(|node| group_of(&node.entry, group_by))
+                .collect()
+        });
+        RenderingDir {
+            nodes,
+            next: 0,
+            omitted,
+            depth,
+            groups,
         }
+    }
+}
+
+/// Prints `nodes`, the entries of a directory at `depth`, and everything
+/// below them, followed by a note on the `omitted` entries if any. Like
+/// `build_children`, keeps the directories it is inside on a stack of its
+/// own.
+fn render_children(
+    nodes: &[TreeNode],
+    omitted: usize,
+    options: &TreeOptions,
+    depth: usize,
+    state: &mut TraversalState,
+) -> io::Result<()> {
+    let columns = listed_columns(options, state);
+    let mut stack = vec![RenderingDir::new(nodes, omitted, depth, options)];
+
+    while let Some(dir) = stack.last_mut() {
+        let index = dir.next;
+        let Some(node) = dir.nodes.get(index) else {
+            // The note on omitted entries follows the directory's last entry
+            let depth = dir.depth;
+            if dir.omitted > 0 {
+                let prefix = if options.no_indent {
+                    ""
+                } else {
+                    options.charset.last_branch.as_str()
+                };
+                let line = format!(
+                    "{}{}… and {} more",
+                    indentation(depth, options, state),
+                    prefix,
+                    dir.omitted
+                );
+                state.write_tree_line(&line, None, options)?;
+            }
+            stack.pop();
+            // Its entry is no longer the one being printed below
+            if !stack.is_empty() {
+                state.last_entry_depths.remove(&(depth - 1));
+            }
+            continue;
+        };
+        dir.next += 1;
+        let depth = dir.depth;
+        let is_entry_last = index == dir.nodes.len() - 1 && dir.omitted == 0;
+        let indent = indentation(depth, options, state);
 
-        // Print indentation
-        let root_path_buf = root_path.as_ref().to_path_buf();
-        let current_path_buf = current_path.to_path_buf();
-        if !options.no_indent && current_path_buf != root_path_buf {
-            for i in 0..depth {
-                if last_entry_depths.contains(&i) {
-                    print!("    ");
+        // Print a section header where a new --group-by section starts
+        if let Some(groups) = &dir.groups {
+            if index == 0 || groups[index - 1] != groups[index] {
+                let marker = if options.no_indent {
+                    String::new()
                 } else {
-                    print!("│   ");
+                    format!("{} ", options.charset.vertical.trim_end())
+                };
+                let header = format!("{}{}[{}]", indent, marker, groups[index].1);
+                state.write_tree_line(&header, None, options)?;
+            }
+        }
+
+        render_entry(node, is_entry_last, &indent, &columns, options, state)?;
+        if state.truncated {
+            state
+                .last_entry_depths
+                .retain(|&below| below < stack[0].depth);
+            return Ok(());
+        }
+
+        if node.is_listed_as_dir(options) {
+            if is_entry_last {
+                state.last_entry_depths.insert(depth);
+            }
+            let below = RenderingDir::new(&node.children, node.omitted, depth + 1, options);
+            stack.push(below);
+        }
+    }
+
+    Ok(())
+}
+
+/// How deep `stream_children` keeps directories open to read as it prints
+/// them. Those deeper are read whole when opened, so that deep trees do not
+/// run out of file handles.
+const OPEN_DEPTH: usize = 128;
+
+/// The listed entries of a directory `stream_children` is printing, in the
+/// order listed.
+enum ListedEntries<'a> {
+    /// Filtered as they are read, in the order read.
+    Read {
+        entries: EntryStream<'a>,
+        path: PathBuf,
+    },
+    /// Read and filtered when the directory was opened, then ordered.
+    Sorted(SortedEntries<'a>),
+}
+
+impl<'a> ListedEntries<'a> {
+    /// Starts reading the entries of `path`, at `depth`, in `order`. With an
+    /// order, or from `OPEN_DEPTH` down, they are all read now.
+    fn open(
+        source: &'a dyn EntrySource,
+        path: &Path,
+        order: Option<&'a EntryOrder<'a>>,
+        options: &TreeOptions,
+        depth: usize,
+        state: &mut TraversalState,
+    ) -> io::Result<Self> {
+        let entries = match source.stream_entries(path) {
+            Ok(entries) => entries,
+            // Keep listing the rest of the tree when a subdirectory is unreadable
+            Err(err) if depth > 0 => {
+                report_error(path, err, options);
+                return Ok(ListedEntries::Sorted(SortedEntries::Memory(
+                    Vec::new().into_iter(),
+                )));
+            }
+            Err(err) => return Err(err),
+        };
+        if let Some(git_ignores) = &state.git_ignores {
+            git_ignores.load_dir(source, path);
+        }
+        let mut read = ListedEntries::Read {
+            entries,
+            path: path.to_path_buf(),
+        };
+        let (order, chunk) = match order {
+            Some(order) => (order, options.external_sort.unwrap_or(usize::MAX)),
+            None if depth < OPEN_DEPTH => return Ok(read),
+            None => (&in_order_read as &EntryOrder, usize::MAX),
+        };
+        let listed = std::iter::from_fn(|| read.next_read(options, depth, state));
+        Ok(ListedEntries::Sorted(SortedEntries::sort(
+            listed, chunk, order,
+        )?))
+    }
+
+    /// The next listed entry, or `None` once all have been.
+    fn next(
+        &mut self,
+        options: &TreeOptions,
+        depth: usize,
+        state: &mut TraversalState,
+    ) -> io::Result<Option<EntryInfo>> {
+        match self {
+            ListedEntries::Read { .. } => Ok(self.next_read(options, depth, state)),
+            ListedEntries::Sorted(entries) => entries.next().transpose(),
+        }
+    }
+
+    /// Reads up to the next listed entry, reporting why if reading fails.
+    fn next_read(
+        &mut self,
+        options: &TreeOptions,
+        depth: usize,
+        state: &mut TraversalState,
+    ) -> Option<EntryInfo> {
+        let ListedEntries::Read { entries, path } = self else {
+            return None;
+        };
+        for entry in entries.by_ref() {
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(err) => {
+                    report_error(path, err, options);
+                    *entries = Box::new(std::iter::empty());
+                    return None;
                 }
+            };
+            state.visited += 1;
+            state.report_progress(path, options, false);
+            if is_listed(&entry, options, depth, state) {
+                return Some(entry);
             }
         }
+        None
+    }
+}
+
+/// Keeps entries in the order read.
+fn in_order_read(_: &EntryInfo, _: &EntryInfo) -> Ordering {
+    Ordering::Equal
+}
+
+/// A directory `stream_children` is printing the entries of.
+struct StreamingDir<'a> {
+    entries: ListedEntries<'a>,
+    /// Its next entry, read ahead to tell whether the one being printed is
+    /// its last.
+    next: Option<EntryInfo>,
+    info_comments: Option<InfoComments>,
+    depth: usize,
+    /// Its identity, forgotten as an ancestor once it is printed.
+    id: Option<DirId>,
+}
+
+impl<'a> StreamingDir<'a> {
+    /// Opens `path`, with identity `id`, whose entries are at `depth`,
+    /// reading up to its first listed entry.
+    fn open(
+        source: &'a dyn EntrySource,
+        path: &Path,
+        id: Option<DirId>,
+        order: Option<&'a EntryOrder<'a>>,
+        options: &TreeOptions,
+        depth: usize,
+        state: &mut TraversalState,
+    ) -> io::Result<Self> {
+        let mut entries = ListedEntries::open(source, path, order, options, depth, state)?;
+        Ok(StreamingDir {
+            next: entries.next(options, depth, state)?,
+            entries,
+            info_comments: info_comments(source, path, options),
+            depth,
+            id,
+        })
+    }
+}
 
-        // Print file/directory name with prefix
-        let prefix = if options.no_indent {
-            ""
-        } else if is_entry_last {
-            "└── "
+/// The columns of each entry's line, leaving out the git status when that
+/// of the root could not be loaded, as it would be blank throughout.
+fn listed_columns(options: &TreeOptions, state: &TraversalState) -> Vec<Column> {
+    let mut columns = active_columns(options);
+    if state.git_statuses.is_none() {
+        columns.retain(|&column| column != Column::GitStatus);
+    }
+    columns
+}
+
+/// Prints the entries below `root`, opened with `StreamingDir::open`, as
+/// each directory is read, for listings that `TreeOptions::streams`. Only
+/// the directories being printed are held in memory, each reading its
+/// entries as it goes or merging them from disk in `order`.
+fn stream_children<'a>(
+    source: &'a dyn EntrySource,
+    root: StreamingDir<'a>,
+    order: Option<&'a EntryOrder<'a>>,
+    options: &TreeOptions,
+    state: &mut TraversalState,
+) -> io::Result<()> {
+    let columns = listed_columns(options, state);
+    let mut stack = vec![root];
+    while let Some(dir) = stack.last_mut() {
+        let entry = if state.should_stop(options) || state.truncated {
+            None
         } else {
-            "├── "
+            dir.next.take()
         };
+        let Some(entry) = entry else {
+            let dir = stack.pop().expect("the directory is on the stack");
+            if let Some(id) = &dir.id {
+                state.ancestors.remove(id);
+            }
+            // Its entry is no longer the one being printed below
+            if !stack.is_empty() {
+                state.last_entry_depths.remove(&(dir.depth - 1));
+            }
+            continue;
+        };
+        let depth = dir.depth;
+        dir.next = dir.entries.next(options, depth, state)?;
+        let is_entry_last = dir.next.is_none();
+        let mut node = new_node(source, entry, dir.info_comments.as_ref(), options, state);
+        let is_dir = node.is_listed_as_dir(options);
+        let id = is_dir.then(|| source.entry_dir_id(&node.entry)).flatten();
+        // A directory we are already inside, reached again through a
+        // symlink, would loop forever
+        node.recursive = id.as_ref().is_some_and(|id| state.ancestors.contains(id));
+        let indent = indentation(depth, options, state);
+        render_entry(&node, is_entry_last, &indent, &columns, options, state)?;
+        if state.truncated || !is_dir || node.recursive {
+            continue;
+        }
+        if is_entry_last {
+            state.last_entry_depths.insert(depth);
+        }
+        state.ancestors.extend(id.iter().cloned());
+        let below = StreamingDir::open(
+            source,
+            &node.entry.path,
+            id,
+            order,
+            options,
+            depth + 1,
+            state,
+        )?;
+        stack.push(below);
+    }
+    Ok(())
+}
 
-        let name = if options.full_path {
-            path.display().to_string()
-        } else {
-            entry.file_name().to_string_lossy().to_string()
+/// Prints `node` after `indent`, as the last entry of its directory or
+/// not, followed by its `.info` comments, and counts it.
+fn render_entry(
+    node: &TreeNode,
+    is_last: bool,
+    indent: &str,
+    columns: &[Column],
+    options: &TreeOptions,
+    state: &mut TraversalState,
+) -> io::Result<()> {
+    // Print file/directory name with prefix
+    let prefix = if options.no_indent {
+        ""
+    } else if is_last {
+        options.charset.last_branch.as_str()
+    } else {
+        options.charset.branch.as_str()
+    };
+    let name = match &options.formatter {
+        Some(formatter) => formatter.format_entry(node, options, &state.dir_sizes),
+        None => format_entry_line(node, columns, options, &state.dir_sizes),
+    };
+    let line = format!("{}{}{}", indent, prefix, name);
+    state.write_tree_line(&line, Some(&node.entry.path), options)?;
+    if state.truncated {
+        return Ok(());
+    }
+    if node.is_listed_as_dir(options) {
+        state.stats.0 += 1;
+    } else {
+        state.stats.1 += 1;
+    }
+
+    // Comments from the .info file, continuing the entry's indentation
+    let continuation = if options.no_indent {
+        String::new()
+    } else if is_last {
+        options.charset.blank()
+    } else {
+        options.charset.vertical.clone()
+    };
+    for comment in &node.comments {
+        let line = format!("{}{}  {}", indent, continuation, comment);
+        state.write_tree_line(&line, None, options)?;
+    }
+    Ok(())
+}
+
+/// The indentation of entries at `depth`, continuing the lines of ancestors
+/// that have siblings below them.
+fn indentation(depth: usize, options: &TreeOptions, state: &TraversalState) -> String {
+    let mut indent = String::new();
+    if !options.no_indent {
+        for i in 0..depth {
+            if state.last_entry_depths.contains(&i) {
+                indent.push_str(&options.charset.blank());
+            } else {
+                indent.push_str(&options.charset.vertical);
+            }
+        }
+    }
+    indent
+}
+
+pub fn list_directory<P: AsRef<Path>>(path: P, options: &TreeOptions) -> std::io::Result<()> {
+    list_directories(&[path], options)
+}
+
+/// Reads the tree below `path` into nodes, filtered and sorted as
+/// `list_directory` would list them, so callers can work with the entries
+/// instead of the rendered text. Each node owns its name, its entry's kind,
+/// size, mtime and other metadata, and its children.
+pub fn build_tree<P: AsRef<Path>>(path: P, options: &TreeOptions) -> std::io::Result<TreeNode> {
+    build_tree_from_source(&Filesystem, path.as_ref(), options)
+}
+
+/// Reads the tree below `root` as read from `source` into nodes, as
+/// `build_tree` does for the filesystem.
+pub fn build_tree_from_source(
+    source: &dyn EntrySource,
+    root: &Path,
+    options: &TreeOptions,
+) -> std::io::Result<TreeNode> {
+    let mut state = TraversalState {
+        deadline: options.timeout.map(|timeout| Instant::now() + timeout),
+        git_ignores: options.gitignore.then(|| GitIgnores::new(root)),
+        git_statuses: load_git_statuses(root, options),
+        ..Default::default()
+    };
+    let tree = build_root(source, root, &root_name(root), options, &mut state)?;
+    state.report_progress(root, options, true);
+    match state.stop_error() {
+        Some(err) => Err(err),
+        None => Ok(tree),
+    }
+}
+
+/// An entry yielded by `TreeWalker`.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct WalkEntry {
+    pub entry: EntryInfo,
+    /// How far below the root the entry is, 0 for the root's children.
+    pub depth: usize,
+    /// Whether the entry is the last listed in its directory.
+    pub is_last: bool,
+    /// Whether each directory between the root and the entry is the last
+    /// listed in its own directory, outermost first, so that the tree
+    /// lines in front of the entry can be drawn.
+    pub parents_last: Vec<bool>,
+    /// Whether the entry is a directory the walk is already inside, reached
+    /// again through a symlink, and so not walked into.
+    pub recursive: bool,
+}
+
+/// Walks the tree below a root, yielding its entries in listing order as it
+/// goes rather than reading the whole tree first. Each directory is read,
+/// filtered and sorted when the walk reaches it.
+///
+/// Entries are filtered and sorted as `list_directory` lists them, except
+/// for options that need a directory's whole subtree before its entry can
+/// be placed: directories are not pruned (`prune`, date filters and
+/// `content_patterns`), and `min_depth` is not applied. Unreadable
+/// subdirectories are reported to `on_error` and walked past; an unreadable
+/// root, passing the `timeout` or setting `cancel` ends the walk with an
+/// error.
+pub struct TreeWalker<'a> {
+    source: &'a dyn EntrySource,
+    options: &'a TreeOptions,
+    state: TraversalState,
+    /// The directories being walked, innermost last: their entries not yet
+    /// yielded, how many entries the listing left out, and their identity
+    /// to detect symlink loops.
+    dirs: Vec<(std::vec::IntoIter<EntryInfo>, usize, Option<DirId>)>,
+    /// `WalkEntry::parents_last` of the entries of the innermost directory.
+    parents_last: Vec<bool>,
+    /// An error to yield before anything else, from reading the root.
+    error: Option<io::Error>,
+}
+
+impl<'a> TreeWalker<'a> {
+    /// Walks the filesystem below `root`.
+    pub fn new<P: AsRef<Path>>(root: P, options: &'a TreeOptions) -> Self {
+        TreeWalker::with_source(&Filesystem, root.as_ref(), options)
+    }
+
+    /// Walks the entries below `root` as read from `source`.
+    pub fn with_source(source: &'a dyn EntrySource, root: &Path, options: &'a TreeOptions) -> Self {
+        let mut walker = TreeWalker {
+            source,
+            options,
+            state: TraversalState {
+                deadline: options.timeout.map(|timeout| Instant::now() + timeout),
+                git_ignores: options.gitignore.then(|| GitIgnores::new(root)),
+                ..Default::default()
+            },
+            dirs: Vec::new(),
+            parents_last: Vec::new(),
+            error: None,
         };
-        let colored_name = if options.no_color || !options.color {
-            name
+        if let Err(err) = walker.open(root, source.read_entries(root), source.dir_id(root), 0) {
+            walker.error = Some(err);
+        }
+        walker
+    }
+
+    /// Starts walking `entries`, as read from the directory at `path`,
+    /// identified by `id`, at `depth`.
+    fn open(
+        &mut self,
+        path: &Path,
+        entries: io::Result<Vec<EntryInfo>>,
+        id: Option<DirId>,
+        depth: usize,
+    ) -> io::Result<()> {
+        let (entries, omitted) = listed_entries(
+            self.source,
+            path,
+            entries,
+            self.options,
+            depth,
+            &mut self.state,
+        )?;
+        self.state.ancestors.extend(id.iter().cloned());
+        self.dirs.push((entries.into_iter(), omitted, id));
+        Ok(())
+    }
+}
+
+impl Iterator for TreeWalker<'_> {
+    type Item = io::Result<WalkEntry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(err) = self.error.take() {
+            self.dirs.clear();
+            return Some(Err(err));
+        }
+        loop {
+            let (entries, omitted, _) = self.dirs.last_mut()?;
+            let Some(entry) = entries.next() else {
+                if let Some((_, _, Some(id))) = self.dirs.pop() {
+                    self.state.ancestors.remove(&id);
+                }
+                self.parents_last.pop();
+                continue;
+            };
+            if self.state.should_stop(self.options) {
+                self.dirs.clear();
+                return self.state.stop_error().map(Err);
+            }
+            let is_last = entries.len() == 0 && *omitted == 0;
+            let mut walked = WalkEntry {
+                depth: self.dirs.len() - 1,
+                is_last,
+                parents_last: self.parents_last.clone(),
+                recursive: false,
+                entry,
+            };
+
+            if walked.entry.is_listed_as_dir(self.options) {
+                let path = &walked.entry.path;
+                let id = self.source.entry_dir_id(&walked.entry);
+                // Directories the walk is already inside and those over
+                // the file limit are yielded without their contents
+                walked.recursive = id
+                    .as_ref()
+                    .is_some_and(|id| self.state.ancestors.contains(id));
+                if !walked.recursive {
+                    // Read once, both to count against the file limit and
+                    // to walk
+                    let entries = self.source.read_entries(path);
+                    let over_limit = self.options.file_limit.is_some_and(|limit| {
+                        entries.as_ref().is_ok_and(|entries| entries.len() > limit)
+                    });
+                    // Below the root, read errors are reported and the
+                    // directory is walked as empty
+                    if !over_limit && self.open(path, entries, id, walked.depth + 1).is_ok() {
+                        self.parents_last.push(is_last);
+                    }
+                }
+            }
+            return Some(Ok(walked));
+        }
+    }
+}
+
+fn root_name(path: &Path) -> String {
+    // Roots like `/`, `..`, `C:\` and `\\server\share` have no file name
+    path.file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_else(|| path.display().to_string())
+}
+
+fn timed_out() -> io::Error {
+    io::Error::new(io::ErrorKind::TimedOut, "traversal time budget exceeded")
+}
+
+fn cancelled() -> io::Error {
+    io::Error::new(io::ErrorKind::Interrupted, "traversal cancelled")
+}
+
+/// Reads the root node, named `root_name`, and the tree below it.
+fn build_root(
+    source: &dyn EntrySource,
+    root: &Path,
+    root_name: &str,
+    options: &TreeOptions,
+    state: &mut TraversalState,
+) -> io::Result<TreeNode> {
+    let mut tree = TreeNode::new(source.entry(root)?);
+    let id = source.entry_dir_id(&tree.entry);
+    state.ancestors.extend(id.iter().cloned());
+    tree.name = root_name.to_string();
+    let entries = source.read_entries(root);
+    let children = build_children(source, root, entries, options, 0, state);
+    if let Some(id) = &id {
+        state.ancestors.remove(id);
+    }
+    (tree.children, tree.omitted) = children?;
+    Ok(tree)
+}
+
+/// One tree of a listing: where its entries come from, its root, and the
+/// name printed for the root.
+pub struct ListingRoot<'a> {
+    pub source: &'a dyn EntrySource,
+    pub path: PathBuf,
+    pub name: String,
+}
+
+/// Prints the tree of entries below `root` as read from `source`, headed by
+/// `root_name` and followed by the directory and file counts.
+pub fn list_entries(
+    source: &dyn EntrySource,
+    root: &Path,
+    root_name: &str,
+    options: &TreeOptions,
+) -> std::io::Result<()> {
+    list_roots(
+        &[ListingRoot {
+            source,
+            path: root.to_path_buf(),
+            name: root_name.to_string(),
+        }],
+        options,
+    )
+}
+
+/// Prints the trees below each of `paths` in turn, followed by the combined
+/// directory and file counts.
+pub fn list_directories<P: AsRef<Path>>(paths: &[P], options: &TreeOptions) -> std::io::Result<()> {
+    let roots: Vec<ListingRoot> = paths
+        .iter()
+        .map(|path| ListingRoot {
+            source: filesystem(options),
+            path: path.as_ref().to_path_buf(),
+            name: root_name(path.as_ref()),
+        })
+        .collect();
+    list_roots(&roots, options)
+}
+
+/// Prints each of `roots` in turn, followed by the combined directory and
+/// file counts. A root that cannot be read is printed as
+/// `name  [error opening dir]` and the rest still listed, after which the
+/// first such error is returned, naming its root.
+pub fn list_roots(roots: &[ListingRoot], options: &TreeOptions) -> std::io::Result<()> {
+    let out: Box<dyn Write> = match &options.output {
+        Some(output) => Box::new(BufWriter::new(File::create(output)?)),
+        None => Box::new(io::stdout()),
+    };
+    write_roots(roots, options, out)
+}
+
+/// Renders the tree below `path` as `list_directory` prints it, returning
+/// the text instead, with `output` ignored.
+pub fn list_directory_as_string<P: AsRef<Path>>(
+    path: P,
+    options: &TreeOptions,
+) -> io::Result<String> {
+    let root = ListingRoot {
+        source: filesystem(options),
+        path: path.as_ref().to_path_buf(),
+        name: root_name(path.as_ref()),
+    };
+    render_roots(&[root], options)
+}
+
+/// Renders each of `roots` as `list_roots` prints them, returning the text
+/// instead, with `output` ignored. With a `Listing` as the source and
+/// options that do not read files themselves (e.g. `gitignore` or
+/// `content_patterns`), nothing touches the filesystem, so this also works
+/// where there is none, such as in WebAssembly.
+pub fn render_roots(roots: &[ListingRoot], options: &TreeOptions) -> io::Result<String> {
+    let buffer = SharedBuffer::default();
+    write_roots(roots, options, Box::new(buffer.clone()))?;
+    let bytes = buffer.0.take();
+    Ok(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+/// An in-memory writer whose clones share one buffer, so that the text
+/// written through `TraversalState::out` can be read back.
+#[derive(Clone, Default)]
+struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Writes each of `roots` in turn to `out`, followed by the combined
+/// directory and file counts.
+fn write_roots(
+    roots: &[ListingRoot],
+    options: &TreeOptions,
+    out: Box<dyn Write>,
+) -> io::Result<()> {
+    let mut state = TraversalState {
+        out,
+        ..Default::default()
+    };
+    if let Some(linemap_path) = &options.linemap {
+        state.linemap = Some(Box::new(BufWriter::new(File::create(linemap_path)?)));
+    }
+    state.deadline = options.timeout.map(|timeout| Instant::now() + timeout);
+
+    let order = entry_order(options);
+    let mut total_size = 0;
+    // Described as each tree is listed, as later roots may reuse its paths
+    let mut descriptions = Vec::new();
+    let mut root_error = None;
+    for root in roots {
+        let (source, current_path) = (root.source, root.path.as_path());
+        let root_name = if options.replace_nonprintable {
+            replace_nonprintable(&root.name)
         } else {
-            colorize(&entry, name)
+            root.name.clone()
         };
-        print!("{}{}", prefix, colored_name);
 
-        if entry.file_type()?.is_dir() {
-            // If it's a directory, recurse into it
-            if !is_hidden {
-                stats.0 += 1;
+        state.git_ignores = options.gitignore.then(|| GitIgnores::new(current_path));
+        state.git_statuses = load_git_statuses(current_path, options);
+
+        // Directory totals, shown with --du and described with --describe
+        if options.disk_usage || options.describe {
+            let mut sizes = HashMap::new();
+            total_size += directory_sizes(
+                source,
+                current_path,
+                options,
+                state.git_ignores.as_ref(),
+                &mut sizes,
+            );
+            state.dir_sizes.extend(sizes);
+        }
+        // Streamed listings read their trees as they are printed
+        let opened = if options.streams() {
+            let root = source.entry(current_path);
+            let id = root
+                .as_ref()
+                .ok()
+                .and_then(|root| source.entry_dir_id(root));
+            state.ancestors.extend(id.iter().cloned());
+            let dir = root.and_then(|_| {
+                StreamingDir::open(
+                    source,
+                    current_path,
+                    id.as_ref().cloned(),
+                    order.as_deref(),
+                    options,
+                    0,
+                    &mut state,
+                )
+            });
+            if let (Err(_), Some(id)) = (&dir, &id) {
+                state.ancestors.remove(id);
             }
-            println!();
-            if is_entry_last {
-                last_entry_depths.insert(depth);
+            dir.map(|dir| (None, Some(dir)))
+        } else {
+            build_root(source, current_path, &root_name, options, &mut state)
+                .map(|tree| (Some(tree), None))
+        };
+        // A root that cannot be read is listed as such, as GNU tree does,
+        // and its error returned once the other roots have been listed
+        let (tree, streamed) = match opened {
+            Ok(opened) => opened,
+            Err(err) => {
+                if !options.explain_filters {
+                    let line = format!("{}  [error opening dir]", root_name);
+                    state.write_tree_line(&line, Some(current_path), options)?;
+                }
+                let message = format!("{}: {}", current_path.display(), err);
+                root_error.get_or_insert(io::Error::new(err.kind(), message));
+                if state.stopped() || state.truncated {
+                    break;
+                }
+                continue;
             }
-            traverse_directory(
-                root_path.as_ref(),
-                &path,
-                options,
-                depth + 1,
-                is_entry_last,
-                stats,
-                last_entry_depths,
-            )?;
-            if is_entry_last {
-                last_entry_depths.remove(&depth);
+        };
+        if tree.is_some() {
+            state.report_progress(current_path, options, true);
+            if options.explain_filters {
+                if state.stopped() {
+                    break;
+                }
+                continue;
             }
+        }
+
+        if options.term_integration {
+            let root_line = format!(
+                "{}{}",
+                working_directory_marker(current_path),
+                hyperlink(current_path, &root_name)
+            );
+            state.write_tree_line(&root_line, Some(current_path), options)?;
         } else {
-            // If it's a file and the size option is set, print its size
-            if !is_hidden {
-                stats.1 += 1;
-            }
-            if options.print_size || options.human_readable {
-                let metadata = entry.metadata()?;
-                let size = metadata.len();
-                let size_str = if options.human_readable {
-                    format!(" ({})", bytes_to_human_readable(size))
-                } else {
-                    format!(" ({:5}B)", size)
-                };
-                print!("{}", size_str);
+            state.write_tree_line(&root_name, Some(current_path), options)?;
+        }
+
+        if let Some(tree) = &tree {
+            render_children(&tree.children, tree.omitted, options, 0, &mut state)?;
+            if options.describe {
+                descriptions.push(describe(tree, &state.dir_sizes, options));
             }
-            println!();
+        }
+        if let Some(dir) = streamed {
+            stream_children(source, dir, order.as_deref(), options, &mut state)?;
+            state.report_progress(current_path, options, true);
+        }
+        if state.stopped() || state.truncated {
+            break;
         }
     }
 
-    Ok(())
+    if options.explain_filters {
+        write_filter_counts(options, &mut state)?;
+    } else {
+        if state.timed_out {
+            state.write_line("", None)?;
+            state.write_line("[timeout reached, listing is incomplete]", None)?;
+        } else if state.cancelled {
+            state.write_line("", None)?;
+            state.write_line("[scan cancelled, listing is incomplete]", None)?;
+        } else if state.truncated {
+            state.write_line("", None)?;
+            let notice = format!(
+                "[output truncated after {} lines, listing is incomplete]",
+                options.head.unwrap_or_default()
+            );
+            state.write_line(&notice, None)?;
+        }
+        state.write_line("", None)?;
+        let mut report = format!("{} directories, {} files", state.stats.0, state.stats.1);
+        if options.disk_usage {
+            let total = if options.human_readable {
+                options.human_size(total_size)
+            } else {
+                format!("{} bytes", total_size)
+            };
+            report = format!("{} used in {}", total, report);
+        }
+        state.write_line(&report, None)?;
+        for description in &descriptions {
+            state.write_line("", None)?;
+            state.write_line(description, None)?;
+        }
+    }
+    state.out.flush()?;
+    if let Some(linemap) = state.linemap.as_mut() {
+        linemap.flush()?;
+    }
+    match state.stop_error().or(root_error) {
+        Some(err) => Err(err),
+        None => Ok(()),
+    }
 }
 
-pub fn list_directory<P: AsRef<Path>>(path: P, options: &TreeOptions) -> std::io::Result<()> {
-    let current_path = path.as_ref();
-    println!(
-        "{}",
-        current_path
-            .file_name()
-            .and_then(|name| name.to_str())
-            .unwrap_or(".")
-    );
-
-    let mut stats = (0, 0); // (directories, files)
-                            // Recursively traverse the directory and print its contents
-    let mut last_entry_depths = HashSet::new();
-
-    traverse_directory(
-        current_path,
-        current_path,
-        options,
-        0,
-        false,
-        &mut stats,
-        &mut last_entry_depths,
-    )?;
+/// Prints a table of how many entries each active filter left out, for
+/// `explain_filters`. Entries inside a directory that was itself left out are
+/// not counted.
+fn write_filter_counts(options: &TreeOptions, state: &mut TraversalState) -> io::Result<()> {
+    let mut filters = Vec::new();
+    if !options.all_files {
+        filters.push((SkipReason::Hidden, "hidden (no -a)".to_string()));
+    }
+    if let Some(level) = options.level {
+        filters.push((SkipReason::Depth, format!("depth (-L {})", level)));
+    }
+    if let Some(min_depth) = options.min_depth {
+        filters.push((
+            SkipReason::Shallow,
+            format!("files above --mindepth {}", min_depth),
+        ));
+    }
+    if !options.pattern_globs.is_empty() || !options.include_regexes.is_empty() {
+        filters.push((
+            SkipReason::NotMatched,
+            "not matching -P/--regex".to_string(),
+        ));
+    }
+    if options.min_size.is_some() || options.max_size.is_some() {
+        filters.push((SkipReason::Size, "size".to_string()));
+    }
+    if options.nonempty {
+        filters.push((SkipReason::Empty, "empty files (--nonempty)".to_string()));
+    }
+    if options.has_date_filter() {
+        filters.push((SkipReason::Date, "date".to_string()));
+    }
+    if options.prunes_empty_dirs() {
+        filters.push((SkipReason::Pruned, "emptied directories".to_string()));
+    }
+    if !options.exclude_patterns.is_empty() || !options.exclude_regexes.is_empty() {
+        filters.push((
+            SkipReason::Excluded,
+            "matching -I/--regex-exclude".to_string(),
+        ));
+    }
+    if options.dir_only {
+        filters.push((SkipReason::NotDirectory, "files (-d)".to_string()));
+    }
+    if options.gitignore {
+        filters.push((SkipReason::GitIgnored, "gitignore".to_string()));
+    }
+    if !options.entry_types.is_empty() {
+        filters.push((SkipReason::WrongType, "other types (--type)".to_string()));
+    }
+    if options.filter.is_some() {
+        filters.push((SkipReason::Filtered, "filter".to_string()));
+    }
+    if !options.content_patterns.is_empty() {
+        filters.push((
+            SkipReason::NoContentMatch,
+            "not containing --contains".to_string(),
+        ));
+    }
+    if options.output.is_some() || options.linemap.is_some() {
+        filters.push((
+            SkipReason::OutputFile,
+            "output files (-o, --linemap)".to_string(),
+        ));
+    }
 
-    println!("\n{} directories, {} files", stats.0, stats.1);
+    state.write_line(&format!("{:<30}{:>10}", "filter", "excluded"), None)?;
+    for (reason, label) in filters {
+        let count = state.skipped.get(&reason).copied().unwrap_or(0);
+        state.write_line(&format!("{:<30}{:>10}", label, count), None)?;
+    }
     Ok(())
 }
+
+/// Returns the first entry below `path` whose name matches any of `patterns`,
+/// without listing or sorting anything. Hidden entries, `exclude_patterns`,
+/// `filter` and the `level` limit are honored as in a listing, and setting
+/// `cancel` ends the search with an error.
+pub fn find_first<P: AsRef<Path>>(
+    path: P,
+    patterns: &[Pattern],
+    options: &TreeOptions,
+) -> io::Result<Option<PathBuf>> {
+    let mut ancestors: HashSet<DirId> = dir_id(path.as_ref()).into_iter().collect();
+    let git_ignores = options.gitignore.then(|| GitIgnores::new(path.as_ref()));
+    let git_ignores = git_ignores.as_ref();
+    let (found, dirs) = find_in_dir(path.as_ref(), patterns, options, 0, git_ignores)?;
+    if found.is_some() {
+        return Ok(found);
+    }
+    // The subdirectories left to search in each directory being searched,
+    // innermost last, and its identity; a stack of its own as in
+    // build_children
+    let mut stack = vec![(dirs.into_iter(), None)];
+    while let Some((dirs, _)) = stack.last_mut() {
+        let Some(dir) = dirs.next() else {
+            if let Some((_, Some(id))) = stack.pop() {
+                ancestors.remove(&id);
+            }
+            continue;
+        };
+        let Some(id) = filesystem(options).entry_dir_id(&dir) else {
+            continue;
+        };
+        if !ancestors.insert(id.to_owned()) {
+            continue;
+        }
+        let (found, dirs) = find_in_dir(&dir.path, patterns, options, stack.len(), git_ignores)?;
+        if found.is_some() {
+            return Ok(found);
+        }
+        stack.push((dirs.into_iter(), Some(id)));
+    }
+    Ok(None)
+}
+
+/// Looks among the entries of `current_path`, at `depth`, for one matching
+/// `patterns`. Returns the path of the first found, if any, and the
+/// subdirectories to look in next.
+fn find_in_dir(
+    current_path: &Path,
+    patterns: &[Pattern],
+    options: &TreeOptions,
+    depth: usize,
+    git_ignores: Option<&GitIgnores>,
+) -> io::Result<(Option<PathBuf>, Vec<EntryInfo>)> {
+    if options.is_cancelled() {
+        return Err(cancelled());
+    }
+    let entries = match filesystem(options).read_entries(current_path) {
+        Ok(entries) => entries,
+        Err(err) if depth > 0 => {
+            report_error(current_path, err, options);
+            return Ok((None, Vec::new()));
+        }
+        Err(err) => return Err(err),
+    };
+    if let Some(git_ignores) = git_ignores {
+        git_ignores.load_dir(&Filesystem, current_path);
+    }
+    let mut dirs = Vec::new();
+    for entry in entries {
+        if matches!(
+            should_skip_entry(&entry, options, depth, git_ignores),
+            Some(
+                SkipReason::Hidden
+                    | SkipReason::Depth
+                    | SkipReason::Excluded
+                    | SkipReason::GitIgnored
+                    | SkipReason::Filtered
+            )
+        ) {
+            continue;
+        }
+        let file_name = entry.file_name.to_string_lossy();
+        if patterns.iter().any(|pattern| pattern.matches(&file_name)) {
+            return Ok((Some(entry.path), Vec::new()));
+        }
+        if entry.kind == EntryKind::Directory || (options.follow_links && entry.is_dir) {
+            dirs.push(entry);
+        }
+    }
+    Ok((None, dirs))
+}
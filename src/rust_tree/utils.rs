@@ -1,14 +1,521 @@
+use chrono::format::{Item, StrftimeItems};
+use chrono::{DateTime, Local, NaiveDate, NaiveDateTime, TimeZone};
+use glob::{Pattern, PatternError};
+#[cfg(unix)]
+use std::cell::RefCell;
+use std::cmp::Ordering;
+#[cfg(unix)]
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::fs;
+use std::io;
+#[cfg(unix)]
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::time::{Duration, SystemTime};
+
+use crate::rust_tree::options::SizeRounding;
+
+/// The most decimal places human readable sizes are given with; more would
+/// only show the noise of floating point arithmetic.
+pub const MAX_SIZE_PRECISION: usize = 9;
+
 pub fn bytes_to_human_readable(bytes: u64) -> String {
-    const UNITS: [&str; 5] = ["B", "KB", "MB", "GB", "TB"];
-    let base: f64 = 1024.0;
-    let unit = UNITS.iter().enumerate().find_map(|(i, unit)| {
-        let size = bytes as f64 / base.powi(i as i32);
-        if size < base {
-            Some(format!("{:.1} {}", size, unit))
-        } else {
-            None
-        }
-    }).unwrap_or_else(|| format!("{:} B", bytes));
+    format_human_size(bytes, 1, SizeRounding::Nearest)
+}
+
+/// Formats a size with a 1024-based unit, using `precision` decimal places
+/// rounded as requested (e.g. `1.46 MB`).
+pub fn format_human_size(bytes: u64, precision: usize, rounding: SizeRounding) -> String {
+    format_size_in_units(
+        bytes,
+        1024.0,
+        &["B", "KB", "MB", "GB", "TB"],
+        precision,
+        rounding,
+    )
+}
+
+/// Formats a size with a 1000-based SI unit, using `precision` decimal
+/// places rounded as requested (e.g. `1.53 MB`).
+pub fn format_si_size(bytes: u64, precision: usize, rounding: SizeRounding) -> String {
+    format_size_in_units(
+        bytes,
+        1000.0,
+        &["B", "kB", "MB", "GB", "TB"],
+        precision,
+        rounding,
+    )
+}
+
+fn format_size_in_units(
+    bytes: u64,
+    base: f64,
+    units: &[&str],
+    precision: usize,
+    rounding: SizeRounding,
+) -> String {
+    let precision = precision.min(MAX_SIZE_PRECISION);
+    let scale = 10f64.powi(precision as i32);
+    let unit = units
+        .iter()
+        .enumerate()
+        .find_map(|(i, unit)| {
+            let size = bytes as f64 / base.powi(i as i32);
+            let rounded = match rounding {
+                SizeRounding::Nearest => (size * scale).round(),
+                SizeRounding::Up => (size * scale).ceil(),
+                SizeRounding::Down => (size * scale).floor(),
+            } / scale;
+            // A size rounding up to the base is shown in the next unit
+            (rounded < base).then(|| format!("{:.*} {}", precision, rounded, unit))
+        })
+        .unwrap_or_else(|| format!("{:} B", bytes));
 
     unit
-}
\ No newline at end of file
+}
+
+/// Parses a GNU tree style pattern argument, where `|` separates alternative
+/// wild-card patterns (e.g. `foo|bar|*.c`).
+pub fn parse_pattern_alternatives(patterns: &str) -> Result<Vec<Pattern>, PatternError> {
+    patterns
+        .split('|')
+        .filter(|pattern| !pattern.is_empty())
+        .map(Pattern::new)
+        .collect()
+}
+
+/// Reads the patterns of an ignore file such as `.treeignore`, or of an
+/// `--include-from` file: one wild-card pattern per line, skipping blank
+/// lines and lines starting with `#`. Patterns match file and directory
+/// names alike, so a trailing `/` is dropped.
+pub fn read_ignore_file(path: &Path) -> io::Result<Vec<Pattern>> {
+    let text = fs::read_to_string(path)?;
+    let mut patterns = Vec::new();
+    for (index, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let pattern = Pattern::new(line.trim_end_matches('/')).map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("line {}: invalid pattern '{}'", index + 1, line),
+            )
+        })?;
+        patterns.push(pattern);
+    }
+    Ok(patterns)
+}
+
+/// Dependency, virtual environment, cache and build output directories left
+/// out by `--smart-ignore`.
+pub const SMART_IGNORE_PATTERNS: &[&str] = &[
+    ".git",
+    ".hg",
+    ".svn",
+    "node_modules",
+    "bower_components",
+    "target",
+    "venv",
+    ".venv",
+    "__pycache__",
+    ".mypy_cache",
+    ".pytest_cache",
+    ".tox",
+    ".gradle",
+    ".next",
+    "dist",
+    "build",
+];
+
+/// The user's home directory, from `HOME` or on Windows `USERPROFILE`.
+pub fn home_dir() -> Option<PathBuf> {
+    let home = std::env::var_os("HOME");
+    #[cfg(windows)]
+    let home = home.or_else(|| std::env::var_os("USERPROFILE"));
+    home.filter(|home| !home.is_empty()).map(PathBuf::from)
+}
+
+/// Whether Windows would run the file at `path` as a program: its extension
+/// is one of those in `PATHEXT`, or of `.com`, `.exe`, `.bat` and `.cmd`
+/// where that is not set.
+#[cfg(not(unix))]
+pub fn has_executable_extension(path: &Path) -> bool {
+    let Some(extension) = path.extension() else {
+        return false;
+    };
+    let extension = format!(".{}", extension.to_string_lossy());
+    let path_ext = std::env::var("PATHEXT").unwrap_or_else(|_| ".COM;.EXE;.BAT;.CMD".to_string());
+    path_ext
+        .split(';')
+        .any(|executable| executable.eq_ignore_ascii_case(&extension))
+}
+
+/// The width of the terminal stdout is connected to, from `COLUMNS` or
+/// else from the terminal itself. `None` when stdout is not a terminal,
+/// whatever `COLUMNS` says.
+pub fn terminal_width() -> Option<usize> {
+    use std::io::IsTerminal;
+
+    if !io::stdout().is_terminal() {
+        return None;
+    }
+    let columns = std::env::var("COLUMNS").ok();
+    if let Some(columns) = columns.and_then(|columns| columns.parse().ok()) {
+        return Some(columns).filter(|&columns| columns > 0);
+    }
+    #[cfg(unix)]
+    {
+        // SAFETY: `winsize` is plain integers, for which zero is valid, and
+        // TIOCGWINSZ only writes a `winsize` through the pointer given.
+        let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+        let result = unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) };
+        if result == 0 && size.ws_col > 0 {
+            return Some(size.ws_col as usize);
+        }
+    }
+    None
+}
+
+/// Whether the entry at `path` has a POSIX ACL granting more than its
+/// permission bits: an access ACL, or if `is_dir`, a default ACL.
+#[cfg(target_os = "linux")]
+pub fn has_acl(path: &Path, is_dir: bool) -> bool {
+    use std::ffi::{CStr, CString};
+    use std::os::unix::ffi::OsStrExt;
+
+    let Ok(path) = CString::new(path.as_os_str().as_bytes()) else {
+        return false;
+    };
+    // Only directories have default ACLs
+    let names: &[&CStr] = if is_dir {
+        &[c"system.posix_acl_access", c"system.posix_acl_default"]
+    } else {
+        &[c"system.posix_acl_access"]
+    };
+    names.iter().any(|name| {
+        // SAFETY: both strings are NUL-terminated, and with a size of 0
+        // getxattr only returns the length of the value, writing nothing.
+        unsafe { libc::getxattr(path.as_ptr(), name.as_ptr(), std::ptr::null_mut(), 0) >= 0 }
+    })
+}
+
+/// ACLs are only detected on Linux.
+#[cfg(not(target_os = "linux"))]
+pub fn has_acl(_path: &Path, _is_dir: bool) -> bool {
+    false
+}
+
+#[cfg(unix)]
+thread_local! {
+    static GROUP_NAMES: RefCell<HashMap<u32, String>> = RefCell::new(HashMap::new());
+}
+
+/// Looks up the name of a group id, falling back to the numeric id when the
+/// group is unknown. Lookups are cached since most entries share a few groups.
+#[cfg(unix)]
+pub fn group_name(gid: u32) -> String {
+    GROUP_NAMES.with(|names| {
+        names
+            .borrow_mut()
+            .entry(gid)
+            .or_insert_with(|| {
+                uzers::get_group_by_gid(gid)
+                    .map(|group| group.name().to_string_lossy().to_string())
+                    .unwrap_or_else(|| gid.to_string())
+            })
+            .clone()
+    })
+}
+
+/// Identifies a directory independently of the path used to reach it.
+#[cfg(unix)]
+pub type DirId = (u64, u64);
+#[cfg(not(unix))]
+pub type DirId = PathBuf;
+
+/// Returns the identity of the directory `path` resolves to: its (device,
+/// inode) pair on Unix, or its canonical path elsewhere.
+#[cfg(unix)]
+pub fn dir_id(path: &Path) -> Option<DirId> {
+    fs::metadata(path)
+        .ok()
+        .map(|metadata| (metadata.dev(), metadata.ino()))
+}
+
+#[cfg(not(unix))]
+pub fn dir_id(path: &Path) -> Option<DirId> {
+    fs::canonicalize(path).ok()
+}
+
+/// Returns the identity of a directory from its metadata, which only Unix
+/// can tell without another lookup.
+#[cfg(unix)]
+pub fn metadata_dir_id(metadata: &fs::Metadata) -> Option<DirId> {
+    metadata.is_dir().then(|| (metadata.dev(), metadata.ino()))
+}
+
+#[cfg(not(unix))]
+pub fn metadata_dir_id(_metadata: &fs::Metadata) -> Option<DirId> {
+    None
+}
+
+/// Parses a duration such as `10s`, `500ms`, `2m` or `1h`; a bare number is
+/// taken as seconds.
+pub fn parse_duration(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    let split = value
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+    let number: f64 = number.parse().ok()?;
+    let seconds = match unit {
+        "" | "s" => number,
+        "ms" => number / 1000.0,
+        "m" => number * 60.0,
+        "h" => number * 3600.0,
+        _ => return None,
+    };
+    Duration::try_from_secs_f64(seconds).ok()
+}
+
+/// Parses a size such as `512`, `10k`, `5M` or `1.5G` into bytes. Units are
+/// 1024-based and case-insensitive, with an optional `B` or `iB` suffix
+/// (`10KB`, `10KiB`); a bare number is taken as bytes.
+pub fn parse_size(value: &str) -> Option<u64> {
+    let value = value.trim();
+    let split = value
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+    let number: f64 = number.parse().ok()?;
+    let unit = unit.to_ascii_lowercase();
+    let unit = unit.trim_end_matches("ib").trim_end_matches('b');
+    let exponent = match unit {
+        "" => 0,
+        "k" => 1,
+        "m" => 2,
+        "g" => 3,
+        "t" => 4,
+        _ => return None,
+    };
+    let bytes = number * 1024f64.powi(exponent);
+    (bytes.is_finite() && bytes <= u64::MAX as f64).then_some(bytes.round() as u64)
+}
+
+/// Returns the last status change time of an entry, or `None` if it is out
+/// of range. Platforms without one fall back to the modification time.
+#[cfg(unix)]
+pub fn change_time(metadata: &fs::Metadata) -> Option<SystemTime> {
+    unix_time(metadata.ctime(), metadata.ctime_nsec())
+}
+
+#[cfg(not(unix))]
+pub fn change_time(metadata: &fs::Metadata) -> Option<SystemTime> {
+    metadata.modified().ok()
+}
+
+/// The time `seconds` and `nanoseconds` after the epoch, as `stat` gives
+/// them: for times before it, `seconds` is negative and the nanoseconds
+/// still count forward from there. `None` if it is out of range.
+pub fn unix_time(seconds: i64, nanoseconds: i64) -> Option<SystemTime> {
+    let whole = Duration::from_secs(seconds.unsigned_abs());
+    let time = if seconds >= 0 {
+        SystemTime::UNIX_EPOCH.checked_add(whole)?
+    } else {
+        SystemTime::UNIX_EPOCH.checked_sub(whole)?
+    };
+    let nanoseconds = u32::try_from(nanoseconds)
+        .ok()
+        .filter(|&n| n < 1_000_000_000)?;
+    time.checked_add(Duration::new(0, nanoseconds))
+}
+
+/// The `-D` date format unless `--timefmt` overrides it.
+pub const DEFAULT_TIME_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+
+/// Parses an absolute date in local time, as `2024-05-01`,
+/// `2024-05-01 12:30`, `2024-05-01 12:30:45` (or with a `T` instead of the
+/// space), or an RFC 3339 timestamp with its own offset.
+pub fn parse_date(value: &str) -> Option<SystemTime> {
+    let value = value.trim();
+    if let Ok(time) = DateTime::parse_from_rfc3339(value) {
+        return Some(time.into());
+    }
+    let value = value.replacen('T', " ", 1);
+    let naive = ["%Y-%m-%d %H:%M:%S", "%Y-%m-%d %H:%M"]
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(&value, format).ok())
+        .or_else(|| {
+            NaiveDate::parse_from_str(&value, "%Y-%m-%d")
+                .ok()?
+                .and_hms_opt(0, 0, 0)
+        })?;
+    Local
+        .from_local_datetime(&naive)
+        .earliest()
+        .map(SystemTime::from)
+}
+
+/// Formats a timestamp in local time using strftime-like `format` tokens,
+/// falling back to `DEFAULT_TIME_FORMAT` if the format is invalid.
+pub fn format_time(time: SystemTime, format: &str) -> String {
+    let time = DateTime::<Local>::from(time);
+    let mut formatted = String::new();
+    if write!(formatted, "{}", time.format(format)).is_err() {
+        return time.format(DEFAULT_TIME_FORMAT).to_string();
+    }
+    formatted
+}
+
+/// Describes how long before `now` a timestamp is, in its largest whole
+/// unit, e.g. `3 hours ago` or `2 days ago`. Times in the future read as
+/// `in 5 minutes`.
+pub fn format_age(time: SystemTime, now: SystemTime) -> String {
+    let (seconds, future) = match now.duration_since(time) {
+        Ok(age) => (age.as_secs(), false),
+        Err(err) => (err.duration().as_secs(), true),
+    };
+    if seconds < 60 {
+        return "just now".to_string();
+    }
+    let units = [
+        (365 * 86400, "year"),
+        (30 * 86400, "month"),
+        (7 * 86400, "week"),
+        (86400, "day"),
+        (3600, "hour"),
+    ];
+    let (length, unit) = units
+        .into_iter()
+        .find(|(length, _)| seconds >= *length)
+        .unwrap_or((60, "minute"));
+    let count = seconds / length;
+    let plural = if count == 1 { "" } else { "s" };
+    if future {
+        format!("in {} {}{}", count, unit, plural)
+    } else {
+        format!("{} {}{} ago", count, unit, plural)
+    }
+}
+
+/// Whether `format` only contains valid strftime-like tokens.
+pub fn is_valid_time_format(format: &str) -> bool {
+    StrftimeItems::new(format).all(|item| item != Item::Error)
+}
+
+/// Replaces control characters, and invisible formatting characters such as
+/// bidirectional overrides, with `?` so names cannot inject terminal escape
+/// sequences or disguise themselves.
+pub fn replace_nonprintable(text: &str) -> String {
+    text.chars()
+        .map(|c| match c {
+            c if c.is_control() => '?',
+            '\u{200B}'..='\u{200F}'
+            | '\u{202A}'..='\u{202E}'
+            | '\u{2060}'..='\u{2069}'
+            | '\u{FEFF}' => '?',
+            c => c,
+        })
+        .collect()
+}
+
+/// Compares names the way `sort -V` does: runs of digits are compared by
+/// numeric value, everything else character by character.
+pub fn version_cmp(a: &str, b: &str) -> Ordering {
+    let mut a_chars = a.chars().peekable();
+    let mut b_chars = b.chars().peekable();
+    loop {
+        match (a_chars.peek(), b_chars.peek()) {
+            (None, None) => return a.cmp(b),
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(a_char), Some(b_char)) if a_char.is_ascii_digit() && b_char.is_ascii_digit() => {
+                let a_number = take_digits(&mut a_chars);
+                let b_number = take_digits(&mut b_chars);
+                let a_digits = a_number.trim_start_matches('0');
+                let b_digits = b_number.trim_start_matches('0');
+                let ordering = a_digits
+                    .len()
+                    .cmp(&b_digits.len())
+                    .then_with(|| a_digits.cmp(b_digits));
+                if ordering != Ordering::Equal {
+                    return ordering;
+                }
+            }
+            (Some(a_char), Some(b_char)) => {
+                let ordering = a_char.cmp(b_char);
+                if ordering != Ordering::Equal {
+                    return ordering;
+                }
+                a_chars.next();
+                b_chars.next();
+            }
+        }
+    }
+}
+
+fn take_digits(chars: &mut std::iter::Peekable<std::str::Chars>) -> String {
+    let mut digits = String::new();
+    while let Some(c) = chars.next_if(|c| c.is_ascii_digit()) {
+        digits.push(c);
+    }
+    digits
+}
+
+/// The name of this machine, as terminals expect in the authority of
+/// `file://` URLs to tell local paths from those on other hosts.
+#[cfg(unix)]
+fn hostname() -> Option<String> {
+    let mut name = [0u8; 256];
+    // SAFETY: gethostname writes at most `name.len()` bytes to the buffer.
+    if unsafe { libc::gethostname(name.as_mut_ptr().cast(), name.len()) } != 0 {
+        return None;
+    }
+    let length = name.iter().position(|&byte| byte == 0)?;
+    String::from_utf8(name[..length].to_vec()).ok()
+}
+
+#[cfg(not(unix))]
+fn hostname() -> Option<String> {
+    std::env::var("COMPUTERNAME").ok()
+}
+
+/// Builds a `file://` URL for the absolute form of `path`, naming this
+/// machine as its host and percent-encoding anything outside the
+/// unreserved URL characters.
+pub fn file_url(path: &Path) -> Option<String> {
+    static HOSTNAME: OnceLock<String> = OnceLock::new();
+    let host = HOSTNAME.get_or_init(|| hostname().unwrap_or_default());
+
+    let absolute = fs::canonicalize(path).ok()?;
+    let mut absolute = absolute.to_string_lossy().to_string();
+    if cfg!(windows) {
+        absolute = absolute.replace('\\', "/");
+        // Verbatim prefixes, e.g. \\?\C:\dir or \\?\UNC\server\share\dir
+        if let Some(share) = absolute.strip_prefix("//?/UNC/") {
+            absolute = format!("//{}", share);
+        } else if let Some(stripped) = absolute.strip_prefix("//?/") {
+            absolute = stripped.to_string();
+        }
+    }
+    if !absolute.starts_with('/') {
+        absolute.insert(0, '/');
+    }
+    // UNC paths already start with the `//` and host of the URL authority
+    let mut url = String::from("file:");
+    if !absolute.starts_with("//") {
+        absolute.insert_str(0, &format!("//{}", host));
+    }
+    for byte in absolute.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' | b':' => {
+                url.push(byte as char)
+            }
+            _ => url.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    Some(url)
+}
@@ -0,0 +1,203 @@
+//! Declarative filesystem fixtures shared by the integration tests.
+//!
+//! ```ignore
+//! let fixture = Fixture::new("project")
+//!     .file("src/main.rs")
+//!     .sized_file("assets/logo.png", 2048)
+//!     .symlink("latest", "src");
+//! let output = run_cmd(&[fixture.arg()]);
+//! ```
+#![allow(dead_code)]
+
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::SystemTime;
+
+use tempfile::TempDir;
+
+/// The `tree` binary built for the tests, which the `cli` feature enables.
+pub fn binary() -> &'static str {
+    // Unset for test targets built without it, which must not run the binary
+    match option_env!("CARGO_BIN_EXE_tree") {
+        Some(binary) => binary,
+        None => panic!("the tree binary needs the cli feature"),
+    }
+}
+
+/// A command running `binary()` with an empty home directory, so that the
+/// developer's own `~/.treeignore` does not change what the tests see.
+pub fn command() -> Command {
+    let home = Path::new(env!("CARGO_TARGET_TMPDIR")).join("empty-home");
+    fs::create_dir_all(&home).expect("failed to create home dir");
+    let mut command = Command::new(binary());
+    command.env("HOME", &home).env("USERPROFILE", &home);
+    command
+}
+
+pub fn run_cmd(arg: &[&str]) -> String {
+    let stdout = command().args(arg).output().expect("command failed").stdout;
+    String::from_utf8(stdout).expect("Bad parsing")
+}
+
+/// Runs the binary and returns its stdout together with the exit code.
+pub fn run_cmd_with_status(arg: &[&str]) -> (String, i32) {
+    let output = command().args(arg).output().expect("command failed");
+    let stdout = String::from_utf8(output.stdout).expect("Bad parsing");
+    (stdout, output.status.code().unwrap_or(-1))
+}
+
+/// Runs the binary with `dir` as working directory, so that relative root
+/// arguments keep the output free of machine specific paths.
+pub fn run_cmd_in(dir: &Path, arg: &[&str]) -> String {
+    let stdout = command()
+        .current_dir(dir)
+        .args(arg)
+        .output()
+        .expect("command failed")
+        .stdout;
+    String::from_utf8(stdout).expect("Bad parsing")
+}
+
+pub fn last_line(output: &str) -> &str {
+    output.lines().last().unwrap_or("")
+}
+
+/// A directory tree created inside a temporary directory and removed on drop.
+///
+/// The root is a named subdirectory of the temporary directory so that the
+/// first line of the rendered tree is predictable.
+pub struct Fixture {
+    _temp: TempDir,
+    root: PathBuf,
+}
+
+impl Fixture {
+    pub fn new(name: &str) -> Self {
+        let temp = tempfile::tempdir().expect("failed to create temp dir");
+        let root = temp.path().join(name);
+        fs::create_dir(&root).unwrap();
+        Fixture { _temp: temp, root }
+    }
+
+    /// The layout used by most tests:
+    ///
+    /// ```text
+    /// test_directory
+    /// ├── .hidden.txt
+    /// ├── dir1
+    /// │   ├── dir1_1
+    /// │   └── file2.txt
+    /// ├── dir2
+    /// │   └── file3.txt
+    /// └── file1.txt
+    /// ```
+    pub fn standard() -> Self {
+        Fixture::new("test_directory")
+            .dir("dir1/dir1_1")
+            .dir("dir2")
+            .file("file1.txt")
+            .file("dir1/file2.txt")
+            .file("dir2/file3.txt")
+            .file(".hidden.txt")
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.root
+    }
+
+    /// The temporary directory holding the root.
+    pub fn parent(&self) -> &Path {
+        self._temp.path()
+    }
+
+    /// The root path as a command line argument.
+    pub fn arg(&self) -> &str {
+        self.root.to_str().expect("non UTF-8 temp path")
+    }
+
+    pub fn join(&self, path: &str) -> PathBuf {
+        self.root.join(path)
+    }
+
+    pub fn dir(self, path: &str) -> Self {
+        fs::create_dir_all(self.join(path)).unwrap();
+        self
+    }
+
+    pub fn file(self, path: &str) -> Self {
+        self.file_with_content(path, "")
+    }
+
+    pub fn file_with_content(self, path: &str, content: &str) -> Self {
+        let path = self.join(path);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).unwrap();
+        }
+        fs::write(path, content).unwrap();
+        self
+    }
+
+    pub fn sized_file(self, path: &str, size: u64) -> Self {
+        let fixture = self.file(path);
+        File::options()
+            .write(true)
+            .open(fixture.join(path))
+            .unwrap()
+            .set_len(size)
+            .unwrap();
+        fixture
+    }
+
+    pub fn modified(self, path: &str, time: SystemTime) -> Self {
+        File::options()
+            .write(true)
+            .open(self.join(path))
+            .unwrap()
+            .set_modified(time)
+            .unwrap();
+        self
+    }
+
+    #[cfg(unix)]
+    pub fn mode(self, path: &str, mode: u32) -> Self {
+        use std::os::unix::fs::PermissionsExt;
+
+        fs::set_permissions(self.join(path), fs::Permissions::from_mode(mode)).unwrap();
+        self
+    }
+
+    /// Creates a symlink at `path` pointing to `target`, which is stored
+    /// verbatim (so relative targets are relative to the link's directory).
+    #[cfg(unix)]
+    pub fn symlink(self, path: &str, target: &str) -> Self {
+        std::os::unix::fs::symlink(target, self.join(path)).unwrap();
+        self
+    }
+
+    #[cfg(windows)]
+    pub fn symlink(self, path: &str, target: &str) -> Self {
+        let link = self.join(path);
+        let resolved = link.parent().unwrap().join(target);
+        if resolved.is_dir() {
+            std::os::windows::fs::symlink_dir(target, link).unwrap();
+        } else {
+            std::os::windows::fs::symlink_file(target, link).unwrap();
+        }
+        self
+    }
+}
+
+/// Whether permission bits are enforced for the current user; they are not
+/// for root, so permission-denied scenarios must be skipped there.
+#[cfg(unix)]
+pub fn permissions_enforced() -> bool {
+    let probe = Fixture::new("probe").dir("locked").mode("locked", 0o000);
+    let denied = fs::read_dir(probe.join("locked")).is_err();
+    fs::set_permissions(
+        probe.join("locked"),
+        std::os::unix::fs::PermissionsExt::from_mode(0o755),
+    )
+    .unwrap();
+    denied
+}
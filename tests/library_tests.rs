@@ -0,0 +1,813 @@
+mod common;
+
+use std::collections::HashMap;
+use std::io::{self, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+use common::Fixture;
+use glob::Pattern;
+use rust_tree::rust_tree::display::{truncate_line, DefaultFormatter, EntryFormatter};
+use rust_tree::rust_tree::entry::{
+    EntryInfo, EntryKind, EntrySource, Filesystem, FilesystemAcls, FilesystemKinds, TreeNode,
+};
+use rust_tree::rust_tree::listing::Listing;
+use rust_tree::rust_tree::options::{SkipReason, SortKey, TraversalWarning, TreeOptions};
+use rust_tree::rust_tree::traversal::{
+    build_tree, build_tree_from_source, find_first, list_directory, render_roots, ListingRoot,
+    TreeWalker,
+};
+use rust_tree::rust_tree::utils::{dir_id, unix_time, DirId};
+
+#[test]
+fn test_on_skip_reports_reasons() {
+    let fixture = Fixture::standard();
+    let skipped: Arc<Mutex<Vec<(PathBuf, SkipReason)>>> = Arc::default();
+    let sink = Arc::clone(&skipped);
+
+    let options = TreeOptions {
+        level: Some(1),
+        exclude_patterns: vec![Pattern::new("dir2").unwrap()],
+        on_skip: Some(Box::new(move |path, reason| {
+            sink.lock().unwrap().push((path.to_path_buf(), reason));
+        })),
+        ..Default::default()
+    };
+    list_directory(fixture.path(), &options).unwrap();
+
+    let mut skipped = skipped.lock().unwrap().clone();
+    skipped.sort_by(|a, b| a.0.cmp(&b.0));
+    assert_eq!(
+        vec![
+            (fixture.join(".hidden.txt"), SkipReason::Hidden),
+            (fixture.join("dir1/dir1_1"), SkipReason::Depth),
+            (fixture.join("dir1/file2.txt"), SkipReason::Depth),
+            (fixture.join("dir2"), SkipReason::Excluded),
+        ],
+        skipped
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn test_on_error_reports_unreadable_directories() {
+    if !common::permissions_enforced() {
+        return;
+    }
+    let fixture = Fixture::standard().dir("locked").mode("locked", 0o000);
+    let errors: Arc<Mutex<Vec<PathBuf>>> = Arc::default();
+    let sink = Arc::clone(&errors);
+
+    let options = TreeOptions {
+        on_error: Some(Box::new(move |path, _err| {
+            sink.lock().unwrap().push(path.to_path_buf());
+        })),
+        ..Default::default()
+    };
+    let result = list_directory(fixture.path(), &options);
+    let fixture = fixture.mode("locked", 0o755);
+
+    assert!(
+        result.is_ok(),
+        "An unreadable subdirectory should not abort"
+    );
+    assert_eq!(vec![fixture.join("locked")], *errors.lock().unwrap());
+}
+
+#[test]
+fn test_on_warning_collects_warnings() {
+    let fixture = Fixture::standard();
+    let warnings: Arc<Mutex<Vec<(PathBuf, String)>>> = Arc::default();
+    let sink = Arc::clone(&warnings);
+
+    let options = TreeOptions {
+        auto_limit: Some(2),
+        on_warning: Some(Box::new(move |warning| {
+            assert!(matches!(
+                warning,
+                TraversalWarning::Truncated {
+                    entries: 3,
+                    listed: 2,
+                    ..
+                }
+            ));
+            let warning = (warning.path().to_path_buf(), warning.to_string());
+            sink.lock().unwrap().push(warning);
+        })),
+        ..Default::default()
+    };
+    let tree = build_tree(fixture.path(), &options).unwrap();
+
+    assert_eq!(2, tree.children.len());
+    let message = format!(
+        "{}: 3 entries, listing the first 2",
+        fixture.path().display()
+    );
+    assert_eq!(
+        vec![(fixture.path().to_path_buf(), message)],
+        *warnings.lock().unwrap()
+    );
+}
+
+#[test]
+fn test_build_tree() {
+    let fixture = Fixture::standard().sized_file("dir2/big.bin", 100);
+    let options = TreeOptions {
+        sort: SortKey::Size,
+        ..Default::default()
+    };
+
+    let tree = build_tree(fixture.path(), &options).unwrap();
+
+    fn names(node: &TreeNode) -> Vec<&str> {
+        node.children
+            .iter()
+            .map(|child| child.name.as_str())
+            .collect()
+    }
+    assert_eq!("test_directory", tree.name);
+    assert_eq!(EntryKind::Directory, tree.entry.kind);
+    assert_eq!(vec!["dir1", "dir2", "file1.txt"], names(&tree));
+    let dir2 = &tree.children[1];
+    assert_eq!(vec!["big.bin", "file3.txt"], names(dir2));
+    assert_eq!(EntryKind::File, dir2.children[0].entry.kind);
+    assert_eq!(Some(100), dir2.children[0].entry.size);
+    assert!(dir2.children[0].entry.modified.is_some());
+    assert!(dir2.children[0].children.is_empty());
+}
+
+#[cfg(unix)]
+#[test]
+fn test_entry_dir_id() {
+    let fixture = Fixture::standard().symlink("link", "dir1");
+    let entries = Filesystem.read_entries(fixture.path()).unwrap();
+    let entry = |name: &str| {
+        entries
+            .iter()
+            .find(|entry| entry.file_name == name)
+            .unwrap()
+    };
+
+    // Read along with the metadata, directly or through the link
+    let dir1 = dir_id(&fixture.join("dir1"));
+    assert!(dir1.is_some());
+    assert_eq!(dir1, entry("dir1").dir_id);
+    assert_eq!(dir1, entry("link").dir_id);
+    assert_eq!(None, entry("file1.txt").dir_id);
+}
+
+#[test]
+fn test_sort_by() {
+    let fixture = Fixture::standard()
+        .sized_file("dir2/big.bin", 100)
+        .file("zebra.txt");
+    let options = TreeOptions {
+        sort_by: Some(Box::new(|a, b| b.file_name.cmp(&a.file_name))),
+        sort: SortKey::Size,
+        dirs_first: true,
+        ..Default::default()
+    };
+
+    let tree = build_tree(fixture.path(), &options).unwrap();
+
+    fn names(node: &TreeNode) -> Vec<&str> {
+        node.children
+            .iter()
+            .map(|child| child.name.as_str())
+            .collect()
+    }
+    // Replaces the size order, with directories still listed first
+    assert_eq!(vec!["dir2", "dir1", "zebra.txt", "file1.txt"], names(&tree));
+    assert_eq!(vec!["file3.txt", "big.bin"], names(&tree.children[0]));
+}
+
+#[test]
+fn test_filter() {
+    let fixture = Fixture::standard();
+    let skipped: Arc<Mutex<Vec<(PathBuf, SkipReason)>>> = Arc::default();
+    let sink = Arc::clone(&skipped);
+    let options = TreeOptions {
+        // Leaves out dir1 and everything below it, and files below the top
+        filter: Some(Box::new(|entry, depth| {
+            entry.file_name != "dir1" && (depth == 0 || entry.kind == EntryKind::Directory)
+        })),
+        on_skip: Some(Box::new(move |path, reason| {
+            sink.lock().unwrap().push((path.to_path_buf(), reason));
+        })),
+        ..Default::default()
+    };
+
+    let tree = build_tree(fixture.path(), &options).unwrap();
+
+    let names: Vec<&str> = tree
+        .children
+        .iter()
+        .map(|child| child.name.as_str())
+        .collect();
+    assert_eq!(vec!["dir2", "file1.txt"], names);
+    assert!(tree.children[0].children.is_empty());
+    let mut skipped = skipped.lock().unwrap().clone();
+    skipped.sort_by(|a, b| a.0.cmp(&b.0));
+    assert_eq!(
+        vec![
+            (fixture.join(".hidden.txt"), SkipReason::Hidden),
+            (fixture.join("dir1"), SkipReason::Filtered),
+            (fixture.join("dir2/file3.txt"), SkipReason::Filtered),
+        ],
+        skipped
+    );
+}
+
+/// Adds each file's size in bytes to the built-in line.
+struct WithBytes;
+
+impl EntryFormatter for WithBytes {
+    fn format_entry(
+        &self,
+        node: &TreeNode,
+        options: &TreeOptions,
+        dir_sizes: &HashMap<PathBuf, u64>,
+    ) -> String {
+        let line = DefaultFormatter.format_entry(node, options, dir_sizes);
+        match node.entry.size {
+            Some(size) if !node.entry.is_dir => format!("{} = {} bytes", line, size),
+            _ => line,
+        }
+    }
+}
+
+#[test]
+fn test_formatter() {
+    let fixture = Fixture::standard().sized_file("dir2/big.bin", 100);
+    let output = fixture.parent().join("listing.txt");
+    let options = TreeOptions {
+        formatter: Some(Box::new(WithBytes)),
+        classify: true,
+        output: Some(output.clone()),
+        ..Default::default()
+    };
+
+    list_directory(fixture.path(), &options).unwrap();
+
+    let listing = std::fs::read_to_string(output).unwrap();
+    let lines: Vec<&str> = listing.lines().collect();
+    assert_eq!(
+        vec![
+            "test_directory",
+            "├── dir1/",
+            "│   ├── dir1_1/",
+            "│   └── file2.txt = 0 bytes",
+            "├── dir2/",
+            "│   ├── big.bin = 100 bytes",
+            "│   └── file3.txt = 0 bytes",
+            "└── file1.txt = 0 bytes",
+            "",
+            "3 directories, 4 files",
+        ],
+        lines
+    );
+}
+
+#[test]
+fn test_render_roots() {
+    let listing = Listing::parse("src/main.rs\nsrc/lib/\nREADME.md\n", None).unwrap();
+    let roots = [ListingRoot {
+        source: &listing,
+        path: PathBuf::new(),
+        name: "upload".to_string(),
+    }];
+    let options = TreeOptions {
+        dirs_first: true,
+        ..Default::default()
+    };
+
+    let text = render_roots(&roots, &options).unwrap();
+
+    assert_eq!(
+        "upload\n\
+         ├── src\n\
+         │   ├── lib\n\
+         │   └── main.rs\n\
+         └── README.md\n\
+         \n\
+         2 directories, 2 files\n",
+        text
+    );
+}
+
+#[test]
+fn test_deep_tree() {
+    /// A chain of directories `DEPTH` levels deep, far deeper than the call
+    /// stack would allow recursing into. Each level's path is its depth, as
+    /// full paths that long would not fit in memory, let alone a real
+    /// filesystem.
+    struct Deep;
+    const DEPTH: usize = 10_000;
+
+    impl EntrySource for Deep {
+        fn read_entries(&self, dir: &Path) -> io::Result<Vec<EntryInfo>> {
+            let depth: usize = dir.to_str().unwrap().parse().unwrap();
+            let path = PathBuf::from((depth + 1).to_string());
+            let mut entry = if depth < DEPTH {
+                EntryInfo::new(path, EntryKind::Directory)
+            } else {
+                EntryInfo::new(path, EntryKind::File)
+            };
+            entry.file_name = "f".into();
+            entry.size = Some(1);
+            Ok(vec![entry])
+        }
+
+        fn dir_id(&self, _path: &Path) -> Option<DirId> {
+            None
+        }
+
+        fn entry(&self, path: &Path) -> io::Result<EntryInfo> {
+            Ok(EntryInfo::new(path.to_path_buf(), EntryKind::Directory))
+        }
+    }
+
+    let options = TreeOptions {
+        // Indenting each line by its depth would print hundreds of megabytes
+        no_indent: true,
+        disk_usage: true,
+        ..Default::default()
+    };
+    let tree = build_tree_from_source(&Deep, Path::new("0"), &options).unwrap();
+    let mut depth = 0;
+    let mut node = &tree;
+    while let Some(child) = node.children.first() {
+        depth += 1;
+        node = child;
+    }
+    // The file is inside the deepest directory
+    assert_eq!(DEPTH + 1, depth);
+    assert_eq!(EntryKind::File, node.entry.kind);
+    drop(tree);
+
+    let roots = [ListingRoot {
+        source: &Deep,
+        path: PathBuf::from("0"),
+        name: "deep".to_string(),
+    }];
+    let text = render_roots(&roots, &options).unwrap();
+    assert_eq!(DEPTH + 4, text.lines().count());
+    // The file's size is totalled all the way up
+    assert!(text.starts_with("deep\nf (    1B)\n"));
+    assert!(text.ends_with("\n1 bytes used in 10000 directories, 1 files\n"));
+
+    // Unsorted, the tree is printed as it is read
+    let options = TreeOptions {
+        no_indent: true,
+        sort: SortKey::None,
+        ..Default::default()
+    };
+    let text = render_roots(&roots, &options).unwrap();
+    assert_eq!(DEPTH + 4, text.lines().count());
+    assert!(text.ends_with("\n10000 directories, 1 files\n"));
+}
+
+#[test]
+fn test_unsorted_listing_reads_as_it_prints() {
+    /// Counts the directories read.
+    #[derive(Default)]
+    struct CountingSource {
+        reads: Mutex<usize>,
+    }
+
+    impl EntrySource for CountingSource {
+        fn read_entries(&self, dir: &Path) -> io::Result<Vec<EntryInfo>> {
+            *self.reads.lock().unwrap() += 1;
+            Filesystem.read_entries(dir)
+        }
+
+        fn dir_id(&self, path: &Path) -> Option<DirId> {
+            Filesystem.dir_id(path)
+        }
+
+        fn entry(&self, path: &Path) -> io::Result<EntryInfo> {
+            Filesystem.entry(path)
+        }
+    }
+
+    let mut fixture = Fixture::new("streamed");
+    for dir in 0..10 {
+        fixture = fixture.file(&format!("d{}/sub/file.txt", dir));
+    }
+    let listing = |source: &CountingSource, sort| {
+        let options = TreeOptions {
+            head: Some(3),
+            sort,
+            ..Default::default()
+        };
+        let roots = [ListingRoot {
+            source,
+            path: fixture.path().to_path_buf(),
+            name: "streamed".to_string(),
+        }];
+        render_roots(&roots, &options).unwrap()
+    };
+
+    // Sorted, the whole tree is read before the first line is printed
+    let source = CountingSource::default();
+    listing(&source, SortKey::Name);
+    assert_eq!(21, *source.reads.lock().unwrap());
+
+    // Unsorted, reading stops with printing, after the first directory
+    let source = CountingSource::default();
+    let text = listing(&source, SortKey::None);
+    assert_eq!(3, *source.reads.lock().unwrap(), "{}", text);
+    assert!(text.contains("[output truncated after 3 lines"), "{}", text);
+}
+
+#[cfg(unix)]
+#[test]
+fn test_recursive_links() {
+    let fixture = Fixture::new("loops")
+        .file("real/file.txt")
+        .symlink("real/up", "..");
+    let options = TreeOptions {
+        follow_links: true,
+        ..Default::default()
+    };
+
+    let tree = build_tree(fixture.path(), &options).unwrap();
+    let up = &tree.children[0].children[1];
+    assert_eq!("up", up.name);
+    assert!(up.recursive);
+    assert!(up.children.is_empty());
+
+    let recursive: Vec<String> = TreeWalker::new(fixture.path(), &options)
+        .map(Result::unwrap)
+        .filter(|walked| walked.recursive)
+        .map(|walked| walked.entry.file_name.to_string_lossy().to_string())
+        .collect();
+    assert_eq!(vec!["up"], recursive);
+}
+
+#[test]
+fn test_tree_walker() {
+    let fixture = Fixture::standard();
+    let options = TreeOptions {
+        exclude_patterns: vec![Pattern::new("file3.txt").unwrap()],
+        ..Default::default()
+    };
+
+    // Enough to draw the tree as the command line does
+    let lines: Vec<String> = TreeWalker::new(fixture.path(), &options)
+        .map(|walked| {
+            let walked = walked.unwrap();
+            let mut line: String = walked
+                .parents_last
+                .iter()
+                .map(|&last| if last { "    " } else { "│   " })
+                .collect();
+            line.push_str(if walked.is_last {
+                "└── "
+            } else {
+                "├── "
+            });
+            line.push_str(&walked.entry.file_name.to_string_lossy());
+            line
+        })
+        .collect();
+    assert_eq!(
+        vec![
+            "├── dir1",
+            "│   ├── dir1_1",
+            "│   └── file2.txt",
+            "├── dir2",
+            "└── file1.txt",
+        ],
+        lines
+    );
+
+    let depths: Vec<usize> = TreeWalker::new(fixture.path(), &options)
+        .map(|walked| walked.unwrap().depth)
+        .collect();
+    assert_eq!(vec![0, 1, 1, 0, 0], depths);
+
+    let mut walker = TreeWalker::new(fixture.join("missing"), &options);
+    assert!(walker.next().unwrap().is_err());
+    assert!(walker.next().is_none());
+}
+
+#[cfg(unix)]
+#[test]
+fn test_filesystem_kinds() {
+    let fixture = Fixture::standard().symlink("link", "dir1");
+    let mut entries = FilesystemKinds.read_entries(fixture.path()).unwrap();
+    entries.sort_by(|a, b| a.file_name.cmp(&b.file_name));
+
+    let kinds: Vec<_> = entries
+        .iter()
+        .map(|entry| (entry.file_name.to_str().unwrap(), entry.kind, entry.is_dir))
+        .collect();
+    assert_eq!(
+        vec![
+            (".hidden.txt", EntryKind::File, false),
+            ("dir1", EntryKind::Directory, true),
+            ("dir2", EntryKind::Directory, true),
+            ("file1.txt", EntryKind::File, false),
+            ("link", EntryKind::Symlink, true),
+        ],
+        kinds
+    );
+    // Nothing else is looked up
+    assert!(entries
+        .iter()
+        .all(|entry| entry.size.is_none() && entry.modified.is_none()));
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn test_filesystem_acls() {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let fixture = Fixture::standard();
+    // An access ACL granting user 65534 read and write, as `setfacl` writes
+    let mut acl = 2u32.to_le_bytes().to_vec();
+    for (tag, perm, id) in [
+        (0x01u16, 6u16, u32::MAX),
+        (0x02, 6, 65534),
+        (0x04, 4, u32::MAX),
+        (0x10, 6, u32::MAX),
+        (0x20, 4, u32::MAX),
+    ] {
+        acl.extend(tag.to_le_bytes());
+        acl.extend(perm.to_le_bytes());
+        acl.extend(id.to_le_bytes());
+    }
+    let path = CString::new(fixture.join("file1.txt").as_os_str().as_bytes()).unwrap();
+    let name = c"system.posix_acl_access";
+    // SAFETY: both strings are NUL-terminated and `acl` is `acl.len()` long.
+    let set = unsafe {
+        libc::setxattr(
+            path.as_ptr(),
+            name.as_ptr(),
+            acl.as_ptr().cast(),
+            acl.len(),
+            0,
+        )
+    };
+    if set != 0 {
+        // The filesystem holding the fixture has no ACLs
+        return;
+    }
+
+    let with_acl = |source: &dyn EntrySource| {
+        let mut names: Vec<_> = source
+            .read_entries(fixture.path())
+            .unwrap()
+            .into_iter()
+            .filter(|entry| entry.has_acl)
+            .map(|entry| entry.file_name)
+            .collect();
+        names.sort();
+        names
+    };
+    assert_eq!(vec!["file1.txt"], with_acl(&FilesystemAcls));
+    assert!(
+        with_acl(&Filesystem).is_empty(),
+        "ACLs should only be looked up when asked for"
+    );
+}
+
+#[test]
+fn test_file_limit_reads_each_directory_once() {
+    /// Counts how often each directory is read.
+    #[derive(Default)]
+    struct CountingSource {
+        reads: Mutex<HashMap<PathBuf, usize>>,
+    }
+
+    impl EntrySource for CountingSource {
+        fn read_entries(&self, dir: &Path) -> io::Result<Vec<EntryInfo>> {
+            *self
+                .reads
+                .lock()
+                .unwrap()
+                .entry(dir.to_path_buf())
+                .or_default() += 1;
+            Filesystem.read_entries(dir)
+        }
+
+        fn dir_id(&self, path: &Path) -> Option<DirId> {
+            Filesystem.dir_id(path)
+        }
+
+        fn entry(&self, path: &Path) -> io::Result<EntryInfo> {
+            Filesystem.entry(path)
+        }
+    }
+
+    let fixture = Fixture::standard();
+    let options = TreeOptions {
+        file_limit: Some(1),
+        ..Default::default()
+    };
+    let expected = HashMap::from([
+        (fixture.path().to_path_buf(), 1),
+        (fixture.join("dir1"), 1),
+        (fixture.join("dir2"), 1),
+    ]);
+
+    let source = CountingSource::default();
+    let tree = build_tree_from_source(&source, fixture.path(), &options).unwrap();
+    assert_eq!(Some(2), tree.children[0].not_opened);
+    assert_eq!(expected, *source.reads.lock().unwrap());
+
+    let source = CountingSource::default();
+    let walked = TreeWalker::with_source(&source, fixture.path(), &options).count();
+    assert_eq!(4, walked);
+    assert_eq!(expected, *source.reads.lock().unwrap());
+}
+
+#[test]
+fn test_cancel() {
+    let fixture = Fixture::standard();
+    let cancel = Arc::new(AtomicBool::new(false));
+    let options = TreeOptions {
+        cancel: Some(Arc::clone(&cancel)),
+        ..Default::default()
+    };
+
+    // Cancelled partway through a walk, e.g. from another thread
+    let mut walker = TreeWalker::new(fixture.path(), &options);
+    assert!(walker.next().unwrap().is_ok());
+    cancel.store(true, Ordering::Relaxed);
+    let err = walker.next().unwrap().unwrap_err();
+    assert_eq!(ErrorKind::Interrupted, err.kind());
+    assert!(walker.next().is_none());
+
+    let err = build_tree(fixture.path(), &options).unwrap_err();
+    assert_eq!(ErrorKind::Interrupted, err.kind());
+    let patterns = [Pattern::new("file3.txt").unwrap()];
+    let err = find_first(fixture.path(), &patterns, &options).unwrap_err();
+    assert_eq!(ErrorKind::Interrupted, err.kind());
+
+    cancel.store(false, Ordering::Relaxed);
+    assert!(build_tree(fixture.path(), &options).is_ok());
+}
+
+#[test]
+fn test_on_progress() {
+    let fixture = Fixture::standard();
+    let calls: Arc<Mutex<Vec<(u64, PathBuf, bool)>>> = Arc::default();
+    let sink = Arc::clone(&calls);
+    let options = TreeOptions {
+        on_progress: Some(Box::new(move |progress| {
+            let call = (
+                progress.entries,
+                progress.current_dir.to_path_buf(),
+                progress.done,
+            );
+            sink.lock().unwrap().push(call);
+        })),
+        ..Default::default()
+    };
+
+    build_tree(fixture.path(), &options).unwrap();
+
+    // Called as soon as the root is read, then throttled until done
+    let calls = calls.lock().unwrap();
+    assert_eq!((4, fixture.path().to_path_buf(), false), calls[0]);
+    let last = calls.last().unwrap();
+    assert_eq!((7, fixture.path().to_path_buf(), true), *last);
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn test_serde_roundtrip() {
+    let fixture = Fixture::standard().sized_file("dir2/big.bin", 100);
+    let tree = build_tree(fixture.path(), &TreeOptions::default()).unwrap();
+
+    let json = serde_json::to_string(&tree).unwrap();
+    let restored: TreeNode = serde_json::from_str(&json).unwrap();
+
+    assert_eq!(tree.name, restored.name);
+    let dir2 = &restored.children[1];
+    assert_eq!("dir2", dir2.name);
+    assert_eq!(EntryKind::Directory, dir2.entry.kind);
+    assert_eq!(Some(100), dir2.children[0].entry.size);
+    assert_eq!(
+        tree.children[1].children[0].entry.modified,
+        dir2.children[0].entry.modified
+    );
+    assert_eq!(fixture.join("dir2/big.bin"), dir2.children[0].entry.path);
+}
+
+#[cfg(feature = "tokio")]
+#[tokio::test]
+async fn test_build_tree_async() {
+    use rust_tree::rust_tree::async_tree::build_tree_async;
+
+    let fixture = Fixture::standard().file_with_content("dir1/.gitignore", "file2.txt\n");
+    let options = TreeOptions {
+        gitignore: true,
+        sort: SortKey::Size,
+        ..Default::default()
+    };
+
+    fn lines(node: &TreeNode, depth: usize, out: &mut Vec<String>) {
+        for child in &node.children {
+            out.push(format!("{}{}", "  ".repeat(depth), child.name));
+            lines(child, depth + 1, out);
+        }
+    }
+    let (mut expected, mut actual) = (Vec::new(), Vec::new());
+    lines(
+        &build_tree(fixture.path(), &options).unwrap(),
+        0,
+        &mut expected,
+    );
+    let tree = build_tree_async(fixture.path(), &options).await.unwrap();
+    lines(&tree, 0, &mut actual);
+    assert_eq!(expected, actual);
+    assert!(!actual.contains(&"  file2.txt".to_string()));
+
+    assert!(build_tree_async(fixture.join("missing"), &options)
+        .await
+        .is_err());
+}
+
+#[cfg(feature = "ffi")]
+#[test]
+fn test_ffi_render() {
+    use rust_tree::rust_tree::ffi::{rust_tree_default_options, rust_tree_render};
+    use rust_tree::rust_tree::traversal::list_directory_as_string;
+    use std::ffi::{c_void, CStr, CString};
+
+    extern "C" {
+        fn free(ptr: *mut c_void);
+    }
+
+    let fixture = Fixture::standard();
+    let path = CString::new(fixture.arg()).unwrap();
+    let exclude = CString::new("dir2|*.md").unwrap();
+    let mut options = rust_tree_default_options();
+    options.level = 1;
+    options.exclude = exclude.as_ptr();
+
+    let text = unsafe {
+        let rendered = rust_tree_render(path.as_ptr(), &options);
+        assert!(!rendered.is_null());
+        let text = CStr::from_ptr(rendered).to_str().unwrap().to_string();
+        free(rendered.cast());
+        text
+    };
+    let expected = TreeOptions {
+        level: Some(1),
+        exclude_patterns: vec![Pattern::new("dir2").unwrap(), Pattern::new("*.md").unwrap()],
+        ..Default::default()
+    };
+    assert_eq!(
+        list_directory_as_string(fixture.path(), &expected).unwrap(),
+        text
+    );
+
+    let missing = CString::new(fixture.join("missing").to_str().unwrap()).unwrap();
+    assert!(unsafe { rust_tree_render(missing.as_ptr(), std::ptr::null()) }.is_null());
+
+    // The committed header is the one generated from the bindings
+    assert_eq!(
+        include_str!(concat!(env!("OUT_DIR"), "/rust_tree.h")),
+        include_str!("../include/rust_tree.h")
+    );
+}
+
+#[test]
+fn test_truncate_line_widths() {
+    // Each of these takes two columns
+    assert_eq!("├── 漢字漢字…", truncate_line("├── 漢字漢字漢字.txt", 14));
+    assert_eq!("📁 a…", truncate_line("📁 abc", 5));
+    assert_eq!("⚙️ a…", truncate_line("⚙️ abc", 5));
+    assert_eq!("📁 abc", truncate_line("📁 abc", 6));
+    // Escape sequences take none, and colors cut short are closed
+    assert_eq!(
+        "\x1b[1;34mab…\x1b[0m",
+        truncate_line("\x1b[1;34mabcd\x1b[0m", 3)
+    );
+    assert_eq!("…", truncate_line("abc", 1));
+    assert_eq!("", truncate_line("abc", 0));
+    assert_eq!("", truncate_line("", 0));
+}
+
+#[test]
+fn test_unix_time_before_epoch() {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    // 1969-12-31 23:59:58.75, as stat gives it: -2 seconds, +750ms
+    assert_eq!(
+        Some(UNIX_EPOCH - Duration::from_millis(1250)),
+        unix_time(-2, 750_000_000)
+    );
+    assert_eq!(
+        Some(UNIX_EPOCH + Duration::from_millis(2750)),
+        unix_time(2, 750_000_000)
+    );
+    assert_eq!(Some(UNIX_EPOCH), unix_time(0, 0));
+    assert_eq!(None, unix_time(0, 1_000_000_000));
+}
@@ -0,0 +1,118 @@
+//! Listing a tree, writing it out and reading it back with `--fromfile` must
+//! give the same tree. These keep the listing parsers in step with what the
+//! renderer and the filesystem report.
+
+mod common;
+
+use std::fs;
+#[cfg(unix)]
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+
+use common::{run_cmd, run_cmd_in, Fixture};
+
+/// A fixture where every directory has contents, since a plain listing can
+/// only tell a directory from a file by what is listed inside it.
+fn fixture() -> Fixture {
+    let time = SystemTime::UNIX_EPOCH + Duration::from_secs(1_600_000_000);
+    Fixture::new("project")
+        .sized_file("src/main.rs", 1200)
+        .sized_file("src/util/strings.rs", 300)
+        .sized_file("docs/guide.md", 4096)
+        .sized_file(".config", 10)
+        .sized_file("README.md", 64)
+        .modified("src/main.rs", time)
+        .modified("docs/guide.md", time)
+}
+
+/// The listing below the root line.
+fn body(output: &str) -> String {
+    output.lines().skip(1).collect::<Vec<_>>().join("\n")
+}
+
+/// Writes a `--fromfile` TSV record for every entry below `dir`.
+fn write_tsv(root: &Path, dir: &Path, tsv: &mut String) {
+    for entry in fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        let metadata = fs::symlink_metadata(&path).unwrap();
+        let mtime = metadata
+            .modified()
+            .unwrap()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_secs_f64();
+        #[cfg(unix)]
+        let mode = format!("{:o}", metadata.mode());
+        // Modes are not read from the filesystem elsewhere
+        #[cfg(not(unix))]
+        let mode = "-";
+        let relative = path.strip_prefix(root).unwrap().to_str().unwrap();
+        tsv.push_str(&format!(
+            "{}\t{}\t{}\t{}\n",
+            relative.replace('\\', "/"),
+            metadata.len(),
+            mtime,
+            mode
+        ));
+        if metadata.is_dir() {
+            write_tsv(root, &path, tsv);
+        }
+    }
+}
+
+#[test]
+fn test_plain_listing_round_trip() {
+    let fixture = fixture();
+    // -f -i prints one path per line, which is a plain listing once the root
+    // line and the report are dropped
+    let output = run_cmd_in(fixture.path(), &["-a", "-f", "-i", "."]);
+    let listing: String = output
+        .lines()
+        .skip(1)
+        .take_while(|line| !line.is_empty())
+        .map(|line| format!("{}\n", line))
+        .collect();
+    let listing_path = fixture.parent().join("listing.txt");
+    fs::write(&listing_path, listing).unwrap();
+
+    for args in [&["-a"][..], &[], &["-d"], &["-L", "1"], &["-P", "*.rs"]] {
+        let from_disk = run_cmd_in(fixture.path(), &[args, &["."]].concat());
+        let from_listing =
+            run_cmd(&[args, &["--fromfile", listing_path.to_str().unwrap()]].concat());
+        assert_eq!(
+            body(&from_disk),
+            body(&from_listing),
+            "Listings should read back as the same tree with {:?}",
+            args
+        );
+    }
+}
+
+#[test]
+fn test_tsv_listing_round_trip() {
+    let fixture = fixture();
+    let mut tsv = String::new();
+    write_tsv(fixture.path(), fixture.path(), &mut tsv);
+    let listing_path = fixture.parent().join("listing.tsv");
+    fs::write(&listing_path, tsv).unwrap();
+
+    // Sizes, dates and modes must survive as well as names and kinds
+    for args in [
+        &["-a", "-s"][..],
+        &["--du"],
+        &["-D", "--timefmt", "%Y-%m-%d %H:%M:%S"],
+        &["-p", "--mode", "both"],
+        &["--sort", "size", "--dirsfirst"],
+    ] {
+        let from_disk = run_cmd_in(fixture.path(), &[args, &["."]].concat());
+        let from_listing =
+            run_cmd(&[args, &["--fromfile", listing_path.to_str().unwrap()]].concat());
+        assert_eq!(
+            body(&from_disk),
+            body(&from_listing),
+            "TSV listings should read back with the same metadata with {:?}",
+            args
+        );
+    }
+}
@@ -0,0 +1,92 @@
+//! Snapshot tests pinning the exact rendering of the text output. Review
+//! changes with `cargo insta review` (or `INSTA_UPDATE=always cargo test`).
+mod common;
+
+use common::{run_cmd_in, Fixture};
+
+fn snapshot_fixture() -> Fixture {
+    Fixture::standard()
+        .sized_file("dir2/archive.tar.gz", 1536)
+        .sized_file("dir1/dir1_1/photo.png", 3 * 1024 * 1024)
+        .sized_file("run.sh", 64)
+}
+
+/// Adds the entries only Unix can create portably: an executable and a link.
+#[cfg(unix)]
+fn unix_snapshot_fixture() -> Fixture {
+    snapshot_fixture()
+        .mode("run.sh", 0o755)
+        .symlink("link", "dir1")
+}
+
+fn render_fixture(fixture: &Fixture, args: &[&str]) -> String {
+    let mut args = args.to_vec();
+    args.push("test_directory");
+    run_cmd_in(fixture.parent(), &args)
+}
+
+fn render(args: &[&str]) -> String {
+    render_fixture(&snapshot_fixture(), args)
+}
+
+#[test]
+fn snapshot_default() {
+    insta::assert_snapshot!(render(&[]));
+}
+
+#[test]
+fn snapshot_all_files() {
+    insta::assert_snapshot!(render(&["-a"]));
+}
+
+#[test]
+fn snapshot_max_depth() {
+    insta::assert_snapshot!(render(&["-L", "1"]));
+}
+
+#[test]
+fn snapshot_dir_only() {
+    insta::assert_snapshot!(render(&["-d"]));
+}
+
+#[test]
+fn snapshot_flat_full_path() {
+    insta::assert_snapshot!(render(&["-f", "-i"]));
+}
+
+#[test]
+fn snapshot_sizes() {
+    insta::assert_snapshot!(render(&["-s"]));
+}
+
+#[test]
+fn snapshot_human_readable_sizes() {
+    insta::assert_snapshot!(render(&["-h"]));
+}
+
+#[test]
+fn snapshot_patterns() {
+    insta::assert_snapshot!(render(&["-P", "*.txt|*.sh", "-I", "dir2"]));
+}
+
+#[cfg(all(unix, feature = "color"))]
+#[test]
+fn snapshot_color() {
+    insta::assert_snapshot!(render_fixture(&unix_snapshot_fixture(), &["-C"]));
+}
+
+#[cfg(unix)]
+#[test]
+fn snapshot_follow_links() {
+    insta::assert_snapshot!(render_fixture(&unix_snapshot_fixture(), &["-l"]));
+}
+
+#[test]
+fn snapshot_group_by_type() {
+    insta::assert_snapshot!(render(&["--group-by", "type"]));
+}
+
+#[test]
+fn snapshot_group_by_extension() {
+    insta::assert_snapshot!(render(&["--group-by", "ext"]));
+}
@@ -1,39 +1,12 @@
-use std::fs::{create_dir_all, File};
-use std::process::Command;
-
-fn run_cmd(arg: &[&str]) -> String {
-    let binary_path = if cfg!(windows) {
-        "target\\debug\\tree.exe"
-    } else {
-        "target/debug/tree"
-    };
-
-    let stdout = Command::new(binary_path)
-        .args(arg)
-        .output()
-        .expect("command failed")
-        .stdout;
-    let stdout_str = String::from_utf8(stdout).expect("Bad parsing");
-    stdout_str
-}
-
-fn create_test_directory() {
-    let base = "tests/test_directory";
-    create_dir_all(format!("{}/dir1/dir1_1", base)).unwrap();
-    create_dir_all(format!("{}/dir2", base)).unwrap();
-    File::create(format!("{}/file1.txt", base)).unwrap();
-    File::create(format!("{}/dir1/file2.txt", base)).unwrap();
-    File::create(format!("{}/dir2/file3.txt", base)).unwrap();
-    File::create(format!("{}/.hidden.txt", base)).unwrap();
-}
+mod common;
 
-fn last_line(output: &str) -> &str {
-    output.lines().last().unwrap_or("")
-}
+use common::{last_line, run_cmd, run_cmd_in, run_cmd_with_status, Fixture};
+use std::process::Command;
+use std::time::{Duration, SystemTime};
 
 #[test]
 fn test_normal() {
-    create_test_directory();
+    let fixture = Fixture::standard();
     let expected = r#"test_directory
 ├── dir1
 │   ├── dir1_1
@@ -45,13 +18,13 @@ fn test_normal() {
 3 directories, 3 files
 "#;
 
-    let output = run_cmd(&["tests/test_directory"]);
+    let output = run_cmd(&[fixture.arg()]);
     assert_eq!(expected, output);
 }
 
 #[test]
 fn test_max_depth() {
-    create_test_directory();
+    let fixture = Fixture::standard();
     let expected = r#"test_directory
 ├── dir1
 ├── dir2
@@ -60,13 +33,13 @@ fn test_max_depth() {
 2 directories, 1 files
 "#;
 
-    let output = run_cmd(&["-L", "1", "tests/test_directory"]);
+    let output = run_cmd(&["-L", "1", fixture.arg()]);
     assert_eq!(expected, output);
 }
 
 #[test]
 fn test_filter_txt_files() {
-    create_test_directory();
+    let fixture = Fixture::standard();
     let expected = r#"test_directory
 ├── dir1
 │   ├── dir1_1
@@ -78,51 +51,2167 @@ fn test_filter_txt_files() {
 3 directories, 3 files
 "#;
 
-    let output = run_cmd(&["-P", "*.txt", "tests/test_directory"]);
+    let output = run_cmd(&["-P", "*.txt", fixture.arg()]);
     assert_eq!(expected, output);
 }
 
 #[test]
 fn test_normal_summary() {
-    create_test_directory();
+    let fixture = Fixture::standard();
     let expected = "3 directories, 3 files";
 
-    let output = run_cmd(&["tests/test_directory"]);
+    let output = run_cmd(&[fixture.arg()]);
     assert_eq!(expected, last_line(&output));
 }
 
 #[test]
 fn test_max_depth_summary() {
-    create_test_directory();
+    let fixture = Fixture::standard();
     let expected = "2 directories, 1 files";
 
-    let output = run_cmd(&["-L", "1", "tests/test_directory"]);
+    let output = run_cmd(&["-L", "1", fixture.arg()]);
     assert_eq!(expected, last_line(&output));
 }
 
 #[test]
 fn test_filter_txt_files_summary() {
-    create_test_directory();
+    let fixture = Fixture::standard();
     let expected = "3 directories, 3 files";
 
-    let output = run_cmd(&["-P", "*.txt", "tests/test_directory"]);
+    let output = run_cmd(&["-P", "*.txt", fixture.arg()]);
     assert_eq!(expected, last_line(&output));
 }
 
 #[test]
 fn test_hidden_files() {
-    create_test_directory();
-    let output = run_cmd(&["tests/test_directory"]);
+    let fixture = Fixture::standard();
+    let output = run_cmd(&[fixture.arg()]);
 
     assert!(
         !output.contains(".hidden.txt"),
         "Hidden files should not be listed without -a flag"
     );
 
-    let output = run_cmd(&["-a", "tests/test_directory"]);
+    let output = run_cmd(&["-a", fixture.arg()]);
 
     assert!(
         output.contains(".hidden.txt"),
         "Hidden files should be listed with -a flag"
     );
+    assert_eq!(
+        "3 directories, 4 files",
+        last_line(&output),
+        "Hidden entries listed with -a should be counted"
+    );
+}
+
+#[test]
+fn test_multiple_exclude_patterns() {
+    let fixture = Fixture::standard();
+    let expected = r#"test_directory
+└── dir2
+    └── file3.txt
+
+1 directories, 1 files
+"#;
+
+    let output = run_cmd(&["-I", "dir1", "-I", "file1*", fixture.arg()]);
+    assert_eq!(expected, output);
+}
+
+#[test]
+fn test_pattern_alternatives() {
+    let fixture = Fixture::standard();
+    let expected = r#"test_directory
+├── dir1
+│   └── file2.txt
+└── dir2
+    └── file3.txt
+
+2 directories, 2 files
+"#;
+
+    let output = run_cmd(&["-P", "file2*|file3*", "-I", "dir1_1|nothing", fixture.arg()]);
+    assert_eq!(expected, output);
+}
+
+/// Checks that the branch glyphs of a rendered tree are balanced: every `│`
+/// continues an open sibling chain and every chain is closed by a `└──`.
+fn assert_balanced_glyphs(output: &str, args: &[&str]) {
+    let mut open: Vec<bool> = Vec::new();
+    for line in output.lines().skip(1).take_while(|line| !line.is_empty()) {
+        let chars: Vec<char> = line.chars().collect();
+        let mut depth = 0;
+        while chars.len() >= (depth + 1) * 4 {
+            let column: String = chars[depth * 4..(depth + 1) * 4].iter().collect();
+            match column.as_str() {
+                "│   " | "    " => depth += 1,
+                _ => break,
+            }
+        }
+        let connector: String = chars.iter().skip(depth * 4).take(4).collect();
+        assert!(
+            connector == "├── " || connector == "└── ",
+            "missing connector in {:?} for {:?}",
+            line,
+            args
+        );
+        assert!(
+            depth <= open.len(),
+            "skipped a level in {:?} for {:?}",
+            line,
+            args
+        );
+        for (i, is_open) in open.iter().enumerate().take(depth) {
+            let column: String = chars[i * 4..(i + 1) * 4].iter().collect();
+            assert_eq!(
+                *is_open,
+                column == "│   ",
+                "unbalanced column {} in {:?} for {:?}",
+                i,
+                line,
+                args
+            );
+        }
+        assert!(
+            open.iter().skip(depth + 1).all(|is_open| !is_open),
+            "unclosed branch before {:?} for {:?}",
+            line,
+            args
+        );
+        open.truncate(depth);
+        open.push(connector == "├── ");
+    }
+    assert!(
+        open.iter().all(|is_open| !is_open),
+        "unclosed branch at end of output for {:?}",
+        args
+    );
+}
+
+#[test]
+fn test_flag_combination_invariants() {
+    let fixture = Fixture::standard();
+    let flags: &[&[&str]] = &[
+        &["-a"],
+        &["-L", "1"],
+        &["-L", "2"],
+        &["-P", "*.txt"],
+        &["-I", "dir2"],
+        &["-d"],
+        &["-U"],
+        &["-c"],
+        &["-D"],
+        &["--dirsfirst"],
+        &["--filesfirst"],
+        &["--sort", "size"],
+        &["--sort", "version"],
+        &["-f"],
+        &["-i"],
+        &["-s"],
+        &["-h"],
+        &["-g"],
+        &["--inodes"],
+        &["--device"],
+        &["-C"],
+        &["-n"],
+        &["-q"],
+        &["--filelimit", "1"],
+    ];
+
+    let mut combinations: Vec<Vec<&str>> = flags.iter().map(|flag| flag.to_vec()).collect();
+    for (i, first) in flags.iter().enumerate() {
+        for second in flags[i + 1..].iter().filter(|second| second[0] != first[0]) {
+            combinations.push([*first, *second].concat());
+        }
+    }
+
+    for mut args in combinations {
+        args.push(fixture.arg());
+        let (output, status) = run_cmd_with_status(&args);
+        if status == 2 {
+            // Conflicting flags are rejected as a usage error
+            continue;
+        }
+        assert_eq!(0, status, "failed for {:?}", args);
+
+        let report = last_line(&output);
+        let counts: Vec<usize> = report
+            .split(|c: char| !c.is_ascii_digit())
+            .filter(|part| !part.is_empty())
+            .map(|part| part.parse().unwrap())
+            .collect();
+        assert_eq!(2, counts.len(), "bad report {:?} for {:?}", report, args);
+
+        let entry_lines = output
+            .lines()
+            .skip(1)
+            .take_while(|line| !line.is_empty())
+            .count();
+        assert_eq!(
+            counts[0] + counts[1],
+            entry_lines,
+            "report does not match listing for {:?}",
+            args
+        );
+
+        if !args.contains(&"-i") {
+            assert_balanced_glyphs(&output, &args);
+        }
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn test_group_column() {
+    let fixture = Fixture::standard();
+    let group = Command::new("id")
+        .arg("-gn")
+        .output()
+        .expect("id failed")
+        .stdout;
+    let group = String::from_utf8(group).unwrap().trim().to_string();
+
+    let output = run_cmd(&["-g", fixture.arg()]);
+    assert!(
+        output.contains(&format!("└── [{:<8}]  file1.txt", group)),
+        "Group column should precede the name: {}",
+        output
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn test_inodes_column() {
+    let fixture = Fixture::new("links").file("original.txt");
+    std::fs::hard_link(fixture.join("original.txt"), fixture.join("link.txt")).unwrap();
+
+    let output = run_cmd(&["--inodes", fixture.arg()]);
+    let inodes: Vec<&str> = output
+        .lines()
+        .filter_map(|line| line.split('[').nth(1))
+        .filter_map(|rest| rest.split(']').next())
+        .map(str::trim)
+        .collect();
+    assert_eq!(
+        2,
+        inodes.len(),
+        "Both entries should show an inode: {}",
+        output
+    );
+    assert_eq!(inodes[0], inodes[1], "Hard links should share an inode");
+}
+
+#[cfg(unix)]
+#[test]
+fn test_device_column() {
+    use std::os::unix::fs::MetadataExt;
+
+    let fixture = Fixture::standard();
+    let device = std::fs::metadata(fixture.join("file1.txt")).unwrap().dev();
+
+    let output = run_cmd(&["--device", fixture.arg()]);
+    assert!(
+        output.contains(&format!("└── [{:>4}]  file1.txt", device)),
+        "Device column should precede the name: {}",
+        output
+    );
+}
+
+#[test]
+fn test_print_size() {
+    let fixture = Fixture::new("sizes")
+        .sized_file("big.bin", 2048)
+        .sized_file("nested/small.txt", 12);
+    let expected = r#"sizes
+├── big.bin ( 2048B)
+└── nested
+    └── small.txt (   12B)
+
+1 directories, 2 files
+"#;
+
+    let output = run_cmd(&["-s", fixture.arg()]);
+    assert_eq!(expected, output);
+}
+
+#[cfg(unix)]
+#[test]
+fn test_follow_links() {
+    let fixture = Fixture::new("links")
+        .file("real/file.txt")
+        .symlink("link", "real")
+        .symlink("real/loop", "..");
+    let expected = r#"links
+├── link -> real
+│   ├── file.txt
+│   └── loop -> ..  [recursive, not followed]
+└── real
+    ├── file.txt
+    └── loop -> ..  [recursive, not followed]
+
+4 directories, 2 files
+"#;
+
+    let output = run_cmd(&["-l", fixture.arg()]);
+    assert_eq!(expected, output);
+}
+
+#[cfg(unix)]
+#[test]
+fn test_symlink_loops() {
+    let fixture = Fixture::new("loops")
+        .sized_file("real/data.bin", 100)
+        .symlink("real/up", "..")
+        .symlink("here", ".")
+        .symlink("self", "self")
+        .symlink("ping", "pong")
+        .symlink("pong", "ping");
+    let expected = r#"loops
+├── here -> .  [recursive, not followed]
+├── ping -> pong
+├── pong -> ping
+├── real
+│   ├── data.bin
+│   └── up -> ..  [recursive, not followed]
+└── self -> self
+
+3 directories, 4 files
+"#;
+
+    let output = run_cmd(&["-l", fixture.arg()]);
+    assert_eq!(expected, output);
+    let output = run_cmd(&["-l", "-U", fixture.arg()]);
+    assert!(
+        output.contains("── here -> .  [recursive, not followed]"),
+        "{}",
+        output
+    );
+    assert_eq!("3 directories, 4 files", last_line(&output));
+
+    // Through a symlinked root, the directory it links to is not listed again
+    let fixture = fixture.symlink("real/link", ".");
+    let root = fixture.join("real/link");
+    let expected = r#"link
+├── data.bin
+├── link -> .  [recursive, not followed]
+└── up -> ..
+    ├── here -> .  [recursive, not followed]
+    ├── ping -> pong
+    ├── pong -> ping
+    ├── real  [recursive, not followed]
+    └── self -> self
+
+4 directories, 4 files
+"#;
+    let output = run_cmd(&["-l", root.to_str().unwrap()]);
+    assert_eq!(expected, output);
+
+    // Nor are its contents counted twice
+    let output = run_cmd(&["-l", "--du", "-i", fixture.arg()]);
+    assert!(output.contains("\nreal (  100B)\n"), "{}", output);
+}
+
+#[cfg(unix)]
+#[test]
+fn test_symlink_targets() {
+    let fixture = Fixture::new("links")
+        .file("real/file.txt")
+        .symlink("current", "real")
+        .symlink("notes", "real/file.txt")
+        .symlink("stale", "missing.txt");
+    let expected = r#"links
+├── current -> real
+├── notes -> real/file.txt
+├── real
+│   └── file.txt
+└── stale -> missing.txt
+
+1 directories, 4 files
+"#;
+    assert_eq!(expected, run_cmd(&[fixture.arg()]));
+
+    // Targets are colored as what they point to, and broken links in red
+    #[cfg(feature = "color")]
+    {
+        let output = run_cmd(&["-C", fixture.arg()]);
+        assert!(
+            output.contains("\x1b[36mcurrent\x1b[0m -> \x1b[1;34mreal\x1b[0m"),
+            "{}",
+            output
+        );
+        assert!(
+            output.contains("\x1b[1;31mstale\x1b[0m -> \x1b[31mmissing.txt\x1b[0m"),
+            "{}",
+            output
+        );
+    }
+}
+
+#[cfg(feature = "color")]
+#[test]
+fn test_color_when() {
+    let fixture = Fixture::standard();
+    let colored = "\x1b[1;34mdir1\x1b[0m";
+    let is_colored = |args: &[&str]| {
+        let mut args = args.to_vec();
+        args.push(fixture.arg());
+        run_cmd(&args).contains(colored)
+    };
+
+    assert!(is_colored(&["--color=always"]));
+    assert!(is_colored(&["--color"]));
+    assert!(is_colored(&["-C"]));
+    assert!(!is_colored(&["--color=never"]));
+    // Output here is not a terminal
+    assert!(!is_colored(&[]));
+    assert!(!is_colored(&["--color=auto"]));
+    // The last of -C and -n wins
+    assert!(!is_colored(&["-C", "-n"]));
+    assert!(is_colored(&["-n", "-C"]));
+    assert!(!is_colored(&["--theme", "default", "--color=never"]));
+
+    // Repeating either of them is no error, so that an alias can be overridden
+    for (args, expected) in [
+        (["-C", "--color=never"], false),
+        (["--color=never", "-C"], true),
+        (["--color=always", "--color=never"], false),
+        (["-n", "-n"], false),
+        (["-C", "-C"], true),
+    ] {
+        let (output, code) = run_cmd_with_status(&[args[0], args[1], fixture.arg()]);
+        assert_eq!(0, code, "{:?}", args);
+        assert_eq!(expected, output.contains(colored), "{:?}", args);
+    }
+}
+
+#[cfg(feature = "color")]
+#[test]
+fn test_themes() {
+    let fixture = Fixture::new("themed").file("src/logo.png");
+
+    // --theme implies -C
+    let output = run_cmd(&["--theme", "light", fixture.arg()]);
+    assert!(output.contains("\x1b[1;38;5;19msrc\x1b[0m"), "{}", output);
+    assert!(
+        output.contains("\x1b[38;5;94mlogo.png\x1b[0m"),
+        "{}",
+        output
+    );
+
+    let output = run_cmd(&["--theme", "mono", fixture.arg()]);
+    assert!(output.contains("\x1b[1msrc\x1b[0m"), "{}", output);
+    assert!(output.contains("└── logo.png\n"), "{}", output);
+
+    // Custom themes override a bundled one
+    let theme = fixture.parent().join("theme.toml");
+    std::fs::write(
+        &theme,
+        "# Mine\n[colors]\nbase = \"mono\"\nimage = \"underline #ff8000\"\n",
+    )
+    .unwrap();
+    let output = run_cmd(&["--theme", theme.to_str().unwrap(), fixture.arg()]);
+    assert!(output.contains("\x1b[1msrc\x1b[0m"), "{}", output);
+    assert!(
+        output.contains("\x1b[4;38;2;255;128;0mlogo.png\x1b[0m"),
+        "{}",
+        output
+    );
+
+    // Trailing comments and literal strings are TOML too
+    std::fs::write(
+        &theme,
+        "[colors] # mine\nbase = 'mono' # no colors\nimage = \"underline #ff8000\" # orange\n",
+    )
+    .unwrap();
+    let output = run_cmd(&["--theme", theme.to_str().unwrap(), fixture.arg()]);
+    assert!(output.contains("\x1b[1msrc\x1b[0m"), "{}", output);
+    assert!(
+        output.contains("\x1b[4;38;2;255;128;0mlogo.png\x1b[0m"),
+        "{}",
+        output
+    );
+
+    std::fs::write(&theme, "image = \"sparkly\"\n").unwrap();
+    let (_, status) = run_cmd_with_status(&["--theme", theme.to_str().unwrap(), fixture.arg()]);
+    assert_ne!(0, status, "Invalid theme files should be rejected");
+    std::fs::write(&theme, "image = \"bold\" extra\n").unwrap();
+    let (_, status) = run_cmd_with_status(&["--theme", theme.to_str().unwrap(), fixture.arg()]);
+    assert_ne!(0, status, "Only comments may follow a value");
+    let (_, status) = run_cmd_with_status(&["--theme", "no-such-theme", fixture.arg()]);
+    assert_ne!(0, status, "Unknown themes should be rejected");
+}
+
+// Windows does not allow '"' in file names
+#[cfg(unix)]
+#[test]
+fn test_quote_names() {
+    let fixture = Fixture::new("quoted")
+        .file("my notes.txt")
+        .file("say \"hi\".txt")
+        .file("plain.txt");
+    let expected = r#"quoted
+├── "my notes.txt"
+├── "plain.txt"
+└── "say \"hi\".txt"
+
+0 directories, 3 files
+"#;
+    assert_eq!(expected, run_cmd(&["-Q", fixture.arg()]));
+}
+
+#[test]
+fn test_head() {
+    let fixture = Fixture::standard();
+    let expected = "test_directory
+├── dir1
+│   ├── dir1_1
+
+[output truncated after 3 lines, listing is incomplete]
+
+2 directories, 0 files
+";
+    assert_eq!(expected, run_cmd(&["--head", "3", fixture.arg()]));
+
+    // A tree that fits is printed whole
+    let output = run_cmd(&["--head", "7", fixture.arg()]);
+    assert!(!output.contains("truncated"), "{}", output);
+    assert_eq!("3 directories, 3 files", last_line(&output));
+}
+
+#[test]
+fn test_max_width() {
+    let fixture = Fixture::new("wide")
+        .file("short.txt")
+        .file("a_rather_long_file_name.txt");
+    let expected = "wide
+├── a_rather_…
+└── short.txt
+
+0 directories, 2 files
+";
+    assert_eq!(expected, run_cmd(&["--max-width", "14", fixture.arg()]));
+
+    // Piped output is left whole, even with COLUMNS set
+    let output = common::command()
+        .args(["--max-width", "auto", fixture.arg()])
+        .env("COLUMNS", "14")
+        .output()
+        .unwrap();
+    assert_eq!(run_cmd(&[fixture.arg()]).as_bytes(), output.stdout);
+}
+
+#[test]
+fn test_dir_counts() {
+    let fixture = Fixture::standard();
+    let expected = "test_directory
+├── dir1 (1 file, 1 dir)
+│   ├── dir1_1 (0 files, 0 dirs)
+│   └── file2.txt
+├── dir2 (1 file, 0 dirs)
+│   └── file3.txt
+└── file1.txt
+
+3 directories, 3 files
+";
+    assert_eq!(expected, run_cmd(&["--dir-counts", fixture.arg()]));
+
+    // Directories below the level limit have nothing listed to count
+    let expected = "test_directory
+├── dir1 (1 file, 1 dir)
+│   ├── dir1_1
+│   └── file2.txt
+";
+    assert!(run_cmd(&["--dir-counts", "-L", "2", fixture.arg()]).starts_with(expected));
+}
+
+#[test]
+fn test_bars() {
+    let fixture = Fixture::new("usage")
+        .sized_file("big.bin", 600)
+        .sized_file("logs/a.log", 300)
+        .sized_file("logs/b.log", 100);
+    let expected = "usage
+├── big.bin (  600B) [######....  60%]
+└── logs (  400B) [####......  40%]
+    ├── a.log (  300B) [########..  75%]
+    └── b.log (  100B) [###.......  25%]
+
+1000 bytes used in 1 directories, 3 files
+";
+    assert_eq!(expected, run_cmd(&["--du", "--bars", fixture.arg()]));
+}
+
+#[cfg(unix)]
+#[test]
+fn test_emoji() {
+    let fixture = Fixture::new("kinds")
+        .file("notes.txt")
+        .file("bin/run.sh")
+        .mode("bin/run.sh", 0o755)
+        .symlink("latest", "notes.txt");
+    let expected = "kinds
+├── 📁 bin
+│   └── ⚙\u{fe0f} run.sh
+├── 🔗 latest -> notes.txt
+└── 📄 notes.txt
+
+1 directories, 3 files
+";
+    assert_eq!(expected, run_cmd(&["--emoji", fixture.arg()]));
+}
+
+#[cfg(unix)]
+#[test]
+fn test_classify() {
+    let fixture = Fixture::new("kinds")
+        .file("plain.txt")
+        .file("bin/run.sh")
+        .mode("bin/run.sh", 0o755)
+        .symlink("latest", "plain.txt")
+        .symlink("stale", "missing.txt");
+    let _socket = std::os::unix::net::UnixListener::bind(fixture.join("server.sock")).unwrap();
+    let expected = "kinds
+├── bin/
+│   └── run.sh*
+├── latest@ -> plain.txt
+├── plain.txt
+├── server.sock=
+└── stale@ -> missing.txt  [broken]
+
+1 directories, 5 files
+";
+    assert_eq!(expected, run_cmd(&["-F", fixture.arg()]));
+}
+
+#[test]
+fn test_linemap() {
+    let fixture = Fixture::standard();
+    let linemap = fixture.parent().join("linemap.tsv");
+
+    let output = run_cmd(&["--linemap", linemap.to_str().unwrap(), fixture.arg()]);
+    let lines: Vec<&str> = output.lines().collect();
+    let records = std::fs::read_to_string(&linemap).unwrap();
+
+    assert_eq!(7, records.lines().count(), "One record per entry line");
+    for record in records.lines() {
+        let fields: Vec<&str> = record.split('\t').collect();
+        let line_number: usize = fields[0].parse().unwrap();
+        let offset: usize = fields[1].parse().unwrap();
+        let path = std::path::Path::new(fields[2]);
+        let name = path.file_name().unwrap().to_str().unwrap();
+
+        assert!(lines[line_number - 1].ends_with(name), "{}", record);
+        assert!(
+            output[offset..].starts_with(lines[line_number - 1]),
+            "{}",
+            record
+        );
+    }
+}
+
+#[test]
+fn test_linemap_inside_tree() {
+    let fixture = Fixture::standard();
+    let linemap = fixture.join("linemap.tsv");
+
+    let output = run_cmd(&["--linemap", linemap.to_str().unwrap(), fixture.arg()]);
+    assert_eq!(
+        run_cmd(&["-I", "linemap.tsv", fixture.arg()]),
+        output,
+        "The linemap file should not be listed"
+    );
+    let records = std::fs::read_to_string(&linemap).unwrap();
+    assert!(!records.contains("linemap.tsv"), "{}", records);
+
+    let output = run_cmd(&[
+        "--du",
+        "--linemap",
+        linemap.to_str().unwrap(),
+        fixture.arg(),
+    ]);
+    assert_eq!(
+        run_cmd(&["--du", "-I", "linemap.tsv", fixture.arg()]),
+        output,
+        "The linemap file should not count towards --du totals"
+    );
+}
+
+#[test]
+fn test_timeout() {
+    let fixture = Fixture::standard();
+
+    let (output, status) = run_cmd_with_status(&["--timeout", "0s", fixture.arg()]);
+    assert_eq!(124, status, "Timeouts should exit with a distinct status");
+    assert!(output.contains("[timeout reached, listing is incomplete]"));
+    assert_eq!("0 directories, 0 files", last_line(&output));
+
+    let (output, status) = run_cmd_with_status(&["--timeout", "1m", fixture.arg()]);
+    assert_eq!(0, status);
+    assert_eq!("3 directories, 3 files", last_line(&output));
+}
+
+#[test]
+fn test_exit_status() {
+    let fixture = Fixture::standard();
+    let missing = fixture.parent().join("missing");
+
+    // The roots that can be read are still listed
+    let (output, status) = run_cmd_with_status(&[missing.to_str().unwrap(), fixture.arg()]);
+    assert_eq!(1, status, "A missing root should exit with 1");
+    let expected = format!(
+        "missing  [error opening dir]\n{}",
+        run_cmd(&[fixture.arg()])
+    );
+    assert_eq!(expected, output);
+
+    let (_, status) = run_cmd_with_status(&["--threads", "0", fixture.arg()]);
+    assert_eq!(2, status, "Invalid arguments should exit with 2");
+    let (_, status) = run_cmd_with_status(&["--no-such-option", fixture.arg()]);
+    assert_eq!(2, status, "Unknown options should exit with 2");
+    let (_, status) = run_cmd_with_status(&["-L", "abc", fixture.arg()]);
+    assert_eq!(2, status, "Invalid levels should exit with 2");
+}
+
+#[cfg(unix)]
+#[test]
+fn test_exit_status_unreadable_directory() {
+    if !common::permissions_enforced() {
+        return;
+    }
+    let fixture = Fixture::standard().dir("locked").mode("locked", 0o000);
+    let (output, status) = run_cmd_with_status(&[fixture.arg()]);
+    let _fixture = fixture.mode("locked", 0o755);
+
+    assert_eq!(1, status, "An unreadable directory should exit with 1");
+    assert!(output.contains("└── locked\n"), "{}", output);
+    assert_eq!("4 directories, 3 files", last_line(&output));
+}
+
+#[test]
+fn test_unsorted() {
+    let fixture = Fixture::standard();
+    let sorted = run_cmd(&["-i", fixture.arg()]);
+    let unsorted = run_cmd(&["-U", "-i", fixture.arg()]);
+
+    let mut sorted_lines: Vec<&str> = sorted.lines().collect();
+    let mut unsorted_lines: Vec<&str> = unsorted.lines().collect();
+    sorted_lines.sort();
+    unsorted_lines.sort();
+    assert_eq!(
+        sorted_lines, unsorted_lines,
+        "-U should list the same entries"
+    );
+}
+
+#[test]
+fn test_exists() {
+    let fixture = Fixture::standard();
+
+    let (output, status) =
+        run_cmd_with_status(&["--exists", "file3*", "--print-match", fixture.arg()]);
+    assert_eq!(0, status);
+    assert_eq!(
+        fixture.join("dir2").join("file3.txt").to_str().unwrap(),
+        output.trim()
+    );
+
+    let (output, status) = run_cmd_with_status(&["--exists", "*.proto", fixture.arg()]);
+    assert_eq!(1, status);
+    assert!(output.is_empty(), "--exists should not list the tree");
+
+    let (_, status) = run_cmd_with_status(&["--exists", "file3*", "-I", "dir2", fixture.arg()]);
+    assert_eq!(1, status, "Excluded directories should not be searched");
+
+    let missing = fixture.parent().join("missing");
+    let output = common::command()
+        .args(["--exists", "*", missing.to_str().unwrap()])
+        .output()
+        .unwrap();
+    assert_eq!(
+        Some(1),
+        output.status.code(),
+        "Failed searches should exit with 1"
+    );
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains(&format!("{}: ", missing.display())),
+        "The error should name the path: {}",
+        stderr
+    );
+    let (_, status) = run_cmd_with_status(&["--exists", "[", fixture.arg()]);
+    assert_eq!(2, status, "Invalid patterns should exit with 2");
+}
+
+#[test]
+fn test_print_date() {
+    // 2001-09-09 01:46:40 UTC, which is the 8th or 9th in any timezone
+    let modified = std::time::UNIX_EPOCH + std::time::Duration::from_secs(1_000_000_000);
+    let fixture = Fixture::new("dates")
+        .file("old.txt")
+        .modified("old.txt", modified);
+
+    let output = run_cmd(&["-D", fixture.arg()]);
+    assert!(
+        output.contains("└── [2001-09-08 ") || output.contains("└── [2001-09-09 "),
+        "Modification date should precede the name: {}",
+        output
+    );
+}
+
+#[test]
+fn test_ctime_sort() {
+    let fixture = Fixture::new("ctimes").file("older.txt");
+    std::thread::sleep(std::time::Duration::from_millis(20));
+    let fixture = fixture.file("newer.txt");
+
+    let output = run_cmd(&["-c", fixture.arg()]);
+    let names: Vec<&str> = output.lines().skip(1).take(2).collect();
+    assert_eq!(vec!["├── newer.txt", "└── older.txt"], names);
+}
+
+#[test]
+fn test_sort_keys() {
+    let fixture = Fixture::new("sorting")
+        .sized_file("v10.txt", 1)
+        .sized_file("v2.md", 300)
+        .sized_file("v1.txt", 20);
+    let names = |key: &str| -> Vec<String> {
+        run_cmd(&["--sort", key, "-i", fixture.arg()])
+            .lines()
+            .skip(1)
+            .take(3)
+            .map(String::from)
+            .collect()
+    };
+
+    assert_eq!(vec!["v1.txt", "v10.txt", "v2.md"], names("name"));
+    assert_eq!(vec!["v1.txt", "v2.md", "v10.txt"], names("version"));
+    assert_eq!(vec!["v2.md", "v1.txt", "v10.txt"], names("size"));
+    assert_eq!(vec!["v2.md", "v1.txt", "v10.txt"], names("extension"));
+}
+
+#[test]
+fn test_ignore_case_sort() {
+    let fixture = Fixture::new("cases")
+        .file("README.md")
+        .file("Zeta.txt")
+        .file("alpha.txt")
+        .file("readme.txt");
+    let names = |args: &[&str]| -> Vec<String> {
+        let mut args = args.to_vec();
+        args.extend(["-i", fixture.arg()]);
+        run_cmd(&args)
+            .lines()
+            .skip(1)
+            .take(4)
+            .map(String::from)
+            .collect()
+    };
+
+    assert_eq!(
+        vec!["README.md", "Zeta.txt", "alpha.txt", "readme.txt"],
+        names(&[])
+    );
+    assert_eq!(
+        vec!["alpha.txt", "README.md", "readme.txt", "Zeta.txt"],
+        names(&["--ignore-case-sort"])
+    );
+    assert_eq!(
+        vec!["README.md", "alpha.txt", "readme.txt", "Zeta.txt"],
+        names(&["--ignore-case-sort", "--sort", "extension"])
+    );
+}
+
+#[test]
+fn test_time_format() {
+    let modified = std::time::UNIX_EPOCH + std::time::Duration::from_secs(1_000_000_000);
+    let fixture = Fixture::new("dates")
+        .file("old.txt")
+        .modified("old.txt", modified);
+
+    let output = run_cmd(&["--timefmt", "%Y", fixture.arg()]);
+    assert!(
+        output.contains("└── [2001]  old.txt"),
+        "--timefmt should imply -D and use the format: {}",
+        output
+    );
+
+    let (_, status) = run_cmd_with_status(&["--timefmt", "%Q", fixture.arg()]);
+    assert_ne!(0, status, "Invalid formats should be rejected");
+}
+
+#[test]
+fn test_term_integration() {
+    let fixture = Fixture::standard();
+
+    let output = run_cmd(&["--term-integration", fixture.arg()]);
+    let lines: Vec<&str> = output.lines().collect();
+    assert!(
+        lines[0].starts_with("\x1b]7;file://"),
+        "Root should report OSC 7"
+    );
+    assert!(
+        !lines[0].starts_with("\x1b]7;file:///"),
+        "OSC 7 should name the host: {:?}",
+        lines[0]
+    );
+    assert!(
+        lines[1].contains("\x1b]8;;file://") && lines[1].ends_with("dir1\x1b]8;;\x1b\\"),
+        "Directories should be hyperlinked: {:?}",
+        lines[1]
+    );
+    assert_eq!("└── file1.txt", lines[6], "Files are left as is");
+
+    let output = run_cmd(&[fixture.arg()]);
+    assert!(!output.contains('\x1b'), "Escapes are opt-in");
+}
+
+#[test]
+fn test_disk_usage() {
+    let fixture = Fixture::new("usage")
+        .sized_file("a/b/deep.bin", 1000)
+        .sized_file("a/shallow.bin", 200)
+        .sized_file("top.bin", 30);
+    let expected = r#"usage
+├── a ( 1200B)
+└── top.bin (   30B)
+
+1230 bytes used in 1 directories, 1 files
+"#;
+
+    let output = run_cmd(&["--du", "-L", "1", fixture.arg()]);
+    assert_eq!(expected, output, "Sizes include contents below -L");
+}
+
+#[test]
+fn test_disk_usage_threads() {
+    let mut fixture = Fixture::new("threads");
+    for dir in 0..20 {
+        for file in 0..5 {
+            fixture = fixture.sized_file(&format!("d{}/sub/f{}.bin", dir, file), 100 + dir);
+        }
+    }
+
+    let output = run_cmd(&["--du", "--threads", "4", fixture.arg()]);
+    assert_eq!(
+        output,
+        run_cmd(&["--du", "--threads", "1", fixture.arg()]),
+        "Totals should not depend on the number of threads"
+    );
+    assert!(output.contains("├── d3 (  515B)"), "{}", output);
+    assert_eq!(
+        "10950 bytes used in 40 directories, 100 files",
+        last_line(&output)
+    );
+
+    let (_, status) = run_cmd_with_status(&["--du", "--threads", "0", fixture.arg()]);
+    assert_ne!(0, status, "A thread count of 0 should be rejected");
+}
+
+#[test]
+fn test_external_sort() {
+    let mut fixture = Fixture::new("external");
+    for file in 0..150 {
+        fixture = fixture.sized_file(&format!("f{}.txt", (file * 37) % 150), file);
+    }
+    for dir in 0..3 {
+        fixture = fixture.file(&format!("d{}/inner.txt", dir));
+    }
+
+    // Sorted on disk in runs of N entries, the listing is the same
+    for args in [
+        vec![],
+        vec!["--dirsfirst", "-s"],
+        vec!["--sort", "size", "-s"],
+        vec!["--sort", "version", "--ignore-case-sort"],
+    ] {
+        let expected = run_cmd(&[args.as_slice(), &[fixture.arg()]].concat());
+        for chunk in ["1", "7", "1000"] {
+            let output =
+                run_cmd(&[args.as_slice(), &["--external-sort", chunk, fixture.arg()]].concat());
+            assert_eq!(
+                expected, output,
+                "{:?} with --external-sort {}",
+                args, chunk
+            );
+        }
+    }
+
+    let (_, status) = run_cmd_with_status(&["--external-sort", "0", fixture.arg()]);
+    assert_ne!(0, status, "Sorting 0 entries in memory should be rejected");
+}
+
+#[test]
+fn test_size_precision() {
+    let fixture = Fixture::new("precision").sized_file("data.bin", 1_530_000);
+
+    let output = run_cmd(&["-h", "--size-precision", "2", fixture.arg()]);
+    assert!(output.contains("data.bin (1.46 MB)"), "{}", output);
+
+    let output = run_cmd(&[
+        "-h",
+        "--size-precision",
+        "0",
+        "--size-rounding",
+        "up",
+        fixture.arg(),
+    ]);
+    assert!(output.contains("data.bin (2 MB)"), "{}", output);
+
+    let output = run_cmd(&["--du", "-h", "--size-precision", "3", fixture.arg()]);
+    assert_eq!(
+        "1.459 MB used in 0 directories, 1 files",
+        last_line(&output),
+        "The report should use the same precision"
+    );
+
+    // Sizes rounding up to 1024 move on to the next unit
+    let fixture = Fixture::new("carry").sized_file("data.bin", 1_048_575);
+    let output = run_cmd(&["-h", fixture.arg()]);
+    assert!(output.contains("data.bin (1.0 MB)"), "{}", output);
+    let output = run_cmd(&["-h", "--size-rounding", "down", fixture.arg()]);
+    assert!(output.contains("data.bin (1023.9 KB)"), "{}", output);
+
+    let (_, status) = run_cmd_with_status(&["-h", "--size-precision", "400", fixture.arg()]);
+    assert_eq!(2, status, "Precisions beyond 9 digits should be rejected");
+}
+
+#[test]
+fn test_si_units() {
+    let fixture = Fixture::new("si")
+        .sized_file("data.bin", 1_530_000)
+        .sized_file("small.txt", 1500);
+
+    let output = run_cmd(&["--si", "--size-precision", "2", fixture.arg()]);
+    assert!(output.contains("data.bin (1.53 MB)"), "{}", output);
+    assert!(output.contains("small.txt (1.50 kB)"), "{}", output);
+}
+
+#[test]
+fn test_regex_filters() {
+    let fixture = Fixture::new("build")
+        .file("src/main.rs")
+        .file("src/main.o")
+        .file("lib/util.pyc")
+        .file("lib/util.py")
+        .file("Main.class")
+        .file("test_main.rs");
+    let expected = r#"build
+├── lib
+│   └── util.py
+├── src
+│   └── main.rs
+└── test_main.rs
+
+2 directories, 3 files
+"#;
+    let output = run_cmd(&["--regex-exclude", r"\.(pyc|o|class)$", fixture.arg()]);
+    assert_eq!(expected, output);
+
+    let output = run_cmd(&["--regex", r"^test_.*\.rs$", "-P", "*.py", fixture.arg()]);
+    assert!(
+        output.contains("├── lib\n│   └── util.py\n├── src\n└── test_main.rs\n"),
+        "Files matching either -P or --regex should be listed: {}",
+        output
+    );
+
+    let (_, status) = run_cmd_with_status(&["--regex", "(unclosed", fixture.arg()]);
+    assert_ne!(0, status, "Invalid regular expressions should be rejected");
+}
+
+#[test]
+fn test_min_depth() {
+    let fixture = Fixture::new("dataset")
+        .file("raw/2023/jan.csv")
+        .file("raw/2024/feb.csv")
+        .file("raw/2024/mar.csv")
+        .file("raw/README")
+        .file("notes.txt");
+    let expected = r#"dataset
+├── raw/2023
+│   └── jan.csv
+├── raw/2024
+│   ├── feb.csv
+│   └── mar.csv
+└── raw/README
+
+2 directories, 4 files
+"#;
+    assert_eq!(expected, run_cmd(&["--mindepth", "2", fixture.arg()]));
+
+    let output = run_cmd(&["--mindepth", "3", "-L", "3", fixture.arg()]);
+    assert!(
+        output.contains("├── raw/2023/jan.csv\n├── raw/2024/feb.csv\n└── raw/2024/mar.csv\n"),
+        "{}",
+        output
+    );
+    assert!(output.ends_with("0 directories, 3 files\n"), "{}", output);
+
+    // Level 1 is the default
+    assert_eq!(
+        run_cmd(&[fixture.arg()]),
+        run_cmd(&["--mindepth", "1", fixture.arg()])
+    );
+}
+
+#[test]
+fn test_full_match() {
+    let fixture = Fixture::new("app")
+        .file("src/api/generated/client.rs")
+        .file("src/api/handlers.rs")
+        .file("src/generated/schema.rs")
+        .file("docs/generated/index.html")
+        .file("docs/intro.md");
+    let expected = r#"app
+├── docs
+│   ├── generated
+│   │   └── index.html
+│   └── intro.md
+└── src
+    └── api
+        └── handlers.rs
+
+4 directories, 3 files
+"#;
+    let output = run_cmd(&["--full-match", "-I", "src/**/generated", fixture.arg()]);
+    assert_eq!(expected, output);
+
+    // Without --full-match the pattern never matches a name
+    let output = run_cmd(&["-I", "src/**/generated", fixture.arg()]);
+    assert!(output.ends_with("6 directories, 5 files\n"), "{}", output);
+
+    // '*' stops at '/', and patterns without one still match names
+    let output = run_cmd(&["--full-match", "-P", "src/*/*.rs|*.md", fixture.arg()]);
+    assert!(
+        output.contains("│   └── intro.md\n")
+            && output.contains("handlers.rs")
+            && output.contains("schema.rs")
+            && !output.contains("client.rs"),
+        "{}",
+        output
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn test_type_filter() {
+    let fixture = Fixture::new("kinds")
+        .file("bin/run.sh")
+        .mode("bin/run.sh", 0o755)
+        .file("bin/README")
+        .file("notes.txt")
+        .symlink("latest", "notes.txt");
+    let listing = |types: &[&str]| -> String {
+        let mut args = vec!["-i"];
+        for entry_type in types {
+            args.extend(["--type", entry_type]);
+        }
+        args.push(fixture.arg());
+        run_cmd(&args).lines().skip(1).collect::<Vec<_>>().join(" ")
+    };
+
+    assert_eq!(
+        "bin README run.sh notes.txt  1 directories, 3 files",
+        listing(&["f"])
+    );
+    assert_eq!(
+        "bin run.sh latest -> notes.txt  1 directories, 2 files",
+        listing(&["x", "l"])
+    );
+    assert_eq!("bin  1 directories, 0 files", listing(&["d"]));
+}
+
+#[cfg(unix)]
+#[test]
+fn test_executables() {
+    let fixture = Fixture::new("tools")
+        .file("bin/deploy")
+        .mode("bin/deploy", 0o755)
+        .file("bin/deploy.conf")
+        .file("scripts/lint.sh")
+        .mode("scripts/lint.sh", 0o744)
+        .file("scripts/README.md");
+    let expected = r#"tools
+├── bin
+│   └── deploy
+└── scripts
+    └── lint.sh
+
+2 directories, 2 files
+"#;
+    assert_eq!(expected, run_cmd(&["--executables", fixture.arg()]));
+}
+
+#[test]
+fn test_ext_filter() {
+    let fixture = Fixture::new("project")
+        .file("Cargo.toml")
+        .file("README.md")
+        .file("src/main.rs")
+        .file("src/data.json")
+        .file("docs/[draft].md");
+    let expected = r#"project
+├── Cargo.toml
+├── docs
+│   └── [draft].md
+└── src
+    └── main.rs
+
+2 directories, 3 files
+"#;
+
+    assert_eq!(
+        expected,
+        run_cmd(&[
+            "--ext",
+            "rs,.toml",
+            "--ext",
+            "md",
+            "-I",
+            "README*",
+            fixture.arg()
+        ])
+    );
+    assert_eq!(
+        run_cmd(&["-P", "*.rs|*.json", fixture.arg()]),
+        run_cmd(&["--ext", "rs", "-P", "*.json", fixture.arg()]),
+        "--ext should combine with -P"
+    );
+}
+
+#[test]
+fn test_size_filters() {
+    let fixture = Fixture::new("sizes")
+        .sized_file("tiny.txt", 100)
+        .sized_file("medium.bin", 20 * 1024)
+        .sized_file("data/large.bin", 6 * 1024 * 1024);
+    let expected = r#"sizes
+├── data
+│   └── large.bin
+└── medium.bin
+
+1 directories, 2 files
+"#;
+
+    assert_eq!(expected, run_cmd(&["--min-size", "10k", fixture.arg()]));
+
+    let output = run_cmd(&["--min-size", "10K", "--max-size", "5MiB", fixture.arg()]);
+    assert!(output.contains("├── data\n└── medium.bin\n"), "{}", output);
+
+    let output = run_cmd(&["--max-size", "100", fixture.arg()]);
+    assert!(output.contains("└── tiny.txt\n"), "{}", output);
+
+    let (_, status) = run_cmd_with_status(&["--min-size", "10 parsecs", fixture.arg()]);
+    assert_ne!(0, status, "Invalid sizes should be rejected");
+}
+
+#[test]
+fn test_nonempty() {
+    let fixture = Fixture::new("out")
+        .sized_file("report.html", 512)
+        .file("assets/.keep")
+        .file("assets/placeholder.png")
+        .sized_file("logs/run.log", 64)
+        .file("logs/empty.log");
+    let expected = r#"out
+├── assets
+├── logs
+│   └── run.log
+└── report.html
+
+2 directories, 2 files
+"#;
+    assert_eq!(expected, run_cmd(&["--nonempty", fixture.arg()]));
+
+    let output = run_cmd(&["--nonempty", "--prune", fixture.arg()]);
+    assert!(
+        output.contains("out\n├── logs\n│   └── run.log\n└── report.html\n"),
+        "Directories left empty should be pruned: {}",
+        output
+    );
+    assert!(output.ends_with("1 directories, 2 files\n"), "{}", output);
+}
+
+#[test]
+fn test_contains() {
+    let fixture = Fixture::new("service")
+        .file_with_content("src/config.rs", "pub const TIMEOUT_MS: u64 = 500;\n")
+        .file_with_content("src/main.rs", "fn main() {\n    serve(TIMEOUT_MS);\n}\n")
+        .file_with_content("src/http/client.rs", "pub struct Client;\n")
+        .file_with_content("docs/setup.md", "Set timeout_ms in the config.\n")
+        .file_with_content("README.md", "A service.\n");
+    let expected = r#"service
+└── src
+    ├── config.rs
+    └── main.rs
+
+1 directories, 2 files
+"#;
+    assert_eq!(
+        expected,
+        run_cmd(&["--contains", "TIMEOUT_MS", fixture.arg()])
+    );
+
+    let output = run_cmd(&["--contains-regex", "(?i)^set timeout", fixture.arg()]);
+    assert!(
+        output.starts_with("service\n└── docs\n    └── setup.md\n"),
+        "{}",
+        output
+    );
+
+    // Text is matched literally
+    let output = run_cmd(&[
+        "--contains",
+        "Client;",
+        "--contains",
+        "a.service",
+        fixture.arg(),
+    ]);
+    assert!(output.ends_with("2 directories, 1 files\n"), "{}", output);
+
+    // Anchors match at the ends of lines, whatever their line endings
+    let fixture = Fixture::new("anchored")
+        .file_with_content("unix.txt", "hello end\nfoo\n")
+        .file_with_content("dos.txt", "hello end\r\nfoo\r\n")
+        .file_with_content("last.txt", "foo")
+        .file_with_content("other.txt", "food\nends here\n");
+    let expected = r#"anchored
+├── dos.txt
+└── unix.txt
+
+0 directories, 2 files
+"#;
+    assert_eq!(
+        expected,
+        run_cmd(&["--contains-regex", "end$", fixture.arg()])
+    );
+    let output = run_cmd(&["--contains-regex", "^foo$", fixture.arg()]);
+    assert!(output.ends_with("0 directories, 3 files\n"), "{}", output);
+    assert!(!output.contains("other.txt"), "{}", output);
+}
+
+#[test]
+fn test_relative_date() {
+    let now = SystemTime::now();
+    let fixture = Fixture::new("recent")
+        .file("fresh.txt")
+        .file("today.txt")
+        .file("old.txt")
+        .modified("today.txt", now - Duration::from_secs(3 * 3600 + 60))
+        .modified("old.txt", now - Duration::from_secs(40 * 86400));
+    let expected = r#"recent
+├── [      just now]  fresh.txt
+├── [   1 month ago]  old.txt
+└── [   3 hours ago]  today.txt
+
+0 directories, 3 files
+"#;
+    assert_eq!(expected, run_cmd(&["--relative-date", fixture.arg()]));
+}
+
+#[test]
+fn test_date_filters() {
+    // Noon UTC on a day since the epoch, the same date in every timezone
+    let day = |days: u64| SystemTime::UNIX_EPOCH + Duration::from_secs(days * 86400 + 12 * 3600);
+    let (jan_2020, jun_2022, mar_2024) = (day(18262), day(19158), day(19783));
+    let fixture = Fixture::new("dated")
+        .file("old/archive.txt")
+        .file("src/legacy.rs")
+        .file("src/main.rs")
+        .file("notes.txt")
+        .modified("old/archive.txt", jan_2020)
+        .modified("src/legacy.rs", jan_2020)
+        .modified("src/main.rs", mar_2024)
+        .modified("notes.txt", jun_2022);
+
+    let expected = r#"dated
+└── src
+    └── main.rs
+
+1 directories, 1 files
+"#;
+    assert_eq!(expected, run_cmd(&["--newer", "2023-01-01", fixture.arg()]));
+
+    let output = run_cmd(&[
+        "--older",
+        "2024-01-01 00:00",
+        "--newer",
+        "2021-01-01",
+        fixture.arg(),
+    ]);
+    assert!(output.starts_with("dated\n└── notes.txt\n\n"), "{}", output);
+
+    let (_, status) = run_cmd_with_status(&["--newer", "last tuesday", fixture.arg()]);
+    assert_ne!(0, status, "Invalid dates should be rejected");
+}
+
+#[test]
+fn test_dirs_and_files_first() {
+    let fixture = Fixture::new("ordering")
+        .file("a.txt")
+        .dir("b")
+        .file("c.txt")
+        .dir("d");
+    let names = |flag: &str| -> Vec<String> {
+        run_cmd(&[flag, "-i", fixture.arg()])
+            .lines()
+            .skip(1)
+            .take(4)
+            .map(String::from)
+            .collect()
+    };
+
+    assert_eq!(vec!["b", "d", "a.txt", "c.txt"], names("--dirsfirst"));
+    assert_eq!(vec!["a.txt", "c.txt", "b", "d"], names("--filesfirst"));
+}
+
+#[test]
+fn test_fromfile() {
+    let fixture = Fixture::new("listings")
+        .file_with_content(
+            "plain.txt",
+            "./src/\nsrc/lib/a.rs\nsrc/main.rs\nREADME.md\n",
+        )
+        .file_with_content(
+            "rich.tsv",
+            "src\t-\t-\t40755\nsrc/main.rs\t1200\t1000000000\t644\nrun.sh\t30\t1000000000\t755\n",
+        )
+        .file_with_content("parent.txt", "a/../b\nc/d/../e\n")
+        .file_with_content("outside.txt", "a\n../x\n");
+    let listing = |args: &[&str], file: &str| -> String {
+        let file = fixture.join(file);
+        let mut args = args.to_vec();
+        args.extend(["--fromfile", file.to_str().unwrap()]);
+        run_cmd(&args)
+            .lines()
+            .skip(1)
+            .collect::<Vec<_>>()
+            .join("\n")
+    };
+
+    let expected = r#"├── README.md
+└── src
+    ├── lib
+    │   └── a.rs
+    └── main.rs
+
+2 directories, 3 files"#;
+    assert_eq!(expected, listing(&[], "plain.txt"));
+
+    let expected = r#"├── [2001]  run.sh (   30B)
+└── [?]  src ( 1200B)
+    └── [2001]  main.rs ( 1200B)
+
+1230 bytes used in 1 directories, 2 files"#;
+    assert_eq!(
+        expected,
+        listing(&["--du", "--timefmt", "%Y"], "rich.tsv"),
+        "TSV listings should be detected and carry sizes and dates"
+    );
+
+    let output = listing(&["--input-format", "plain"], "rich.tsv");
+    assert!(
+        output.contains("run.sh\t30\t1000000000\t755"),
+        "--input-format should override detection: {}",
+        output
+    );
+
+    let expected = r#"├── b
+└── c
+    └── e
+
+1 directories, 2 files"#;
+    assert_eq!(expected, listing(&["-a"], "parent.txt"));
+    let file = fixture.join("outside.txt");
+    let output = common::command()
+        .args(["--fromfile", file.to_str().unwrap()])
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert_eq!(Some(1), output.status.code(), "{}", stderr);
+    assert!(stderr.contains("line 2: invalid path"), "{}", stderr);
+}
+
+#[test]
+fn test_permissions() {
+    let fixture = Fixture::new("listings").file_with_content(
+        "modes.tsv",
+        "bin\t-\t-\t40755\nbin/run.sh\t-\t-\t104750\nnotes.txt\t-\t-\t640\nshared\t-\t-\t41777\n",
+    );
+    let listing = fixture.join("modes.tsv");
+    let listing = listing.to_str().unwrap();
+
+    let expected = r#"├── [drwxr-xr-x]  bin
+│   └── [-rwsr-x---]  run.sh
+├── [-rw-r-----]  notes.txt
+└── [drwxrwxrwt]  shared
+"#;
+    let output = run_cmd(&["-p", "--fromfile", listing]);
+    assert!(output.contains(expected), "{}", output);
+
+    let output = run_cmd(&["--mode", "octal", "--fromfile", listing]);
+    assert!(output.contains("└── [4750]  run.sh"), "{}", output);
+    assert!(output.contains("└── [1777]  shared"), "{}", output);
+
+    let output = run_cmd(&["-p", "--mode", "both", "--fromfile", listing]);
+    assert!(
+        output.contains("[-rw-r----- 0640]  notes.txt"),
+        "{}",
+        output
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn test_permissions_from_filesystem() {
+    let fixture = Fixture::new("perms")
+        .file("script.sh")
+        .mode("script.sh", 0o755);
+
+    let output = run_cmd(&["-p", "--mode", "both", fixture.arg()]);
+    assert!(
+        output.contains("[-rwxr-xr-x 0755]  script.sh"),
+        "{}",
+        output
+    );
+}
+
+#[test]
+fn test_root_without_file_name() {
+    let fixture = Fixture::standard();
+
+    let output = run_cmd_in(&fixture.join("dir1"), &["-L", "1", ".."]);
+    assert_eq!(Some(".."), output.lines().next());
+
+    #[cfg(unix)]
+    {
+        let output = run_cmd(&["-d", "-L", "1", "/"]);
+        assert_eq!(Some("/"), output.lines().next());
+    }
+}
+
+#[cfg(windows)]
+#[test]
+fn test_windows_drive_and_unc_roots() {
+    let fixture = Fixture::standard();
+    let drive = fixture.path().components().next().unwrap();
+    let drive = format!("{}\\", drive.as_os_str().to_string_lossy());
+
+    let (output, status) = run_cmd_with_status(&["-d", "-L", "1", &drive]);
+    assert_eq!(0, status);
+    assert_eq!(Some(drive.as_str()), output.lines().next());
+
+    // Reach the fixture through the administrative share, where available
+    let local = fixture.path().to_string_lossy().to_string();
+    let unc = format!("\\\\localhost\\{}${}", &local[..1], &local[2..]);
+    if !std::path::Path::new(&unc).is_dir() {
+        return;
+    }
+    let (output, status) = run_cmd_with_status(&["--term-integration", &unc]);
+    assert_eq!(0, status);
+    assert!(output.contains("file://localhost/"), "{}", output);
+    assert!(output.contains("dir1"), "{}", output);
+}
+
+#[cfg(unix)]
+#[test]
+fn test_replace_nonprintable() {
+    let fixture = Fixture::new("names")
+        .file("evil\x1b[31mred")
+        .file("tab\there");
+
+    let output = run_cmd(&["-q", fixture.arg()]);
+    assert!(output.contains("├── evil?[31mred\n"), "{}", output);
+    assert!(output.contains("└── tab?here\n"), "{}", output);
+    assert!(!output.contains('\x1b'), "No escape may reach the terminal");
+
+    let output = run_cmd(&[fixture.arg()]);
+    assert!(
+        output.contains("evil\x1b[31mred"),
+        "Names are printed as is without -q"
+    );
+}
+
+#[test]
+fn test_charset() {
+    let fixture = Fixture::standard();
+    let expected = r#"test_directory
+|-- dir1
+|   |-- dir1_1
+|   `-- file2.txt
+|-- dir2
+|   `-- file3.txt
+`-- file1.txt
+
+3 directories, 3 files
+"#;
+    assert_eq!(expected, run_cmd(&["--charset", "ascii", fixture.arg()]));
+
+    let output = run_cmd(&["--charset", "+-- ,'-- ,:  ", "-L", "1", fixture.arg()]);
+    assert!(
+        output.contains("+-- dir1\n+-- dir2\n'-- file1.txt\n"),
+        "{}",
+        output
+    );
+
+    let (_, status) = run_cmd_with_status(&["--charset", "klingon", fixture.arg()]);
+    assert_ne!(0, status, "Unknown charsets should be rejected");
+}
+
+#[test]
+fn test_line_style() {
+    let fixture = Fixture::standard();
+    let expected = r#"test_directory
+┣━━ dir1
+┃   ┣━━ dir1_1
+┃   ┗━━ file2.txt
+┣━━ dir2
+┃   ┗━━ file3.txt
+┗━━ file1.txt
+
+3 directories, 3 files
+"#;
+    assert_eq!(expected, run_cmd(&["--line-style", "heavy", fixture.arg()]));
+
+    let output = run_cmd(&["--line-style=rounded", "-L", "1", fixture.arg()]);
+    assert!(output.contains("├── dir2\n╰── file1.txt\n"), "{}", output);
+}
+
+#[test]
+fn test_columns() {
+    let fixture = Fixture::new("listings").file_with_content(
+        "rich.tsv",
+        "src\t-\t-\t40755\nsrc/main.rs\t1200\t1000000000\t644\n",
+    );
+    let listing = fixture.join("rich.tsv");
+    let listing = listing.to_str().unwrap();
+
+    let output = run_cmd(&["--columns", "perms,size,name", "--fromfile", listing]);
+    assert!(
+        output.contains("└── [drwxr-xr-x]  src\n    └── [-rw-r--r--  1200B]  main.rs\n"),
+        "{}",
+        output
+    );
+
+    let output = run_cmd(&["--columns", "name,size,perms", "-h", "--fromfile", listing]);
+    assert!(
+        output.contains("    └── main.rs (1.2 KB) [-rw-r--r--]\n"),
+        "{}",
+        output
+    );
+
+    let output = run_cmd(&["--columns", "size", "--fromfile", listing]);
+    assert!(output.contains("└── [ 1200B]  main.rs\n"), "{}", output);
+
+    let (_, status) = run_cmd_with_status(&["--columns", "perms,colour", "--fromfile", listing]);
+    assert_ne!(0, status, "Unknown columns should be rejected");
+}
+
+#[test]
+fn test_version() {
+    let version = format!("tree {}\n", env!("CARGO_PKG_VERSION"));
+    assert_eq!(version, run_cmd(&["--version"]));
+
+    for args in [&["-VV"][..], &["--version", "--verbose"]] {
+        let output = run_cmd(args);
+        assert!(output.starts_with(&version), "{}", output);
+        assert!(output.contains("\ntarget: "), "{}", output);
+        assert!(output.contains("\ncommit: "), "{}", output);
+        let features: Vec<&str> = output
+            .lines()
+            .find_map(|line| line.strip_prefix("features: "))
+            .unwrap_or_default()
+            .split(',')
+            .collect();
+        assert!(features.contains(&"cli"), "{}", output);
+        #[cfg(feature = "color")]
+        assert!(features.contains(&"color"), "{}", output);
+    }
+}
+
+#[test]
+fn test_output_file() {
+    let fixture = Fixture::standard();
+    let output_path = fixture.join("listing.txt");
+
+    let stdout = run_cmd(&["-o", output_path.to_str().unwrap(), fixture.arg()]);
+    assert_eq!("", stdout, "Nothing should be printed to stdout");
+    let listing = std::fs::read_to_string(&output_path).unwrap();
+    assert_eq!(
+        run_cmd(&["-I", "listing.txt", fixture.arg()]),
+        listing,
+        "The output file should not list itself"
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn test_broken_pipe() {
+    use std::io::Read;
+    use std::process::Stdio;
+
+    // Enough output to fill the pipe before the reader goes away
+    let mut fixture = Fixture::new("many");
+    for i in 0..2000 {
+        fixture = fixture.file(&format!("a_reasonably_long_file_name_number_{:05}.txt", i));
+    }
+    let linemap = fixture.parent().join("linemap.tsv");
+
+    for args in [
+        vec![fixture.arg()],
+        vec!["--linemap", linemap.to_str().unwrap(), fixture.arg()],
+    ] {
+        let mut child = common::command()
+            .args(&args)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let mut head = [0u8; 16];
+        child.stdout.take().unwrap().read_exact(&mut head).unwrap();
+        let output = child.wait_with_output().unwrap();
+
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.is_empty(), "Expected a silent exit, got {}", stderr);
+        assert_eq!(Some(0), output.status.code(), "{:?}", args);
+    }
+
+    // Short outputs, with the reader gone before anything is written
+    for args in [
+        vec!["--print-smart-ignore"],
+        vec!["-VV"],
+        vec!["--explain-filters", fixture.arg()],
+        vec!["--exists", "*.txt", "--print-match", fixture.arg()],
+    ] {
+        let mut child = common::command()
+            .args(&args)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+        drop(child.stdout.take());
+        let output = child.wait_with_output().unwrap();
+
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.is_empty(), "Expected a silent exit, got {}", stderr);
+        assert_eq!(Some(0), output.status.code(), "{:?}", args);
+    }
+}
+
+#[test]
+fn test_info_comments() {
+    let fixture = Fixture::new("project")
+        .file("Makefile")
+        .file("README.md")
+        .file("src/main.rs")
+        .file_with_content(
+            ".info",
+            "# notes\nMakefile\n\tBuild rules\nsrc/\n\tSource code\n\tsecond line\n*.md\nREADME*\n\tDocs\n",
+        )
+        .file_with_content("src/.info", "main.rs\n\tEntry point\n");
+    let expected = r#"project
+├── Makefile
+│     Build rules
+├── README.md
+│     Docs
+└── src
+      Source code
+      second line
+    └── main.rs
+          Entry point
+
+1 directories, 3 files
+"#;
+
+    assert_eq!(expected, run_cmd(&["--info", fixture.arg()]));
+    assert!(!run_cmd(&[fixture.arg()]).contains("Docs"));
+}
+
+#[test]
+fn test_describe() {
+    let fixture = Fixture::new("project")
+        .sized_file("src/main.rs", 2048)
+        .sized_file("src/lib.rs", 1024)
+        .sized_file("docs/guide.md", 512)
+        .sized_file("README.md", 100)
+        .sized_file("build.rs", 10);
+
+    let output = run_cmd(&["--describe", "-L", "1", fixture.arg()]);
+    assert_eq!(
+        "project holds 2 directories and 2 files (3.6 KB in total). \
+         At the top level: docs/, src/, README.md and build.rs. \
+         The most common file types are .md (1) and .rs (1). \
+         The largest directories are src/ (3.0 KB) and docs/ (512.0 B).",
+        last_line(&output)
+    );
+    assert!(
+        output.contains("\n2 directories, 2 files\n\nproject holds"),
+        "The description should follow the report: {}",
+        output
+    );
+}
+
+#[test]
+fn test_explain_filters() {
+    let fixture = Fixture::standard();
+    let expected = "\
+filter                          excluded
+hidden (no -a)                         1
+depth (-L 1)                           2
+matching -I/--regex-exclude            1
+";
+
+    let output = run_cmd(&["--explain-filters", "-L", "1", "-I", "*2*", fixture.arg()]);
+    assert_eq!(expected, output);
+
+    let output = run_cmd(&["--explain-filters", "-a", fixture.arg()]);
+    assert_eq!("filter                          excluded\n", output);
+}
+
+#[test]
+fn test_dir_descriptions() {
+    let fixture = Fixture::new("project")
+        .file_with_content("docs/.dirinfo", "\nUser and developer guides\nMore text\n")
+        .file_with_content("src/README.md", "Intro\n\n## Source code\n\n# Later\n")
+        .file_with_content("tests/README.md", "No heading here\n")
+        .file_with_content("vendor/.dirinfo", &"x".repeat(80));
+    let expected = format!(
+        r#"project
+├── docs  # User and developer guides
+├── src  # Source code
+│   └── README.md
+├── tests
+│   └── README.md
+└── vendor  # {}...
+
+4 directories, 2 files
+"#,
+        "x".repeat(57)
+    );
+
+    let output = run_cmd(&["--dir-descriptions", fixture.arg()]);
+    assert_eq!(expected, output);
+}
+
+#[test]
+fn test_multiple_paths() {
+    let fixture = Fixture::standard();
+    let dir1 = fixture.join("dir1");
+    let dir2 = fixture.join("dir2");
+    let expected = r#"dir1
+├── dir1_1
+└── file2.txt
+dir2
+└── file3.txt
+
+1 directories, 2 files
+"#;
+
+    let output = run_cmd(&[dir1.to_str().unwrap(), dir2.to_str().unwrap()]);
+    assert_eq!(expected, output);
+
+    let (output, status) = run_cmd_with_status(&[
+        "--exists",
+        "file3.txt",
+        "--print-match",
+        dir1.to_str().unwrap(),
+        dir2.to_str().unwrap(),
+    ]);
+    assert_eq!(0, status);
+    assert_eq!(format!("{}\n", dir2.join("file3.txt").display()), output);
+}
+
+#[test]
+fn test_auto_limit() {
+    let mut fixture = Fixture::new("wide")
+        .file("narrow/a.txt")
+        .file("narrow/b.txt");
+    for i in 0..10 {
+        fixture = fixture.file(&format!("photos/{:02}.jpg", i));
+    }
+    let expected = r#"wide
+├── narrow
+│   ├── a.txt
+│   └── b.txt
+└── photos
+    ├── 00.jpg
+    ├── 01.jpg
+    ├── 02.jpg
+    └── … and 7 more
+
+2 directories, 5 files
+"#;
+
+    let output = common::command()
+        .args(["--auto-limit", "3", fixture.arg()])
+        .output()
+        .unwrap();
+    assert_eq!(expected, String::from_utf8_lossy(&output.stdout));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("photos: 10 entries, listing the first 3"),
+        "Wide directories should be reported: {}",
+        stderr
+    );
+}
+
+#[test]
+fn test_limit() {
+    let mut fixture = Fixture::new("wide").file("narrow/a.txt");
+    for i in 0..10 {
+        fixture = fixture.file(&format!("photos/{:02}.jpg", i));
+    }
+    let expected = r#"wide
+├── narrow
+│   └── a.txt
+└── photos
+    ├── 00.jpg
+    ├── 01.jpg
+    └── … and 8 more
+
+2 directories, 3 files
+"#;
+
+    let output = common::command()
+        .args(["--limit", "2", fixture.arg()])
+        .output()
+        .unwrap();
+    assert_eq!(expected, String::from_utf8_lossy(&output.stdout));
+    assert!(output.stderr.is_empty(), "--limit should not warn");
+}
+
+#[test]
+fn test_exclude_from() {
+    let fixture = Fixture::new("site")
+        .file("index.html")
+        .file("cache/page.html")
+        .file("style.css.map")
+        .file("style.css");
+    let excludes = fixture.parent().join("excludes");
+    std::fs::write(&excludes, "# build output\ncache/\n\n*.map\n").unwrap();
+    let expected = r#"site
+├── index.html
+└── style.css
+
+0 directories, 2 files
+"#;
+    let output = run_cmd(&["--exclude-from", excludes.to_str().unwrap(), fixture.arg()]);
+    assert_eq!(expected, output);
+
+    let missing = fixture.parent().join("missing");
+    let (_, status) = run_cmd_with_status(&["--exclude-from", missing.to_str().unwrap(), "."]);
+    assert_ne!(0, status, "A missing pattern file should be an error");
+}
+
+#[test]
+fn test_include_from() {
+    let fixture = Fixture::new("pkg")
+        .file("src/lib.rs")
+        .file("src/main.rs")
+        .file("tests/lib.rs")
+        .file("Cargo.toml");
+    let manifest = fixture.parent().join("manifest");
+    std::fs::write(&manifest, "# shipped files\nsrc/lib.rs\nCargo.toml\n").unwrap();
+    let expected = r#"pkg
+├── Cargo.toml
+├── src
+│   └── lib.rs
+└── tests
+
+2 directories, 2 files
+"#;
+    let output = run_cmd(&[
+        "--full-match",
+        "--include-from",
+        manifest.to_str().unwrap(),
+        fixture.arg(),
+    ]);
+    assert_eq!(expected, output);
+}
+
+#[test]
+fn test_smart_ignore() {
+    let fixture = Fixture::new("webapp")
+        .file("node_modules/react/index.js")
+        .file("dist/bundle.js")
+        .file("target/debug/app")
+        .file("coverage/lcov.info")
+        .file("src/index.js")
+        .file("package.json");
+    let expected = r#"webapp
+├── coverage
+│   └── lcov.info
+├── package.json
+└── src
+    └── index.js
+
+2 directories, 3 files
+"#;
+    assert_eq!(expected, run_cmd(&["--smart-ignore", fixture.arg()]));
+
+    let output = run_cmd(&["--smart-ignore-add", "coverage|*.info", fixture.arg()]);
+    assert!(output.ends_with("1 directories, 2 files\n"), "{}", output);
+
+    let list = run_cmd(&["--print-smart-ignore", "--smart-ignore-add", "coverage"]);
+    assert!(list.lines().any(|line| line == "node_modules"), "{}", list);
+    assert_eq!(Some("coverage"), list.lines().last());
+}
+
+#[test]
+fn test_treeignore() {
+    let fixture = Fixture::new("repo")
+        .file_with_content(".treeignore", "# build output\ntarget/\n\n*.log\n")
+        .file("target/debug/app")
+        .file("src/main.rs")
+        .file("src/secrets.env")
+        .file("debug.log");
+    let home = fixture.parent().join("home");
+    std::fs::create_dir(&home).unwrap();
+    std::fs::write(home.join(".treeignore"), "*.env\n").unwrap();
+    let run = |args: &[&str]| {
+        let output = common::command()
+            .args(args)
+            .env("HOME", &home)
+            .env("USERPROFILE", &home)
+            .output()
+            .unwrap();
+        String::from_utf8_lossy(&output.stdout).to_string()
+    };
+    let expected = r#"repo
+└── src
+    └── main.rs
+
+1 directories, 1 files
+"#;
+
+    assert_eq!(expected, run(&[fixture.arg()]));
+    let output = run(&["--no-treeignore", fixture.arg()]);
+    assert!(output.ends_with("3 directories, 4 files\n"), "{}", output);
+}
+
+#[test]
+fn test_gitignore() {
+    let fixture = Fixture::new("repo")
+        .dir(".git/info")
+        .file_with_content(".git/info/exclude", "scratch/\n")
+        .file_with_content(".gitignore", "*.log\n/build\n")
+        .file_with_content("src/.gitignore", "generated.rs\n!keep.log\n")
+        .file("src/main.rs")
+        .file("src/generated.rs")
+        .file("src/keep.log")
+        .file("src/build/out.txt")
+        .file("build/app")
+        .file("scratch/notes.txt")
+        .file("debug.log")
+        .file("editor.swp");
+    let global = fixture.parent().join("global_ignore");
+    std::fs::write(&global, "*.swp\n").unwrap();
+    let config = fixture.parent().join("gitconfig");
+    std::fs::write(
+        &config,
+        format!("[core]\n\texcludesFile = {}\n", global.display()).replace('\\', "/"),
+    )
+    .unwrap();
+    let run = |args: &[&str]| {
+        let output = common::command()
+            .args(args)
+            .env("GIT_CONFIG_GLOBAL", &config)
+            .output()
+            .unwrap();
+        String::from_utf8_lossy(&output.stdout).to_string()
+    };
+    let expected = r#"repo
+└── src
+    ├── build
+    │   └── out.txt
+    ├── keep.log
+    └── main.rs
+
+2 directories, 3 files
+"#;
+
+    assert_eq!(expected, run(&["--gitignore", fixture.arg()]));
+
+    // Rules from above the listed directory still apply inside the repository
+    let src = fixture.join("src");
+    let output = run(&["--gitignore", src.to_str().unwrap()]);
+    assert!(output.ends_with("1 directories, 3 files\n"), "{}", output);
+
+    let output = run(&[fixture.arg()]);
+    assert!(output.ends_with("4 directories, 8 files\n"), "{}", output);
+}
+
+#[test]
+fn test_git_status() {
+    let fixture = Fixture::new("repo")
+        .file_with_content(".gitignore", "*.log\n")
+        .file_with_content("src/lib.rs", "pub fn lib() {}\n")
+        .file_with_content("README.md", "# Repo\n");
+    let git = |args: &[&str]| {
+        let status = Command::new("git")
+            .args(["-c", "user.name=tree", "-c", "user.email=tree@example.com"])
+            .arg("-C")
+            .arg(fixture.path())
+            .args(args)
+            .env("GIT_CONFIG_GLOBAL", "/dev/null")
+            .status()
+            .expect("git should be installed");
+        assert!(status.success(), "git {:?} failed", args);
+    };
+    git(&["init", "-q"]);
+    git(&["add", "."]);
+    git(&["commit", "-q", "-m", "Initial commit"]);
+    std::fs::write(fixture.join("README.md"), "# Repo\n\nChanged\n").unwrap();
+    std::fs::write(fixture.join("src/main.rs"), "fn main() {}\n").unwrap();
+    git(&["add", "src/main.rs"]);
+    std::fs::create_dir(fixture.join("scratch")).unwrap();
+    std::fs::write(fixture.join("scratch/notes.txt"), "").unwrap();
+    std::fs::write(fixture.join("debug.log"), "").unwrap();
+
+    let expected = r#"repo
+├── [  ]  .gitignore
+├── [ M]  README.md
+├── [!!]  debug.log
+├── [??]  scratch
+│   └── [??]  notes.txt
+└── [  ]  src
+    ├── [  ]  lib.rs
+    └── [A ]  main.rs
+
+2 directories, 6 files
+"#;
+    let output = run_cmd(&["--git-status", "-a", "-I", ".git", fixture.arg()]);
+    assert_eq!(expected, output);
+}
+
+#[test]
+fn test_git_status_outside_repository() {
+    let fixture = Fixture::standard();
+
+    let (output, status) = run_cmd_with_status(&["--git-status", fixture.arg()]);
+    assert_eq!(0, status, "A missing git status should only warn");
+    assert_eq!(
+        run_cmd(&[fixture.arg()]),
+        output,
+        "Without a repository there is no git status column"
+    );
+}
+
+#[test]
+fn test_file_limit() {
+    let fixture = Fixture::new("limits")
+        .file("small/a.txt")
+        .file("big/1.txt")
+        .file("big/2.txt")
+        .file("big/3.txt");
+    let expected = r#"limits
+├── big [3 entries exceeds filelimit, not opening dir]
+└── small
+    └── a.txt
+
+2 directories, 1 files
+"#;
+
+    assert_eq!(expected, run_cmd(&["--filelimit", "2", fixture.arg()]));
 }
@@ -0,0 +1,117 @@
+use std::path::PathBuf;
+use std::time::UNIX_EPOCH;
+
+use pyo3::exceptions::{PyTypeError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::{PyDict, PyList};
+
+use rust_tree::rust_tree::entry::{EntryKind, TreeNode};
+use rust_tree::rust_tree::options::{SortKey, TreeOptions};
+use rust_tree::rust_tree::traversal;
+use rust_tree::rust_tree::utils::parse_pattern_alternatives;
+
+/// Renders the tree below `path` as the `tree` command prints it.
+///
+/// Keyword arguments set the listing options, e.g.
+/// `list_directory_as_string(".", level=2, exclude="target|*.pyc")`.
+#[pyfunction]
+#[pyo3(signature = (path, **options))]
+fn list_directory_as_string(
+    path: PathBuf,
+    options: Option<&Bound<'_, PyDict>>,
+) -> PyResult<String> {
+    let options = tree_options(options)?;
+    Ok(traversal::list_directory_as_string(path, &options)?)
+}
+
+/// Reads the tree below `path` into nested dicts with the `name`, `path`,
+/// `kind` (`"file"`, `"directory"`, `"symlink"` or `"other"`), `size`,
+/// `modified` (seconds since the epoch) and `children` of each entry,
+/// filtered and sorted as they would be listed. Takes the same keyword
+/// arguments as `list_directory_as_string`.
+#[pyfunction]
+#[pyo3(signature = (path, **options))]
+fn build_tree<'py>(
+    py: Python<'py>,
+    path: PathBuf,
+    options: Option<&Bound<'py, PyDict>>,
+) -> PyResult<Bound<'py, PyDict>> {
+    let options = tree_options(options)?;
+    let tree = traversal::build_tree(path, &options)?;
+    node_dict(py, &tree)
+}
+
+/// The `TreeOptions` for the keyword arguments of a call.
+fn tree_options(kwargs: Option<&Bound<'_, PyDict>>) -> PyResult<TreeOptions> {
+    let mut options = TreeOptions::default();
+    let Some(kwargs) = kwargs else {
+        return Ok(options);
+    };
+    let patterns = |value: &Bound<'_, PyAny>| -> PyResult<_> {
+        parse_pattern_alternatives(&value.extract::<String>()?)
+            .map_err(|err| PyValueError::new_err(err.to_string()))
+    };
+    for (key, value) in kwargs {
+        match key.extract::<String>()?.as_str() {
+            "all_files" => options.all_files = value.extract()?,
+            "level" => options.level = value.extract()?,
+            "dir_only" => options.dir_only = value.extract()?,
+            "full_path" => options.full_path = value.extract()?,
+            "dirs_first" => options.dirs_first = value.extract()?,
+            "files_first" => options.files_first = value.extract()?,
+            "print_size" => options.print_size = value.extract()?,
+            "human_readable" => options.human_readable = value.extract()?,
+            "gitignore" => options.gitignore = value.extract()?,
+            "pattern" => options.pattern_globs = patterns(&value)?,
+            "exclude" => options.exclude_patterns = patterns(&value)?,
+            "sort" => {
+                options.sort = value
+                    .extract::<String>()?
+                    .parse::<SortKey>()
+                    .map_err(PyValueError::new_err)?
+            }
+            key => {
+                return Err(PyTypeError::new_err(format!(
+                    "unexpected keyword argument '{}'",
+                    key
+                )))
+            }
+        }
+    }
+    Ok(options)
+}
+
+/// `node` and the nodes below it as nested dicts.
+fn node_dict<'py>(py: Python<'py>, node: &TreeNode) -> PyResult<Bound<'py, PyDict>> {
+    let entry = &node.entry;
+    let dict = PyDict::new(py);
+    dict.set_item("name", &node.name)?;
+    dict.set_item("path", entry.path.to_string_lossy())?;
+    let kind = match entry.kind {
+        EntryKind::File => "file",
+        EntryKind::Directory => "directory",
+        EntryKind::Symlink => "symlink",
+        EntryKind::Other => "other",
+    };
+    dict.set_item("kind", kind)?;
+    dict.set_item("size", entry.size)?;
+    let modified = entry
+        .modified
+        .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+        .map(|since_epoch| since_epoch.as_secs_f64());
+    dict.set_item("modified", modified)?;
+    let children = PyList::empty(py);
+    for child in &node.children {
+        children.append(node_dict(py, child)?)?;
+    }
+    dict.set_item("children", children)?;
+    Ok(dict)
+}
+
+/// Directory listings from the rust_tree library.
+#[pymodule]
+fn tree_py(module: &Bound<'_, PyModule>) -> PyResult<()> {
+    module.add_function(wrap_pyfunction!(list_directory_as_string, module)?)?;
+    module.add_function(wrap_pyfunction!(build_tree, module)?)?;
+    Ok(())
+}